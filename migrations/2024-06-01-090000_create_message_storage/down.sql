@@ -0,0 +1,4 @@
+DROP TABLE message_oob;
+DROP TABLE message_body;
+DROP TABLE message_version;
+DROP TABLE message;
@@ -0,0 +1,38 @@
+CREATE TABLE message (
+	message_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	id VARCHAR NOT NULL,
+	stanza_id VARCHAR,
+	peer VARCHAR NOT NULL,
+	from_jid VARCHAR NOT NULL,
+	to_jid VARCHAR NOT NULL,
+	direction VARCHAR NOT NULL,
+	message_type VARCHAR NOT NULL,
+	timestamp BIGINT NOT NULL,
+	UNIQUE(account, id)
+);
+
+CREATE INDEX message_peer_timestamp ON message(account, peer, timestamp);
+
+CREATE TABLE message_version (
+	version_pk INTEGER PRIMARY KEY NOT NULL,
+	message_fk INTEGER NOT NULL REFERENCES message(message_pk) ON DELETE CASCADE,
+	id VARCHAR NOT NULL,
+	timestamp BIGINT NOT NULL,
+	UNIQUE(message_fk, id)
+);
+
+CREATE TABLE message_body (
+	body_pk INTEGER PRIMARY KEY NOT NULL,
+	version_fk INTEGER NOT NULL REFERENCES message_version(version_pk) ON DELETE CASCADE,
+	lang VARCHAR NOT NULL,
+	body TEXT NOT NULL,
+	UNIQUE(version_fk, lang)
+);
+
+CREATE TABLE message_oob (
+	oob_pk INTEGER PRIMARY KEY NOT NULL,
+	version_fk INTEGER NOT NULL REFERENCES message_version(version_pk) ON DELETE CASCADE,
+	url VARCHAR NOT NULL,
+	description VARCHAR
+);
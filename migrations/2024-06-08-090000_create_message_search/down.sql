@@ -0,0 +1,4 @@
+DROP TRIGGER message_body_fts_update;
+DROP TRIGGER message_body_fts_delete;
+DROP TRIGGER message_body_fts_insert;
+DROP TABLE message_body_fts;
@@ -0,0 +1,20 @@
+CREATE VIRTUAL TABLE message_body_fts USING fts5(
+	body,
+	content='message_body',
+	content_rowid='body_pk'
+);
+
+INSERT INTO message_body_fts(rowid, body) SELECT body_pk, body FROM message_body;
+
+CREATE TRIGGER message_body_fts_insert AFTER INSERT ON message_body BEGIN
+	INSERT INTO message_body_fts(rowid, body) VALUES (new.body_pk, new.body);
+END;
+
+CREATE TRIGGER message_body_fts_delete AFTER DELETE ON message_body BEGIN
+	INSERT INTO message_body_fts(message_body_fts, rowid, body) VALUES ('delete', old.body_pk, old.body);
+END;
+
+CREATE TRIGGER message_body_fts_update AFTER UPDATE ON message_body BEGIN
+	INSERT INTO message_body_fts(message_body_fts, rowid, body) VALUES ('delete', old.body_pk, old.body);
+	INSERT INTO message_body_fts(rowid, body) VALUES (new.body_pk, new.body);
+END;
@@ -0,0 +1,3 @@
+ALTER TABLE message DROP COLUMN retraction_reason;
+ALTER TABLE message DROP COLUMN retracted_by;
+ALTER TABLE message DROP COLUMN retracted;
@@ -0,0 +1,3 @@
+ALTER TABLE message ADD COLUMN retracted BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE message ADD COLUMN retracted_by VARCHAR;
+ALTER TABLE message ADD COLUMN retraction_reason VARCHAR;
@@ -0,0 +1,7 @@
+CREATE TABLE message_subject (
+	subject_pk INTEGER PRIMARY KEY NOT NULL,
+	message_fk INTEGER NOT NULL REFERENCES message(message_pk) ON DELETE CASCADE,
+	lang VARCHAR NOT NULL,
+	subject TEXT NOT NULL,
+	UNIQUE(message_fk, lang)
+);
@@ -0,0 +1 @@
+ALTER TABLE message ADD COLUMN origin_id VARCHAR;
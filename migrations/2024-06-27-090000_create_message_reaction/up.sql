@@ -0,0 +1,7 @@
+CREATE TABLE message_reaction (
+	reaction_pk INTEGER PRIMARY KEY NOT NULL,
+	message_fk INTEGER NOT NULL REFERENCES message(message_pk) ON DELETE CASCADE,
+	author VARCHAR NOT NULL,
+	emoji VARCHAR NOT NULL,
+	UNIQUE(message_fk, author, emoji)
+);
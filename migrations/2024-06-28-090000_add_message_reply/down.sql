@@ -0,0 +1,3 @@
+ALTER TABLE message DROP COLUMN reply_preview;
+ALTER TABLE message DROP COLUMN reply_to;
+ALTER TABLE message DROP COLUMN reply_id;
@@ -0,0 +1,3 @@
+ALTER TABLE message ADD COLUMN reply_id VARCHAR;
+ALTER TABLE message ADD COLUMN reply_to VARCHAR;
+ALTER TABLE message ADD COLUMN reply_preview TEXT;
@@ -0,0 +1,38 @@
+-- SQLite can't drop a constraint, the table is rebuilt with message ids unique per account
+CREATE TABLE message_new (
+	message_pk INTEGER PRIMARY KEY NOT NULL,
+	account VARCHAR NOT NULL,
+	id VARCHAR NOT NULL,
+	stanza_id VARCHAR,
+	origin_id VARCHAR,
+	peer VARCHAR NOT NULL,
+	from_jid VARCHAR NOT NULL,
+	to_jid VARCHAR NOT NULL,
+	direction VARCHAR NOT NULL,
+	message_type VARCHAR NOT NULL,
+	timestamp BIGINT NOT NULL,
+	retracted BOOLEAN NOT NULL DEFAULT 0,
+	retracted_by VARCHAR,
+	retraction_reason VARCHAR,
+	delivery VARCHAR,
+	reply_id VARCHAR,
+	reply_to VARCHAR,
+	reply_preview TEXT,
+	UNIQUE(account, id)
+);
+
+INSERT INTO message_new (
+	message_pk, account, id, stanza_id, origin_id, peer, from_jid, to_jid, direction,
+	message_type, timestamp, retracted, retracted_by, retraction_reason, delivery, reply_id,
+	reply_to, reply_preview
+)
+SELECT
+	message_pk, account, id, stanza_id, origin_id, peer, from_jid, to_jid, direction,
+	message_type, timestamp, retracted, retracted_by, retraction_reason, delivery, reply_id,
+	reply_to, reply_preview
+FROM message;
+
+DROP TABLE message;
+ALTER TABLE message_new RENAME TO message;
+
+CREATE INDEX message_peer_timestamp ON message(account, peer, timestamp);
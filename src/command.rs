@@ -348,7 +348,8 @@ macro_rules! parse_command_args(
             ::anyhow::bail!("Missing {} argument", stringify!($arg))
         }
 
-        let mut sub_commands: HashMap<String, CommandParser> = HashMap::new();
+        let mut sub_commands: ::std::collections::HashMap<String, CommandParser> =
+            ::std::collections::HashMap::new();
         parse_subcommand_attrs!(sub_commands, $attrs);
 
         return match sub_commands.get(&$command.args[$index]) {
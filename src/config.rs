@@ -18,9 +18,30 @@ pub struct Config {
     pub accounts: HashMap<String, ConnectionInfo>,
     #[serde(default = "true_")]
     pub bell: bool,
+    /// Send chat state notifications (typing indicators)
+    #[serde(default = "true_")]
+    pub chat_states: bool,
+    /// Send delivery receipts to contacts allowed to see our presence
+    #[serde(default = "true_")]
+    pub receipts: bool,
+    /// Tell contacts when their messages have been displayed
+    #[serde(default = "true_")]
+    pub displayed_markers: bool,
+    /// Show occupants joining, leaving and changing role in channel windows
+    #[serde(default = "true_")]
+    pub channel_events: bool,
+    /// Preferred languages, most preferred first, used to choose among the bodies and subjects
+    /// of a message. The first one is the language of our messages and stanzas.
+    pub languages: Vec<String>,
     pub theme: Theme,
 }
 
+impl Config {
+    pub fn prefered_langs(&self) -> Vec<&str> {
+        self.languages.iter().map(String::as_str).collect()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Theme {
     pub title_bar: ColorTuple,
@@ -4,6 +4,9 @@
 use std::cmp;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use anyhow::bail;
 use xmpp_parsers::BareJid;
 
 use crate::account::Account;
@@ -25,6 +28,58 @@ pub enum Role {
     None,
 }
 
+impl Affiliation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Affiliation::Owner => "owner",
+            Affiliation::Admin => "admin",
+            Affiliation::Member => "member",
+            Affiliation::Outcast => "outcast",
+            Affiliation::None => "none",
+        }
+    }
+}
+
+impl FromStr for Affiliation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(Affiliation::Owner),
+            "admin" => Ok(Affiliation::Admin),
+            "member" => Ok(Affiliation::Member),
+            "outcast" => Ok(Affiliation::Outcast),
+            "none" => Ok(Affiliation::None),
+            _ => bail!("Unknown affiliation {s}"),
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Visitor => "visitor",
+            Role::Participant => "participant",
+            Role::Moderator => "moderator",
+            Role::None => "none",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "visitor" => Ok(Role::Visitor),
+            "participant" => Ok(Role::Participant),
+            "moderator" => Ok(Role::Moderator),
+            "none" => Ok(Role::None),
+            _ => bail!("Unknown role {s}"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Occupant {
     pub nick: String,
@@ -30,11 +30,13 @@ use tokio::task;
 use uuid::Uuid;
 
 use xmpp_parsers::caps::{self, Caps};
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::data_forms::DataForm;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::hashes as xmpp_hashes;
 use xmpp_parsers::iq::{Iq, IqType};
 use xmpp_parsers::legacy_omemo;
-use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
 use xmpp_parsers::muc::Muc;
 use xmpp_parsers::presence::{Presence, Show as PresenceShow, Type as PresenceType};
 use xmpp_parsers::pubsub::event::PubSubEvent;
@@ -48,7 +50,8 @@ use crate::command::{Command, CommandParser};
 use crate::config::Config;
 use crate::conversation::{Channel, Conversation};
 use crate::crypto::CryptoEngine;
-use crate::message::Message;
+use crate::data_form;
+use crate::message::{DeliveryState, Message};
 use crate::mods;
 use crate::storage::Storage;
 use crate::{
@@ -82,7 +85,16 @@ pub enum Event {
     RawCommand(Option<Account>, String, String),
     Command(Command),
     SendMessage(Account, Message),
+    /// Send a new version of one of our messages (XEP-0308)
+    SendCorrection {
+        account: Account,
+        peer: BareJid,
+        id: String,
+        body: String,
+    },
     Message(Option<Account>, Message),
+    /// Headline with a body sent by a server or a service (MOTD, alerts...)
+    Notice(Account, Message),
     Chat {
         account: Account,
         contact: BareJid,
@@ -131,6 +143,25 @@ pub enum Event {
         conversation: BareJid,
         occupant: conversation::Occupant,
     },
+    /// Occupant left a channel, possibly removed by a moderator
+    OccupantLeft {
+        account: Account,
+        conversation: BareJid,
+        occupant: conversation::Occupant,
+    },
+    /// Occupant of a channel, possibly us, is now known under another nick
+    NickChanged {
+        account: Account,
+        conversation: BareJid,
+        occupant: conversation::Occupant,
+        nick: String,
+    },
+    /// Information about a channel shown in its window, such as an occupant joining
+    ChannelLog {
+        account: Account,
+        conversation: BareJid,
+        body: String,
+    },
     WindowChange,
     LoadChannelHistory {
         account: Account,
@@ -142,8 +173,20 @@ pub enum Event {
         contact: BareJid,
         from: Option<DateTime<FixedOffset>>,
     },
+    /// Outgoing message that couldn't be handed to the connection
+    Unsent {
+        account: Account,
+        peer: BareJid,
+        id: String,
+    },
+    SearchResults {
+        query: String,
+        results: Vec<(Account, Message)>,
+    },
     Quit,
     Key(Key),
+    /// Text pasted in the terminal, delivered at once
+    Paste(String),
     AutoComplete {
         account: Option<Account>,
         context: String,
@@ -158,8 +201,39 @@ pub enum Event {
         important: bool,
     },
     Subject(Account, Jid, HashMap<String, String>),
+    /// Chat state (XEP-0085) of a conversation participant changed
+    ChatStateChanged {
+        account: Account,
+        conversation: BareJid,
+        from: String,
+        state: ChatState,
+    },
+    ChatStates(mods::chatstates::ChatStatesEvent),
+    /// Ad-hoc command (XEP-0050) progress
+    Adhoc(mods::adhoc::AdhocEvent),
+    /// Message shown to the user in the current window
+    Displayed {
+        account: Account,
+        message: Message,
+    },
     Omemo(mods::omemo::OmemoEvent),
     UIRender(bool),
+    /// Edit the input in an external editor
+    Editor,
+    /// External editor exited, with the edited text if successful
+    Edited(Option<String>),
+    /// Data form (XEP-0004) to fill in its own window, replacing the form already shown in it
+    DataForm {
+        account: Account,
+        window: String,
+        form: DataForm,
+    },
+    /// Data form filled by the user, None if it was cancelled
+    DataFormSubmit {
+        account: Account,
+        window: String,
+        form: Option<DataForm>,
+    },
 }
 
 pub enum Mod {
@@ -174,6 +248,13 @@ pub enum Mod {
     Mam(mods::mam::MamMod),
     Correction(mods::correction::CorrectionMod),
     Omemo(mods::omemo::OmemoMod),
+    Retraction(mods::retraction::RetractionMod),
+    ChatStates(mods::chatstates::ChatStatesMod),
+    Receipts(mods::receipts::ReceiptsMod),
+    Reactions(mods::reactions::ReactionsMod),
+    Reply(mods::reply::ReplyMod),
+    Muc(mods::muc::MucMod),
+    Adhoc(mods::adhoc::AdhocMod),
 }
 
 macro_rules! from_mod {
@@ -208,6 +289,13 @@ from_mod!(UI, mods::ui::UIMod);
 from_mod!(Mam, mods::mam::MamMod);
 from_mod!(Messages, mods::messages::MessagesMod);
 from_mod!(Correction, mods::correction::CorrectionMod);
+from_mod!(Retraction, mods::retraction::RetractionMod);
+from_mod!(ChatStates, mods::chatstates::ChatStatesMod);
+from_mod!(Receipts, mods::receipts::ReceiptsMod);
+from_mod!(Reactions, mods::reactions::ReactionsMod);
+from_mod!(Reply, mods::reply::ReplyMod);
+from_mod!(Muc, mods::muc::MucMod);
+from_mod!(Adhoc, mods::adhoc::AdhocMod);
 
 pub trait ModTrait: Display {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()>;
@@ -250,6 +338,13 @@ impl ModTrait for Mod {
             Mod::Messages(r#mod) => r#mod.init(aparte),
             Mod::Correction(r#mod) => r#mod.init(aparte),
             Mod::Omemo(r#mod) => r#mod.init(aparte),
+            Mod::Reply(r#mod) => r#mod.init(aparte),
+            Mod::Muc(r#mod) => r#mod.init(aparte),
+            Mod::Adhoc(r#mod) => r#mod.init(aparte),
+            Mod::Reactions(r#mod) => r#mod.init(aparte),
+            Mod::Receipts(r#mod) => r#mod.init(aparte),
+            Mod::ChatStates(r#mod) => r#mod.init(aparte),
+            Mod::Retraction(r#mod) => r#mod.init(aparte),
         }
     }
 
@@ -266,6 +361,13 @@ impl ModTrait for Mod {
             Mod::Messages(r#mod) => r#mod.on_event(aparte, event),
             Mod::Correction(r#mod) => r#mod.on_event(aparte, event),
             Mod::Omemo(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Reply(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Muc(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Adhoc(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Reactions(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Receipts(r#mod) => r#mod.on_event(aparte, event),
+            Mod::ChatStates(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Retraction(r#mod) => r#mod.on_event(aparte, event),
         }
     }
 
@@ -294,6 +396,17 @@ impl ModTrait for Mod {
                 r#mod.can_handle_xmpp_message(aparte, account, message, delay)
             }
             Mod::Omemo(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Reply(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Muc(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Adhoc(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Reactions(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Receipts(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::ChatStates(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Retraction(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
         }
     }
 
@@ -335,6 +448,25 @@ impl ModTrait for Mod {
             Mod::Omemo(r#mod) => {
                 r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
             }
+            Mod::Reply(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Muc(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay, archive),
+            Mod::Adhoc(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Reactions(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Receipts(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::ChatStates(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
+            Mod::Retraction(r#mod) => {
+                r#mod.handle_xmpp_message(aparte, account, message, delay, archive)
+            }
         }
     }
 }
@@ -353,6 +485,13 @@ impl fmt::Debug for Mod {
             Mod::Messages(_) => f.write_str("Mod::Messages"),
             Mod::Correction(_) => f.write_str("Mod::Correction"),
             Mod::Omemo(_) => f.write_str("Mod::Omemo"),
+            Mod::Reply(_) => f.write_str("Mod::Reply"),
+            Mod::Muc(_) => f.write_str("Mod::Muc"),
+            Mod::Adhoc(_) => f.write_str("Mod::Adhoc"),
+            Mod::Reactions(_) => f.write_str("Mod::Reactions"),
+            Mod::Receipts(_) => f.write_str("Mod::Receipts"),
+            Mod::ChatStates(_) => f.write_str("Mod::ChatStates"),
+            Mod::Retraction(_) => f.write_str("Mod::Retraction"),
         }
     }
 }
@@ -371,6 +510,13 @@ impl Display for Mod {
             Mod::Messages(r#mod) => r#mod.fmt(f),
             Mod::Correction(r#mod) => r#mod.fmt(f),
             Mod::Omemo(r#mod) => r#mod.fmt(f),
+            Mod::Reply(r#mod) => r#mod.fmt(f),
+            Mod::Muc(r#mod) => r#mod.fmt(f),
+            Mod::Adhoc(r#mod) => r#mod.fmt(f),
+            Mod::Reactions(r#mod) => r#mod.fmt(f),
+            Mod::Receipts(r#mod) => r#mod.fmt(f),
+            Mod::ChatStates(r#mod) => r#mod.fmt(f),
+            Mod::Retraction(r#mod) => r#mod.fmt(f),
         }
     }
 }
@@ -378,6 +524,8 @@ impl Display for Mod {
 pub struct Connection {
     pub sink: mpsc::UnboundedSender<Element>,
     pub account: FullJid,
+    /// Stream is established and stanzas can be sent
+    pub online: bool,
 }
 
 command_def!(connect,
@@ -487,6 +635,69 @@ Examples:
     Ok(())
 });
 
+command_def!(
+    form_submit,
+    r#"/form submit
+
+Description:
+    Send the form shown in the current window. Fill it beforehand: select a
+    field with Ctrl+Up/Ctrl+Down, type its value and press Enter. Boolean and
+    single choice fields change to their next value when Enter is pressed on
+    an empty input, options of multiple choice fields are selected or
+    unselected by typing them.
+
+Example:
+    /form submit"#,
+    {},
+    |aparte, command| {
+        let form = aparte
+            .get_mod_mut::<mods::ui::UIMod>()
+            .take_form(&command.context);
+        let (account, form) = form.context("No form in this window")?;
+        aparte.schedule(Event::DataFormSubmit {
+            account,
+            window: command.context.clone(),
+            form: Some(data_form::submit_form(&form)),
+        });
+        aparte.schedule(Event::Close(command.context));
+        Ok(())
+    }
+);
+
+command_def!(
+    form_cancel,
+    r#"/form cancel
+
+Description:
+    Cancel the form shown in the current window. Closing its window also
+    cancels it.
+
+Example:
+    /form cancel"#,
+    {},
+    |aparte, command| {
+        if !aparte
+            .get_mod::<mods::ui::UIMod>()
+            .has_form(&command.context)
+        {
+            anyhow::bail!("No form in this window");
+        }
+        aparte.schedule(Event::Close(command.context));
+        Ok(())
+    }
+);
+
+command_def!(form,
+r#"/form submit|cancel"#,
+{
+    action: Command = {
+        children: {
+            "submit": form_submit,
+            "cancel": form_cancel,
+        }
+    },
+});
+
 command_def!(leave,
 r#"Usage: /leave [<window>]
 
@@ -891,6 +1102,13 @@ impl Aparte {
         aparte.add_mod(Mod::Messages(mods::messages::MessagesMod::default()));
         aparte.add_mod(Mod::Correction(mods::correction::CorrectionMod::default()));
         aparte.add_mod(Mod::Omemo(mods::omemo::OmemoMod::default()));
+        aparte.add_mod(Mod::Reply(mods::reply::ReplyMod::default()));
+        aparte.add_mod(Mod::Muc(mods::muc::MucMod::default()));
+        aparte.add_mod(Mod::Adhoc(mods::adhoc::AdhocMod::default()));
+        aparte.add_mod(Mod::Reactions(mods::reactions::ReactionsMod::default()));
+        aparte.add_mod(Mod::Receipts(mods::receipts::ReceiptsMod::default()));
+        aparte.add_mod(Mod::ChatStates(mods::chatstates::ChatStatesMod::default()));
+        aparte.add_mod(Mod::Retraction(mods::retraction::RetractionMod::default()));
 
         Ok(aparte)
     }
@@ -996,6 +1214,7 @@ impl Aparte {
         let connection = Connection {
             account: account.clone(),
             sink,
+            online: false,
         };
 
         self.connections.insert(account.clone(), connection);
@@ -1007,6 +1226,7 @@ impl Aparte {
         self.add_command(connect::new());
         self.add_command(win::new());
         self.add_command(close::new());
+        self.add_command(form::new());
         self.add_command(leave::new());
         self.add_command(msg::new());
         self.add_command(join::new());
@@ -1105,19 +1325,56 @@ impl Aparte {
         }
     }
 
-    fn send_stanza(&mut self, account: Account, stanza: Element) {
+    /// Whether messages can currently be sent through the given account
+    pub fn is_online(&self, account: &Account) -> bool {
+        match self.connections.get(account) {
+            Some(connection) => connection.online && !connection.sink.is_closed(),
+            None => false,
+        }
+    }
+
+    fn send_stanza(&mut self, account: Account, mut stanza: Element) {
+        // tokio-xmpp writes the stream header without xml:lang, so the language of our stanzas
+        // is declared on each of them instead (RFC 6120 §8.1.5)
+        if let Some(lang) = self.config.languages.first() {
+            if ["message", "presence", "iq"]
+                .iter()
+                .any(|name| stanza.is(*name, xmpp_parsers::ns::DEFAULT_NS))
+                && stanza.attr("xml:lang").is_none()
+            {
+                stanza.set_attr("xml:lang", lang.as_str());
+            }
+        }
+
         let mut raw = Vec::<u8>::new();
         stanza.write_to(&mut raw).unwrap();
         log::debug!("SEND: {}", String::from_utf8(raw).unwrap());
-        match self.connections.get_mut(&account) {
-            Some(connection) => {
-                if let Err(e) = connection.sink.send(stanza) {
+        let unsent = match self.connections.get_mut(&account) {
+            Some(connection) => match connection.sink.send(stanza) {
+                Ok(()) => return,
+                Err(e) => {
                     log::warn!("Cannot send stanza: {}", e);
+                    e.0
                 }
-            }
+            },
             None => {
                 log::warn!("No connection found for {}", account);
+                stanza
             }
+        };
+
+        // The message was already marked as sent, it must go back to the outbox
+        if let Ok(XmppParsersMessage {
+            id: Some(id),
+            to: Some(to),
+            ..
+        }) = XmppParsersMessage::try_from(unsent)
+        {
+            self.schedule(Event::Unsent {
+                account,
+                peer: to.to_bare(),
+                id,
+            });
         }
     }
 
@@ -1236,7 +1493,7 @@ impl Aparte {
     }
 
     pub fn handle_event(&mut self, event: Event) -> Result<(), ()> {
-        if self.read_password.load(Relaxed) && matches!(event, Event::Key(..)) {
+        if self.read_password.load(Relaxed) && matches!(event, Event::Key(..) | Event::Paste(..)) {
             log::debug!("Event: {:?}", Event::Key(Key::Char('*')));
         } else {
             log::debug!("Event: {:?}", event);
@@ -1263,8 +1520,23 @@ impl Aparte {
                     self.log(err);
                 }
             }
-            Event::SendMessage(account, message) => {
-                self.schedule(Event::Message(Some(account.clone()), message.clone()));
+            Event::SendMessage(account, mut message) => {
+                // Our messages are written in our most preferred language
+                if let (Message::Xmpp(xmpp_message), Some(lang)) =
+                    (&mut message, self.config.languages.first())
+                {
+                    xmpp_message.set_default_lang(lang);
+                }
+
+                // Keep the message in the outbox until the connection is back, MessagesMod
+                // flushes it on Event::Connected, or on Event::Joined for channel messages
+                if !self.is_online(&account) {
+                    if let Message::Xmpp(xmpp_message) = &mut message {
+                        xmpp_message.delivery = Some(DeliveryState::Pending);
+                    }
+                    self.schedule(Event::Message(Some(account), message));
+                    return Ok(());
+                }
 
                 // Encrypt if required
                 let encryption = message.encryption_recipient().and_then(|recipient| {
@@ -1274,19 +1546,45 @@ impl Aparte {
                         .map(|crypto_engine| crypto_engine.encrypt(self, &account, &message))
                 });
 
-                match encryption {
-                    Some(Ok(encrypted_message)) => self.send(&account, encrypted_message),
+                if let Message::Xmpp(xmpp_message) = &mut message {
+                    match &encryption {
+                        Some(Err(e)) => {
+                            xmpp_message.delivery = None;
+                            xmpp_message.error = Some(format!("Cannot encrypt message: {e}"));
+                        }
+                        _ => xmpp_message.delivery = Some(DeliveryState::Sent),
+                    }
+                }
+                self.schedule(Event::Message(Some(account.clone()), message.clone()));
+
+                let mut stanza = match encryption {
+                    Some(Ok(encrypted_message)) => encrypted_message,
                     Some(Err(e)) => {
-                        log::error!("Cannot encrypt message (TODO print error in UI): {e}")
+                        log::error!("Cannot encrypt message: {e}");
+                        return Ok(());
                     }
-                    None => self.send(&account, message),
+                    None => match Element::try_from(message) {
+                        Ok(stanza) => stanza,
+                        Err(()) => {
+                            log::error!("Cannot convert message to stanza");
+                            return Ok(());
+                        }
+                    },
+                };
+                // Once chat states are supported, our messages carry the active one (XEP-0085)
+                if self.config.chat_states {
+                    stanza.append_child(ChatState::Active.into());
                 }
+                self.send(&account, stanza);
             }
             Event::Connect(account, password) => {
                 self.connect(&account, password);
             }
             Event::Connected(account, _) => {
                 self.log(format!("Connected as {}", account));
+                if let Some(connection) = self.connections.get_mut(&account) {
+                    connection.online = true;
+                }
                 let mut presence = Presence::new(PresenceType::None);
                 presence.show = Some(PresenceShow::Chat);
 
@@ -1301,6 +1599,9 @@ impl Aparte {
             }
             Event::Disconnected(account, err) => {
                 self.log(format!("Connection lost for {}: {}", account, err));
+                if let Some(connection) = self.connections.get_mut(&account) {
+                    connection.online = false;
+                }
             }
             Event::AuthError(account, err) => {
                 self.log(format!("Authentication error for {}: {}", account, err));
@@ -1397,6 +1698,14 @@ impl Aparte {
         let mut matched_mod = None;
         let mut message = message;
 
+        // Bounced messages carry a copy of what we sent, which must not be handled as if it
+        // was received: only keep the error
+        if message.type_ == XmppParsersMessageType::Error {
+            message
+                .payloads
+                .retain(|payload| payload.is("error", xmpp_parsers::ns::DEFAULT_NS));
+        }
+
         let encryption_ns = message
             .payloads
             .iter()
@@ -1596,6 +1905,11 @@ impl Aparte {
         crypto_engines.insert((account.clone(), recipient.clone()), crypto_engine);
     }
 
+    pub fn has_crypto_engine(&self, account: &Account, recipient: &BareJid) -> bool {
+        let crypto_engines = self.crypto_engines.lock().unwrap();
+        crypto_engines.contains_key(&(account.clone(), recipient.clone()))
+    }
+
     pub fn send<T>(&mut self, account: &Account, element: T)
     where
         T: TryInto<Element> + Debug,
@@ -0,0 +1,552 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+//! XEP-0004: Data Forms
+//!
+//! Interactive view of a data form. A field is selected with [`DataFormView::select`] and its
+//! value is changed with [`DataFormView::edit`], usually with the content of the input.
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use termion::{color, style};
+use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
+use xmpp_parsers::Jid;
+
+use terminus::{
+    Dimensions, EventHandler, LayoutParam, LayoutParams, MeasureSpec, MeasureSpecs,
+    RequestedDimension, RequestedDimensions, Screen, View,
+};
+
+const TRUE_VALUES: [&str; 4] = ["1", "true", "yes", "on"];
+const FALSE_VALUES: [&str; 4] = ["0", "false", "no", "off"];
+
+fn is_editable(field: &Field) -> bool {
+    !matches!(field.type_, FieldType::Fixed | FieldType::Hidden)
+}
+
+fn get_bool(field: &Field) -> bool {
+    field
+        .values
+        .first()
+        .map(|value| TRUE_VALUES.contains(&value.as_str()))
+        .unwrap_or(false)
+}
+
+/// Value of the option designated by its value or its label
+fn find_option(field: &Field, input: &str) -> Result<String> {
+    match field
+        .options
+        .iter()
+        .find(|option| option.value == input || option.label.as_deref() == Some(input))
+    {
+        Some(option) => Ok(option.value.clone()),
+        None => bail!("Unknown option {input}"),
+    }
+}
+
+/// Change the value of a field given the text typed by the user
+pub fn edit_field(field: &mut Field, raw_input: &str) -> Result<()> {
+    let input = raw_input.trim();
+    match field.type_ {
+        FieldType::Fixed | FieldType::Hidden => bail!("This field can't be edited"),
+        FieldType::Boolean => {
+            let value = if input.is_empty() {
+                !get_bool(field)
+            } else if TRUE_VALUES.contains(&input) {
+                true
+            } else if FALSE_VALUES.contains(&input) {
+                false
+            } else {
+                bail!("Invalid boolean {input}")
+            };
+            field.values = vec![String::from(if value { "1" } else { "0" })];
+        }
+        FieldType::ListSingle => {
+            let value = match input.is_empty() {
+                // Cycle through options
+                true => {
+                    let current = field
+                        .options
+                        .iter()
+                        .position(|option| Some(&option.value) == field.values.first());
+                    let next = current.map(|current| current + 1).unwrap_or(0);
+                    match field.options.get(next).or(field.options.first()) {
+                        Some(option) => option.value.clone(),
+                        None => bail!("No option to choose from"),
+                    }
+                }
+                false => find_option(field, input)?,
+            };
+            field.values = vec![value];
+        }
+        FieldType::ListMulti => {
+            if input.is_empty() {
+                bail!("Type an option to select or unselect it");
+            }
+            let value = find_option(field, input)?;
+            match field.values.iter().position(|selected| selected == &value) {
+                Some(index) => {
+                    field.values.remove(index);
+                }
+                None => field.values.push(value),
+            }
+        }
+        FieldType::JidSingle if input.is_empty() => field.values.clear(),
+        FieldType::JidSingle => {
+            let jid = Jid::from_str(input)?;
+            field.values = vec![jid.to_string()];
+        }
+        FieldType::JidMulti => {
+            field.values = input
+                .split([',', ' '])
+                .filter(|jid| !jid.is_empty())
+                .map(|jid| Ok(Jid::from_str(jid)?.to_string()))
+                .collect::<Result<Vec<_>>>()?;
+        }
+        FieldType::TextSingle => {
+            field.values = match input.is_empty() {
+                true => vec![],
+                false => vec![input.to_string()],
+            };
+        }
+        // Passwords may start or end with spaces
+        FieldType::TextPrivate => {
+            field.values = match raw_input.is_empty() {
+                true => vec![],
+                false => vec![raw_input.to_string()],
+            };
+        }
+        FieldType::TextMulti => {
+            field.values = input.lines().map(String::from).collect();
+        }
+    }
+
+    Ok(())
+}
+
+/// Form to send back once filled, fields without var are only informative
+pub fn submit_form(form: &DataForm) -> DataForm {
+    DataForm {
+        type_: DataFormType::Submit,
+        form_type: form.form_type.clone(),
+        title: None,
+        instructions: None,
+        fields: form
+            .fields
+            .iter()
+            .filter(|field| field.type_ != FieldType::Fixed && !field.var.is_empty())
+            .map(|field| Field {
+                var: field.var.clone(),
+                type_: field.type_.clone(),
+                label: None,
+                required: false,
+                options: vec![],
+                values: field.values.clone(),
+                media: vec![],
+            })
+            .collect(),
+    }
+}
+
+/// Lines of a text sent by a remote entity, without terminal control sequences
+fn clean_lines(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines().map(terminus::clean_str)
+}
+
+/// Plain text version of a form, usually the result of a command
+pub fn format_form(form: &DataForm) -> String {
+    let mut lines = Vec::new();
+    if let Some(title) = &form.title {
+        lines.push(terminus::clean_str(title));
+    }
+    if let Some(instructions) = &form.instructions {
+        lines.extend(clean_lines(instructions));
+    }
+    for field in form.fields.iter() {
+        match field.type_ {
+            FieldType::Hidden => {}
+            FieldType::Fixed => {
+                lines.extend(field.values.iter().flat_map(|value| clean_lines(value)))
+            }
+            _ => {
+                let label = field.label.as_ref().unwrap_or(&field.var);
+                lines.push(terminus::clean_str(&format!(
+                    "{label}: {}",
+                    field.values.join(", ")
+                )));
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+pub struct DataFormView<E> {
+    form: DataForm,
+    /// Index of the selected field in form.fields
+    selected: Option<usize>,
+    /// Why the last edit failed, shown below the form
+    error: Option<String>,
+    event_handler: Option<EventHandler<Self, E>>,
+    dirty: Cell<bool>,
+    layouts: LayoutParams,
+    dimensions: Option<Dimensions>,
+}
+
+impl<E> DataFormView<E> {
+    pub fn new(form: DataForm) -> Self {
+        let selected = form.fields.iter().position(is_editable);
+        Self {
+            form,
+            selected,
+            error: None,
+            event_handler: None,
+            dirty: Cell::new(true),
+            layouts: LayoutParams {
+                width: LayoutParam::MatchParent,
+                height: LayoutParam::MatchParent,
+            },
+            dimensions: None,
+        }
+    }
+
+    pub fn with_event<F>(mut self, event_handler: F) -> Self
+    where
+        F: FnMut(&mut Self, &mut E) + 'static,
+    {
+        self.event_handler = Some(Rc::new(RefCell::new(Box::new(event_handler))));
+        self
+    }
+
+    pub fn get_form(&self) -> &DataForm {
+        &self.form
+    }
+
+    /// Replace the form, for instance with the next stage of a multi-stage process
+    pub fn set_form(&mut self, form: DataForm) {
+        self.selected = form.fields.iter().position(is_editable);
+        self.form = form;
+        self.error = None;
+        self.dirty.set(true);
+    }
+
+    /// Select the previous or the next editable field
+    pub fn select(&mut self, up: bool) {
+        let Some(selected) = self.selected else {
+            return;
+        };
+        let next = match up {
+            true => self.form.fields[..selected].iter().rposition(is_editable),
+            false => self.form.fields[selected + 1..]
+                .iter()
+                .position(is_editable)
+                .map(|index| selected + 1 + index),
+        };
+        if next.is_some() {
+            self.selected = next;
+            self.dirty.set(true);
+        }
+    }
+
+    /// Change the value of the selected field
+    pub fn edit(&mut self, input: &str) -> Result<()> {
+        let result = match self.selected {
+            Some(selected) => edit_field(&mut self.form.fields[selected], input),
+            None => Err(anyhow!("This form has nothing to fill")),
+        };
+        self.error = result.as_ref().err().map(|err| err.to_string());
+        self.dirty.set(true);
+        result
+    }
+
+    fn format_value(field: &Field) -> String {
+        match field.type_ {
+            FieldType::Boolean => String::from(if get_bool(field) { "[x]" } else { "[ ]" }),
+            FieldType::TextPrivate => "*".repeat(field.values.concat().len()),
+            FieldType::ListSingle | FieldType::ListMulti => String::new(),
+            _ => terminus::clean_str(&field.values.join(", ")),
+        }
+    }
+
+    /// Lines to display, with the index of the first line of the selected field
+    fn get_lines(&self) -> (Vec<String>, usize) {
+        let mut lines = Vec::new();
+        let mut selected_line = 0;
+
+        if let Some(title) = &self.form.title {
+            let title = terminus::clean_str(title);
+            lines.push(format!("{}{}{}", style::Bold, title, style::NoBold));
+        }
+        if let Some(instructions) = &self.form.instructions {
+            lines.extend(clean_lines(instructions));
+        }
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        for (index, field) in self.form.fields.iter().enumerate() {
+            let label = terminus::clean_str(field.label.as_ref().unwrap_or(&field.var));
+            match field.type_ {
+                FieldType::Hidden => continue,
+                FieldType::Fixed => {
+                    lines.extend(field.values.iter().flat_map(|value| clean_lines(value)));
+                    continue;
+                }
+                _ => {}
+            }
+
+            let required = if field.required { "*" } else { "" };
+            let line = format!("{label}{required}: {}", Self::format_value(field));
+            if Some(index) == self.selected {
+                selected_line = lines.len();
+                lines.push(format!("{}{}{}", style::Invert, line, style::NoInvert));
+            } else {
+                lines.push(line);
+            }
+
+            for option in field.options.iter() {
+                let checked = field.values.contains(&option.value);
+                let marker = match (&field.type_, checked) {
+                    (FieldType::ListMulti, true) => "[x]",
+                    (FieldType::ListMulti, false) => "[ ]",
+                    (_, true) => "(•)",
+                    (_, false) => "( )",
+                };
+                let option = terminus::clean_str(option.label.as_ref().unwrap_or(&option.value));
+                lines.push(format!("    {marker} {option}"));
+            }
+        }
+
+        (lines, selected_line)
+    }
+}
+
+impl<E, W> View<E, W> for DataFormView<E>
+where
+    W: Write + AsFd,
+{
+    fn measure(&self, measure_specs: &MeasureSpecs) -> RequestedDimensions {
+        let dimension = |layout: LayoutParam, spec: MeasureSpec| match (layout, spec) {
+            (LayoutParam::Absolute(absolute), MeasureSpec::AtMost(at_most)) => {
+                RequestedDimension::Absolute(std::cmp::min(absolute, at_most))
+            }
+            (LayoutParam::Absolute(absolute), MeasureSpec::Unspecified) => {
+                RequestedDimension::Absolute(absolute)
+            }
+            _ => RequestedDimension::ExpandMax,
+        };
+
+        RequestedDimensions {
+            width: dimension(self.layouts.width, measure_specs.width),
+            height: dimension(self.layouts.height, measure_specs.height),
+        }
+    }
+
+    fn layout(&mut self, dimensions: &Dimensions) {
+        log::debug!("layout {} {:?}", std::any::type_name::<Self>(), dimensions);
+        if self.dimensions.as_ref() != Some(dimensions) {
+            self.dirty.set(true);
+            self.dimensions.replace(dimensions.clone());
+        }
+    }
+
+    fn render(&self, screen: &mut Screen<W>) {
+        if self.dirty.replace(false) {
+            log::debug!(
+                "rendering {} at {:?}",
+                std::any::type_name::<Self>(),
+                self.dimensions
+            );
+            let dimensions = self.dimensions.as_ref().unwrap();
+            let width = dimensions.width as usize;
+            // Last line is kept for the error of the last edit
+            let height = match self.error {
+                Some(_) => dimensions.height.saturating_sub(1),
+                None => dimensions.height,
+            };
+
+            // Scroll just enough to keep the selected field visible
+            let (lines, selected_line) = self.get_lines();
+            let first = (selected_line + 1).saturating_sub(height as usize);
+
+            for (top, line) in (dimensions.top..dimensions.top + height).zip(
+                lines
+                    .iter()
+                    .skip(first)
+                    .map(Some)
+                    .chain(std::iter::repeat(None)),
+            ) {
+                terminus::goto!(screen, dimensions.left, top);
+                terminus::vprint!(screen, "{: <1$}", "", width);
+                if let Some(line) = line {
+                    terminus::goto!(screen, dimensions.left, top);
+                    let line = match terminus::term_string_visible_len(line) > width {
+                        true => terminus::term_string_visible_truncate(line, width, Some("…")),
+                        false => line.clone(),
+                    };
+                    terminus::vprint!(screen, "{}{}", line, style::Reset);
+                }
+            }
+
+            if let (Some(error), true) = (&self.error, height < dimensions.height) {
+                terminus::goto!(screen, dimensions.left, dimensions.top + height);
+                terminus::vprint!(screen, "{: <1$}", "", width);
+                terminus::goto!(screen, dimensions.left, dimensions.top + height);
+                let error = terminus::term_string_visible_truncate(error, width, Some("…"));
+                terminus::vprint!(
+                    screen,
+                    "{}{}{}",
+                    color::Fg(color::Red),
+                    error,
+                    color::Fg(color::Reset)
+                );
+            }
+        }
+    }
+
+    fn event(&mut self, event: &mut E) {
+        if let Some(handler) = &self.event_handler {
+            let handler = Rc::clone(handler);
+            let handler = &mut *handler.borrow_mut();
+            handler(self, event);
+        }
+    }
+
+    fn set_dirty(&mut self) {
+        self.dirty.set(true);
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use xmpp_parsers::Element;
+
+    fn form() -> DataForm {
+        let form: Element = r#"<x xmlns="jabber:x:data" type="form">
+            <title>Configuration</title>
+            <field var="FORM_TYPE" type="hidden"><value>http://jabber.org/protocol/muc#roomconfig</value></field>
+            <field var="intro" type="fixed"><value>Room settings</value></field>
+            <field var="muc#roomconfig_persistentroom" type="boolean" label="Persistent"><value>0</value></field>
+            <field var="muc#roomconfig_whois" type="list-single" label="Who can see real JIDs">
+                <value>moderators</value>
+                <option label="Moderators"><value>moderators</value></option>
+                <option label="Anyone"><value>anyone</value></option>
+            </field>
+            <field var="muc#roomconfig_roomadmins" type="jid-multi"/>
+        </x>"#
+            .parse()
+            .unwrap();
+        DataForm::try_from(form).unwrap()
+    }
+
+    #[test]
+    fn test_edit_fields() {
+        // Given
+        let mut view = DataFormView::<()>::new(form());
+
+        // When
+        view.edit("").unwrap();
+        view.select(false);
+        view.edit("Anyone").unwrap();
+        view.select(false);
+        view.edit("paul@fariello.eu, needle@fariello.eu").unwrap();
+        let invalid = view.edit("@");
+        view.select(false);
+        let submitted = submit_form(view.get_form());
+
+        // Then
+        assert!(invalid.is_err());
+        assert_eq!(
+            submitted.form_type,
+            Some(String::from("http://jabber.org/protocol/muc#roomconfig"))
+        );
+        let values = submitted
+            .fields
+            .iter()
+            .map(|field| (field.var.as_str(), field.values.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                ("muc#roomconfig_persistentroom", vec![String::from("1")]),
+                ("muc#roomconfig_whois", vec![String::from("anyone")]),
+                (
+                    "muc#roomconfig_roomadmins",
+                    vec![
+                        String::from("paul@fariello.eu"),
+                        String::from("needle@fariello.eu")
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_text_fields() {
+        // Given
+        let form: Element = r#"<x xmlns="jabber:x:data" type="form">
+            <field var="password" type="text-private"/>
+            <field var="description" type="text-multi"/>
+        </x>"#
+            .parse()
+            .unwrap();
+        let mut view = DataFormView::<()>::new(DataForm::try_from(form).unwrap());
+
+        // When
+        view.edit(" secret ").unwrap();
+        view.select(false);
+        view.edit("First line\nSecond line").unwrap();
+
+        // Then
+        let values = view
+            .get_form()
+            .fields
+            .iter()
+            .map(|field| field.values.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                vec![String::from(" secret ")],
+                vec![String::from("First line"), String::from("Second line")]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_form_strips_control_sequences() {
+        // Given
+        let form = DataForm {
+            type_: DataFormType::Result_,
+            form_type: None,
+            title: Some(String::from("Result\x1b[2J")),
+            instructions: None,
+            fields: vec![Field {
+                var: String::from("status"),
+                type_: FieldType::TextSingle,
+                label: Some(String::from("\x1b[31mStatus")),
+                required: false,
+                options: vec![],
+                values: vec![String::from("done")],
+                media: vec![],
+            }],
+        };
+
+        // When
+        let formatted = format_form(&form);
+
+        // Then
+        assert_eq!(formatted, "Result\nStatus: done");
+    }
+}
@@ -18,11 +18,13 @@ mod message;
 mod command;
 mod color;
 mod crypto;
+mod data_form;
 mod i18n;
 #[cfg(feature = "image")]
 mod image;
 mod mods;
 mod storage;
+mod styling;
 mod word;
 
 use crate::core::Aparte;
@@ -2,13 +2,14 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use std::cmp::{self, Ordering};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::hash::{self, Hash};
 #[cfg(feature = "image")]
 use std::io::Cursor;
 use std::io::Write;
 use std::os::fd::AsFd;
+use std::str::FromStr;
 use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
 #[cfg(feature = "image")]
@@ -29,9 +30,13 @@ use termion::color;
 use unicode_segmentation::UnicodeSegmentation as _;
 use uuid::Uuid;
 use xmpp_parsers::delay::Delay;
+use xmpp_parsers::hints::Store;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
+use xmpp_parsers::message_correct::Replace;
 use xmpp_parsers::oob::Oob;
-use xmpp_parsers::{BareJid, Jid};
+use xmpp_parsers::receipts::Request;
+use xmpp_parsers::stanza_id::{OriginId, StanzaId};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
 
 use crate::account::Account;
 use crate::color::id_to_rgb;
@@ -43,6 +48,15 @@ use crate::core::Event;
 use crate::i18n;
 #[cfg(feature = "image")]
 use crate::image::convert_to_sixel;
+use crate::styling;
+
+pub const NS_CHAT_MARKERS: &str = "urn:xmpp:chat-markers:0";
+pub const NS_REPLY: &str = "urn:xmpp:reply:0";
+pub const NS_FALLBACK: &str = "urn:xmpp:fallback:0";
+pub const NS_RETRACT: &str = "urn:xmpp:message-retract:1";
+
+const RETRACTION_FALLBACK_BODY: &str =
+    "This person attempted to retract a previous message, but it's unsupported by your client.";
 
 #[derive(Debug, Clone)]
 pub struct XmppMessageVersion {
@@ -82,9 +96,71 @@ impl XmppMessageVersion {
     }
 }
 
+/// Removal of a message, either by its author (XEP-0424) or by a moderator (XEP-0425)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retraction {
+    /// Moderator who removed the message, None when retracted by its author
+    pub by: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl Retraction {
+    pub fn tombstone(&self) -> String {
+        match (&self.by, &self.reason) {
+            (None, _) => "This message has been retracted".to_string(),
+            (Some(by), None) => format!("This message has been moderated by {by}"),
+            (Some(by), Some(reason)) => {
+                format!("This message has been moderated by {by}: {reason}")
+            }
+        }
+    }
+}
+
+/// Reference to the message answered by a reply (XEP-0461)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// Author of the referenced message
+    pub to: Option<Jid>,
+    /// Id of the referenced message, its stanza-id in channels
+    pub id: String,
+    /// One line summary of the referenced message, e.g. "paul: Hello"
+    pub preview: Option<String>,
+}
+
+/// Delivery progress of an outgoing message, in increasing order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeliveryState {
+    /// Waiting in the outbox for the account to be connected
+    Pending,
+    /// Handed to our server
+    Sent,
+    /// Known to be stored by the server (archived with a stanza-id)
+    Acked,
+    /// Received by the recipient client (XEP-0184 or XEP-0333)
+    Delivered,
+    /// Seen by the recipient (XEP-0333)
+    Displayed,
+}
+
+impl DeliveryState {
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "⧗",
+            DeliveryState::Sent => "·",
+            DeliveryState::Acked => "✓",
+            DeliveryState::Delivered => "✓✓",
+            DeliveryState::Displayed => "◉",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VersionedXmppMessage {
     pub id: String,
+    /// XEP-0359 stanza-id assigned by the archive holding this message
+    pub stanza_id: Option<String>,
+    /// XEP-0359 origin-id assigned by the sending client
+    pub origin_id: Option<String>,
     pub from: BareJid,
     pub from_full: Jid,
     pub to: BareJid,
@@ -93,6 +169,21 @@ pub struct VersionedXmppMessage {
     pub type_: XmppMessageType,
     pub direction: Direction,
     pub archive: bool,
+    pub retraction: Option<Retraction>,
+    /// Delivery state of outgoing messages
+    pub delivery: Option<DeliveryState>,
+    /// Sender asked for a delivery receipt (XEP-0184)
+    pub receipt_request: bool,
+    /// Sender asked for chat markers (XEP-0333)
+    pub markable: bool,
+    /// Reactions (XEP-0444) indexed by their author
+    pub reactions: HashMap<String, Vec<String>>,
+    pub reply: Option<Reply>,
+    /// Error returned in place of delivering an outgoing message
+    pub error: Option<String>,
+    /// Subjects of normal and headline messages indexed by language, channel subjects are
+    /// handled separately
+    pub subjects: HashMap<String, String>,
 }
 
 impl VersionedXmppMessage {
@@ -100,9 +191,48 @@ impl VersionedXmppMessage {
         let last = self.history.iter().max().unwrap();
         last.bodies.iter()
     }
-    pub fn get_last_body(&self) -> &str {
+    pub fn get_last_body(&self, prefered_langs: Vec<&str>) -> &str {
         let last = self.history.iter().max().unwrap();
-        last.get_best_body(vec![])
+        // Retracted messages have no body left
+        i18n::get_best(&last.bodies, prefered_langs)
+            .map(|(_, body)| body.as_str())
+            .unwrap_or_default()
+    }
+
+    pub fn get_best_subject(&self, prefered_langs: Vec<&str>) -> Option<&str> {
+        i18n::get_best(&self.subjects, prefered_langs).map(|(_, subject)| subject.as_str())
+    }
+
+    /// Bodies without language are written in the given one
+    pub fn set_default_lang(&mut self, lang: &str) {
+        if let Some(last) = self.history.iter_mut().max() {
+            if let Some(body) = last.bodies.remove("") {
+                last.bodies.insert(lang.to_string(), body);
+            }
+        }
+    }
+
+    /// One line summary of this message: its author and the first line of its body
+    pub fn get_preview(&self, prefered_langs: Vec<&str>) -> String {
+        let author = match &self.type_ {
+            XmppMessageType::Channel => match self.from_full.try_as_full() {
+                Ok(full_jid) => full_jid.resource().to_string(),
+                Err(bare_jid) => bare_jid.to_string(),
+            },
+            XmppMessageType::Chat => self.from.to_string(),
+        };
+        let body = self.get_last_body(prefered_langs);
+        let line = body.lines().next().unwrap_or_default();
+        format!("{author}: {line}")
+    }
+
+    /// Drop the content of every version of this message
+    pub fn retract(&mut self, retraction: Retraction) {
+        for version in self.history.iter_mut() {
+            version.bodies.clear();
+            version.oobs.clear();
+        }
+        self.retraction = Some(retraction);
     }
 
     pub fn get_original_timestamp(&self) -> &DateTime<FixedOffset> {
@@ -115,11 +245,12 @@ impl VersionedXmppMessage {
             .id
             .clone()
             .unwrap_or_else(|| Uuid::new_v4().to_string());
-        let bodies: HashMap<String, String> = message
-            .bodies
-            .iter()
-            .map(|(lang, body)| (lang.clone(), body.0.clone()))
-            .collect();
+        if self.history.iter().any(|version| version.id == id) {
+            // Same correction received twice (reflection, archive)
+            return;
+        }
+
+        let bodies = Message::get_bodies_from_xmpp(message);
 
         let oobs: Vec<Oob> = message
             .payloads
@@ -185,11 +316,7 @@ impl Message {
             .clone()
             .unwrap_or_else(|| Uuid::new_v4().to_string());
         if let Some(from) = message.from.clone() {
-            let bodies: HashMap<String, String> = message
-                .bodies
-                .iter()
-                .map(|(lang, body)| (lang.clone(), body.0.clone()))
-                .collect();
+            let bodies = Message::get_bodies_from_xmpp(message);
             let oobs: Vec<_> = message
                 .payloads
                 .iter()
@@ -210,12 +337,15 @@ impl Message {
                 None => account.clone().into(),
             };
 
-            match message.type_ {
-                XmppParsersMessageType::Chat => {
+            let mut built = match message.type_ {
+                // Normal messages and headlines are displayed as chats with their sender
+                XmppParsersMessageType::Chat
+                | XmppParsersMessageType::Normal
+                | XmppParsersMessageType::Headline => {
                     if from.clone().node() == account.node()
                         && from.clone().domain() == account.domain()
                     {
-                        Ok(Message::outgoing_chat(
+                        Message::outgoing_chat(
                             id,
                             timestamp,
                             &from,
@@ -223,9 +353,9 @@ impl Message {
                             bodies,
                             Some(oobs),
                             archive,
-                        ))
+                        )
                     } else {
-                        Ok(Message::incoming_chat(
+                        Message::incoming_chat(
                             id,
                             timestamp,
                             &from,
@@ -233,10 +363,10 @@ impl Message {
                             bodies,
                             Some(oobs),
                             archive,
-                        ))
+                        )
                     }
                 }
-                XmppParsersMessageType::Groupchat => Ok(Message::incoming_channel(
+                XmppParsersMessageType::Groupchat => Message::incoming_channel(
                     id,
                     timestamp,
                     &from,
@@ -244,14 +374,135 @@ impl Message {
                     bodies,
                     Some(oobs),
                     archive,
-                )),
-                _ => Err(()),
+                ),
+                _ => return Err(()),
+            };
+
+            if let Message::Xmpp(xmpp_message) = &mut built {
+                xmpp_message.stanza_id = Message::get_stanza_id_from_xmpp(account, message);
+                xmpp_message.origin_id = Message::get_origin_id_from_xmpp(message);
+                xmpp_message.reply = Message::get_reply_from_xmpp(message);
+                if message.type_ != XmppParsersMessageType::Groupchat {
+                    xmpp_message.subjects = message
+                        .subjects
+                        .iter()
+                        .map(|(lang, subject)| (lang.clone(), subject.0.clone()))
+                        .collect();
+                }
+                xmpp_message.receipt_request = message
+                    .payloads
+                    .iter()
+                    .any(|payload| payload.is("request", ns::RECEIPTS));
+                xmpp_message.markable = message
+                    .payloads
+                    .iter()
+                    .any(|payload| payload.is("markable", NS_CHAT_MARKERS));
+                if xmpp_message.direction == Direction::Outgoing && xmpp_message.stanza_id.is_some()
+                {
+                    xmpp_message.delivery = Some(DeliveryState::Acked);
+                }
             }
+
+            Ok(built)
         } else {
             Err(())
         }
     }
 
+    /// Get the stanza-id set by the archive responsible for this message: our own account for
+    /// chats, the room for channels.
+    pub fn get_stanza_id_from_xmpp(
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) -> Option<String> {
+        let by = match message.type_ {
+            XmppParsersMessageType::Groupchat => message.from.as_ref()?.to_bare(),
+            _ => account.to_bare(),
+        };
+
+        message
+            .payloads
+            .iter()
+            .filter_map(|payload| StanzaId::try_from(payload.clone()).ok())
+            .find(|stanza_id| stanza_id.by.to_bare() == by)
+            .map(|stanza_id| stanza_id.id)
+    }
+
+    /// Range of the bodies quoting the message answered by a reply, in characters
+    fn get_reply_fallback_range(message: &XmppParsersMessage) -> Option<(usize, usize)> {
+        message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("reply", NS_REPLY))?;
+        let body = message
+            .payloads
+            .iter()
+            .find(|payload| {
+                payload.is("fallback", NS_FALLBACK) && payload.attr("for") == Some(NS_REPLY)
+            })?
+            .get_child("body", NS_FALLBACK)?;
+        let start = body.attr("start")?.parse().ok()?;
+        let end = body.attr("end")?.parse().ok()?;
+        Some((start, end))
+    }
+
+    /// Get bodies without the fallback quote of replies, which is rendered separately
+    pub fn get_bodies_from_xmpp(message: &XmppParsersMessage) -> HashMap<String, String> {
+        let range = Message::get_reply_fallback_range(message);
+        message
+            .bodies
+            .iter()
+            .map(|(lang, body)| {
+                let body = match range {
+                    Some((start, end)) => body
+                        .0
+                        .chars()
+                        .take(start)
+                        .chain(body.0.chars().skip(end))
+                        .collect(),
+                    None => body.0.clone(),
+                };
+                (lang.clone(), body)
+            })
+            .collect()
+    }
+
+    pub fn get_reply_from_xmpp(message: &XmppParsersMessage) -> Option<Reply> {
+        let reply = message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("reply", NS_REPLY))?;
+
+        // Quote used as preview until the referenced message is found
+        let preview = Message::get_reply_fallback_range(message).and_then(|(start, end)| {
+            let body = &message.bodies.values().next()?.0;
+            let quote = body
+                .chars()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .collect::<String>();
+            quote
+                .lines()
+                .map(|line| line.trim_start_matches('>').trim())
+                .find(|line| !line.is_empty())
+                .map(str::to_string)
+        });
+
+        Some(Reply {
+            to: reply.attr("to").and_then(|to| Jid::from_str(to).ok()),
+            id: reply.attr("id")?.to_string(),
+            preview,
+        })
+    }
+
+    pub fn get_origin_id_from_xmpp(message: &XmppParsersMessage) -> Option<String> {
+        message
+            .payloads
+            .iter()
+            .find_map(|payload| OriginId::try_from(payload.clone()).ok())
+            .map(|origin_id| origin_id.id)
+    }
+
     pub fn get_local_destination_from_xmpp<'a>(
         account: &Account,
         message: &'a XmppParsersMessage,
@@ -321,6 +572,8 @@ impl Message {
 
         Message::Xmpp(VersionedXmppMessage {
             id,
+            stanza_id: None,
+            origin_id: None,
             from: from.to_bare(),
             from_full: from.clone(),
             to: to.to_bare(),
@@ -329,6 +582,14 @@ impl Message {
             type_: XmppMessageType::Chat,
             direction: Direction::Incoming,
             archive,
+            retraction: None,
+            delivery: None,
+            receipt_request: false,
+            markable: false,
+            reactions: HashMap::new(),
+            reply: None,
+            error: None,
+            subjects: HashMap::new(),
         })
     }
 
@@ -351,7 +612,9 @@ impl Message {
         };
 
         Message::Xmpp(VersionedXmppMessage {
-            id,
+            id: id.clone(),
+            stanza_id: None,
+            origin_id: Some(id),
             from: from.to_bare(),
             from_full: from.clone(),
             to: to.to_bare(),
@@ -360,6 +623,14 @@ impl Message {
             type_: XmppMessageType::Chat,
             direction: Direction::Outgoing,
             archive,
+            retraction: None,
+            delivery: None,
+            receipt_request: false,
+            markable: false,
+            reactions: HashMap::new(),
+            reply: None,
+            error: None,
+            subjects: HashMap::new(),
         })
     }
 
@@ -383,6 +654,8 @@ impl Message {
 
         Message::Xmpp(VersionedXmppMessage {
             id,
+            stanza_id: None,
+            origin_id: None,
             from: from.to_bare(),
             from_full: from.clone(),
             to: to.to_bare(),
@@ -391,6 +664,14 @@ impl Message {
             type_: XmppMessageType::Channel,
             direction: Direction::Incoming,
             archive,
+            retraction: None,
+            delivery: None,
+            receipt_request: false,
+            markable: false,
+            reactions: HashMap::new(),
+            reply: None,
+            error: None,
+            subjects: HashMap::new(),
         })
     }
 
@@ -413,7 +694,9 @@ impl Message {
         };
 
         Message::Xmpp(VersionedXmppMessage {
-            id,
+            id: id.clone(),
+            stanza_id: None,
+            origin_id: Some(id),
             from: from.to_bare(),
             from_full: from.clone(),
             to: to.to_bare(),
@@ -422,6 +705,14 @@ impl Message {
             type_: XmppMessageType::Channel,
             direction: Direction::Outgoing,
             archive,
+            retraction: None,
+            delivery: None,
+            receipt_request: false,
+            markable: false,
+            reactions: HashMap::new(),
+            reply: None,
+            error: None,
+            subjects: HashMap::new(),
         })
     }
 
@@ -436,6 +727,8 @@ impl Message {
     pub fn encryption_recipient(&self) -> Option<BareJid> {
         match self {
             Message::Log(_) => None,
+            // Retractions carry no content to protect
+            Message::Xmpp(message) if message.retraction.is_some() => None,
             Message::Xmpp(message) => match message.direction {
                 Direction::Outgoing => match message.type_ {
                     XmppMessageType::Chat => Some(message.to.clone()),
@@ -448,7 +741,7 @@ impl Message {
 
     pub fn body(&self) -> &str {
         match self {
-            Message::Xmpp(message) => message.get_last_body(),
+            Message::Xmpp(message) => message.get_last_body(vec![]),
             Message::Log(LogMessage { body, .. }) => body,
         }
     }
@@ -498,33 +791,133 @@ impl PartialOrd for Message {
     }
 }
 
+/// Reference the answered message, quoting it for clients not supporting replies
+fn add_reply(xmpp_message: &mut XmppParsersMessage, reply: &Reply) {
+    let mut element = Element::builder("reply", NS_REPLY).attr("id", reply.id.clone());
+    if let Some(to) = &reply.to {
+        element = element.attr("to", to.to_string());
+    }
+    xmpp_message.payloads.push(element.build());
+
+    if let Some(preview) = &reply.preview {
+        let quote = format!("> {}\n", preview);
+        for body in xmpp_message.bodies.values_mut() {
+            body.0 = format!("{}{}", quote, body.0);
+        }
+        xmpp_message.payloads.push(
+            Element::builder("fallback", NS_FALLBACK)
+                .attr("for", NS_REPLY)
+                .append(
+                    Element::builder("body", NS_FALLBACK)
+                        .attr("start", "0")
+                        .attr("end", quote.chars().count().to_string())
+                        .build(),
+                )
+                .build(),
+        );
+    }
+}
+
+/// Identify the stanza sending the last version of the message, which is the correction
+/// (XEP-0308) of the message when it has several versions
+fn set_ids(xmpp_message: &mut XmppParsersMessage, message: &VersionedXmppMessage) {
+    let last = message.history.iter().max().unwrap();
+    let origin_id = match last.id == message.id {
+        true => message.origin_id.clone().unwrap_or(message.id.clone()),
+        false => {
+            xmpp_message.payloads.push(
+                Replace {
+                    id: message.id.clone(),
+                }
+                .into(),
+            );
+            last.id.clone()
+        }
+    };
+    xmpp_message.id = Some(last.id.clone());
+    xmpp_message
+        .payloads
+        .push(OriginId { id: origin_id }.into());
+}
+
+/// Retraction (XEP-0424) of the message. Chat messages are referenced by their origin-id,
+/// channel messages by the stanza-id the channel assigned.
+fn retraction_stanza(message: &VersionedXmppMessage) -> Result<XmppParsersMessage, ()> {
+    let (peer, type_, id) = match message.type_ {
+        XmppMessageType::Chat => (
+            message.to.clone(),
+            XmppParsersMessageType::Chat,
+            message.origin_id.clone().unwrap_or(message.id.clone()),
+        ),
+        XmppMessageType::Channel => (
+            match message.direction {
+                Direction::Incoming => message.from.clone(),
+                Direction::Outgoing => message.to.clone(),
+            },
+            XmppParsersMessageType::Groupchat,
+            message.stanza_id.clone().ok_or(())?,
+        ),
+    };
+
+    let mut stanza = XmppParsersMessage::new(Some(Jid::from(peer)));
+    stanza.id = Some(Uuid::new_v4().to_string());
+    stanza.type_ = type_;
+    stanza.bodies.insert(
+        String::new(),
+        xmpp_parsers::message::Body(RETRACTION_FALLBACK_BODY.to_string()),
+    );
+    stanza.payloads.push(
+        Element::builder("retract", NS_RETRACT)
+            .attr("id", id)
+            .build(),
+    );
+    stanza.payloads.push(
+        Element::builder("fallback", NS_FALLBACK)
+            .attr("for", NS_RETRACT)
+            .build(),
+    );
+    stanza.payloads.push(Store.into());
+    Ok(stanza)
+}
+
 impl TryFrom<Message> for xmpp_parsers::Element {
     type Error = ();
 
     fn try_from(message: Message) -> Result<Self, Self::Error> {
         match message {
             Message::Log(_) => Err(()),
+            // Our own channel messages can come back as incoming ones, retracting them is allowed
+            Message::Xmpp(message) if message.retraction.is_some() => {
+                retraction_stanza(&message).map(Into::into)
+            }
             Message::Xmpp(message) => match message.direction {
                 Direction::Outgoing => match message.type_ {
                     XmppMessageType::Chat => {
                         let mut xmpp_message = xmpp_parsers::message::Message::new(Some(
                             Jid::from(message.to.clone()),
                         ));
-                        xmpp_message.id = Some(message.id.clone());
+                        set_ids(&mut xmpp_message, &message);
                         xmpp_message.type_ = xmpp_parsers::message::MessageType::Chat;
+                        xmpp_message.payloads.push(Request.into());
+                        xmpp_message
+                            .payloads
+                            .push(Element::builder("markable", NS_CHAT_MARKERS).build());
                         xmpp_message.bodies = message
                             .get_last_bodies()
                             .map(|(lang, body)| {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(reply) = &message.reply {
+                            add_reply(&mut xmpp_message, reply);
+                        }
                         Ok(xmpp_message.into())
                     }
                     XmppMessageType::Channel => {
                         let mut xmpp_message = xmpp_parsers::message::Message::new(Some(
                             Jid::from(message.to.clone()),
                         ));
-                        xmpp_message.id = Some(message.id.clone());
+                        set_ids(&mut xmpp_message, &message);
                         xmpp_message.type_ = xmpp_parsers::message::MessageType::Groupchat;
                         xmpp_message.bodies = message
                             .get_last_bodies()
@@ -532,6 +925,9 @@ impl TryFrom<Message> for xmpp_parsers::Element {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(reply) = &message.reply {
+                            add_reply(&mut xmpp_message, reply);
+                        }
                         Ok(xmpp_message.into())
                     }
                 },
@@ -541,13 +937,80 @@ impl TryFrom<Message> for xmpp_parsers::Element {
     }
 }
 
+/// Graphic rendition attributes set by SGR escape sequences, indexed by the parameter
+/// resetting them
+#[derive(Default)]
+struct Rendition(BTreeMap<u8, String>);
+
+impl Rendition {
+    fn update(&mut self, params: &str) {
+        let mut params = params.split(';');
+        while let Some(param) = params.next() {
+            // Empty parameter defaults to 0
+            let code = param.parse::<u8>().unwrap_or(0);
+            match code {
+                0 => self.0.clear(),
+                1 | 2 => {
+                    self.0.insert(22, code.to_string());
+                }
+                3 | 4 | 5 | 7 | 9 => {
+                    self.0.insert(code + 20, code.to_string());
+                }
+                21 | 22 => {
+                    self.0.remove(&22);
+                }
+                23 | 24 | 25 | 27 | 29 | 39 | 49 => {
+                    self.0.remove(&code);
+                }
+                30..=37 | 90..=97 => {
+                    self.0.insert(39, code.to_string());
+                }
+                40..=47 | 100..=107 => {
+                    self.0.insert(49, code.to_string());
+                }
+                38 | 48 => {
+                    // Extended colors: 5;n or 2;r;g;b
+                    let mut color = vec![code.to_string()];
+                    if let Some(mode) = params.next() {
+                        let count = if mode == "2" { 3 } else { 1 };
+                        color.push(mode.to_string());
+                        color.extend(params.by_ref().take(count).map(str::to_string));
+                    }
+                    self.0.insert(code + 1, color.join(";"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn restore(&self) -> String {
+        match self.0.is_empty() {
+            true => String::new(),
+            false => format!(
+                "\x1b[{}m",
+                self.0.values().cloned().collect::<Vec<_>>().join(";")
+            ),
+        }
+    }
+
+    fn reset(&self) -> String {
+        match self.0.is_empty() {
+            true => String::new(),
+            false => termion::style::Reset.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MessageView {
     pub message: Message,
+    selected: bool,
     dimensions: Option<Dimensions>,
     #[cfg(feature = "image")]
     image: Arc<RwLock<Option<SixelImage>>>,
     dirty: Arc<AtomicBool>,
+    /// Preferred languages used to choose the body to display
+    languages: Vec<String>,
 }
 
 impl Eq for MessageView {}
@@ -578,11 +1041,13 @@ impl Hash for MessageView {
 
 impl MessageView {
     #[cfg(not(feature = "image"))]
-    pub fn new(_aparte: &mut AparteAsync, message: Message) -> Self {
+    pub fn new(aparte: &mut AparteAsync, message: Message) -> Self {
         MessageView {
             message,
+            selected: false,
             dimensions: None,
             dirty: Arc::new(AtomicBool::new(true)),
+            languages: aparte.config.languages.clone(),
         }
     }
 
@@ -629,9 +1094,22 @@ impl MessageView {
         };
         MessageView {
             message,
+            selected: false,
             dimensions: None,
             image,
             dirty,
+            languages: aparte.config.languages.clone(),
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.selected
+    }
+
+    pub fn set_selected(&mut self, selected: bool) {
+        if self.selected != selected {
+            self.selected = selected;
+            self.dirty.store(true, atomic::Ordering::Relaxed);
         }
     }
 
@@ -676,7 +1154,11 @@ impl MessageView {
         lines
     }
 
-    fn format_header(message: &VersionedXmppMessage) -> String {
+    fn format_header(
+        message: &VersionedXmppMessage,
+        selected: bool,
+        prefered_langs: &[&str],
+    ) -> String {
         let author = terminus::clean_str(&match &message.type_ {
             XmppMessageType::Channel => match &message.from_full.try_as_full() {
                 Ok(full_jid) => full_jid.resource().to_string(),
@@ -686,13 +1168,28 @@ impl MessageView {
         });
 
         let timestamp = Local.from_utc_datetime(&message.get_original_timestamp().naive_local());
-        let body = message.get_last_body();
+        let timestamp = match selected {
+            true => format!(
+                "{}{}{}",
+                termion::style::Invert,
+                timestamp.format("%T"),
+                termion::style::NoInvert
+            ),
+            false => timestamp.format("%T").to_string(),
+        };
+        let body = message.get_last_body(prefered_langs.to_vec());
         let me = body.starts_with("/me");
 
         let (r, g, b) = id_to_rgb(&author);
 
         let mut attributes = "".to_string();
-        if message.has_multiple_version() {
+        if message.error.is_some() {
+            attributes.push_str("✗ ");
+        } else if let Some(delivery) = &message.delivery {
+            attributes.push_str(delivery.glyph());
+            attributes.push(' ');
+        }
+        if message.has_multiple_version() && message.retraction.is_none() {
             attributes.push_str("✎ ");
         }
 
@@ -701,7 +1198,7 @@ impl MessageView {
                 "{}{}{} - {}* {}{}{}",
                 color::Bg(color::Reset),
                 color::Fg(color::Reset),
-                timestamp.format("%T"),
+                timestamp,
                 attributes,
                 color::Fg(color::Rgb(r, g, b)),
                 author,
@@ -711,7 +1208,7 @@ impl MessageView {
                 "{}{}{} - {}{}{}:{} ",
                 color::Bg(color::Reset),
                 color::Fg(color::Reset),
-                timestamp.format("%T"),
+                timestamp,
                 attributes,
                 color::Fg(color::Rgb(r, g, b)),
                 author,
@@ -720,39 +1217,136 @@ impl MessageView {
         }
     }
 
-    fn format_xmpp_text(message: &VersionedXmppMessage, max_width: Option<u16>) -> Vec<String> {
-        let mut buffer = Self::format_header(message);
+    fn format_xmpp_text(
+        message: &VersionedXmppMessage,
+        selected: bool,
+        prefered_langs: &[&str],
+        max_width: Option<u16>,
+    ) -> Vec<String> {
+        let mut buffer = Self::format_header(message, selected, prefered_langs);
 
-        let padding_len = buffer.len();
+        // Selection must not alter the layout of the message
+        let padding_len = Self::format_header(message, false, prefered_langs).len();
         let padding = " ".repeat(padding_len);
 
-        let body = message.get_last_body();
-        let mut iter = body.strip_prefix("/me").unwrap_or(body).lines();
+        if let Some(retraction) = &message.retraction {
+            buffer.push_str(&format!(
+                "{}{}{}",
+                termion::style::Italic,
+                terminus::clean_str(&retraction.tombstone()),
+                termion::style::NoItalic
+            ));
+            return Self::format_text(buffer, max_width);
+        }
+
+        if let Some(preview) = message
+            .reply
+            .as_ref()
+            .and_then(|reply| reply.preview.as_ref())
+        {
+            let preview = format!("↪ {}", terminus::clean_str(preview));
+            let preview = match max_width {
+                Some(max_width) => terminus::term_string_visible_truncate(
+                    &preview,
+                    (max_width as usize).saturating_sub(terminus::term_string_visible_len(&buffer)),
+                    Some("…"),
+                ),
+                None => preview,
+            };
+            buffer.push_str(&format!(
+                "{}{}{}\n{}",
+                color::Fg(color::LightBlack),
+                preview,
+                color::Fg(color::Reset),
+                padding
+            ));
+        }
+
+        if let Some(subject) = message.get_best_subject(prefered_langs.to_vec()) {
+            buffer.push_str(&format!(
+                "{}{}{}\n{}",
+                termion::style::Bold,
+                terminus::clean_str(subject),
+                termion::style::NoBold,
+                padding
+            ));
+        }
+
+        let body = message.get_last_body(prefered_langs.to_vec());
+        let body = terminus::clean_str(body.strip_prefix("/me").unwrap_or(body));
+        let mut iter = styling::format(&body).into_iter();
 
         if let Some(line) = iter.next() {
-            buffer.push_str(&terminus::clean_str(line));
+            buffer.push_str(&line);
         }
         for line in iter {
-            buffer.push_str(format!("\n{}{}", padding, terminus::clean_str(line)).as_str());
+            buffer.push_str(format!("\n{}{}", padding, line).as_str());
+        }
+
+        if let Some(reactions) = Self::format_reactions(message) {
+            buffer.push_str(format!("\n{}{}", padding, reactions).as_str());
+        }
+
+        if let Some(error) = &message.error {
+            buffer.push_str(&format!(
+                "\n{}{}⚠ {} (/retry to send again){}",
+                padding,
+                color::Fg(color::Red),
+                terminus::clean_str(error),
+                color::Fg(color::Reset)
+            ));
         }
 
         Self::format_text(buffer, max_width)
     }
 
+    /// Compact summary of reactions, most frequent first: "👍 2 ❤ 1"
+    fn format_reactions(message: &VersionedXmppMessage) -> Option<String> {
+        let mut counts: Vec<(&String, usize)> = Vec::new();
+        for reaction in message.reactions.values().flatten() {
+            match counts.iter_mut().find(|(known, _)| *known == reaction) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((reaction, 1)),
+            }
+        }
+        if counts.is_empty() {
+            return None;
+        }
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+        Some(
+            counts
+                .iter()
+                .map(|(reaction, count)| format!("{} {}", terminus::clean_str(reaction), count))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
     fn format(&self, max_width: Option<u16>) -> Vec<String> {
         match &self.message {
             Message::Log(message) => Self::format_log(message, max_width),
-            Message::Xmpp(message) => Self::format_xmpp_text(message, max_width),
+            Message::Xmpp(message) => {
+                let prefered_langs = self
+                    .languages
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                Self::format_xmpp_text(message, self.selected, &prefered_langs, max_width)
+            }
         }
     }
 
     fn format_text(text: String, max_width: Option<u16>) -> Vec<String> {
         let mut buffers: Vec<String> = Vec::new();
+        // Styles are reset at the end of each line and restored on the next one, so that
+        // lines can be rendered independently
+        let mut rendition = Rendition::default();
         for line in text.lines() {
             let mut words = line.split_word_bounds();
 
             let mut line_len = 0;
-            let mut chunk = String::new();
+            let mut chunk = rendition.restore();
             while let Some(word) = words.next() {
                 let visible_word;
                 let mut remaining = String::new();
@@ -784,6 +1378,9 @@ impl MessageView {
                                         '\x20'..='\x2f' => {} // intermediate bytes
                                         '\x40'..='\x7e' => {
                                             // final byte
+                                            if c == 'm' {
+                                                rendition.update(&escape[2..escape.len() - 1]);
+                                            }
                                             chunk.push_str(&escape);
                                             end = true;
                                         }
@@ -820,8 +1417,9 @@ impl MessageView {
                     line_len + grapheme_count > max_width as usize
                 }) {
                     // Wrap line
+                    chunk.push_str(&rendition.reset());
                     buffers.push(chunk);
-                    chunk = String::new();
+                    chunk = rendition.restore();
                     line_len = 0;
                 }
 
@@ -829,6 +1427,7 @@ impl MessageView {
                 line_len += grapheme_count;
             }
 
+            chunk.push_str(&rendition.reset());
             buffers.push(chunk);
         }
 
@@ -873,7 +1472,12 @@ impl MessageView {
 
         terminus::clear_screen(dimensions, screen);
 
-        let header = Self::format_header(message);
+        let prefered_langs = self
+            .languages
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let header = Self::format_header(message, self.selected, &prefered_langs);
 
         terminus::goto!(screen, dimensions.left, dimensions.top);
         terminus::vprint!(screen, "{}", header);
@@ -992,6 +1596,8 @@ mod tests {
     use std::rc::Rc;
     use std::time::UNIX_EPOCH;
 
+    use std::str::FromStr;
+
     use chrono::Utc;
     use test_log::test;
 
@@ -1033,10 +1639,12 @@ mod tests {
                     timestamp: epoch.into(),
                     body: String::from(log),
                 }),
+                selected: false,
                 dimensions: None,
                 #[cfg(feature = "image")]
                 image: Arc::new(RwLock::new(None)),
                 dirty: Arc::new(AtomicBool::new(true)),
+                languages: Vec::new(),
             },
             Local.from_utc_datetime(&epoch.naive_utc()),
         )
@@ -1182,4 +1790,269 @@ mod tests {
             raw_formatted_log_message_line(None, 1, 40, " log").as_bytes()
         );
     }
+
+    #[test]
+    fn test_render_styling() {
+        // Given
+        let from = Jid::from_str("paul@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("me@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "*bold* and `*code*`".to_string());
+        let message =
+            Message::incoming_chat("id", LocalTz::now().into(), &from, &to, bodies, None, false);
+        let Message::Xmpp(message) = message else {
+            panic!("Expected an XMPP message");
+        };
+
+        // When
+        let lines = MessageView::format_xmpp_text(&message, false, &[], None);
+
+        // Then
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(&format!(
+            "{}*bold*{} and {}`*code*`{}",
+            termion::style::Bold,
+            termion::style::NoBold,
+            color::Fg(color::Yellow),
+            color::Fg(color::Reset)
+        )));
+    }
+
+    #[test]
+    fn test_render_styling_blocks() {
+        // Given
+        let body = "> _quoted_\n```\n*code*\n```\nnot*bold*";
+
+        // When
+        let lines = styling::format(body);
+
+        // Then
+        assert_eq!(
+            lines,
+            vec![
+                format!(
+                    "{}> {}{}_quoted_{}",
+                    color::Fg(color::LightBlack),
+                    color::Fg(color::Reset),
+                    termion::style::Italic,
+                    termion::style::NoItalic
+                ),
+                format!("{}```", color::Fg(color::Yellow)),
+                "*code*".to_string(),
+                format!("```{}", color::Fg(color::Reset)),
+                "not*bold*".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_wrapped_styling() {
+        // Given
+        let text = format!(
+            "{}a very long{} text",
+            termion::style::Bold,
+            termion::style::NoBold
+        );
+
+        // When
+        let lines = MessageView::format_text(text, Some(6));
+
+        // Then
+        // wrapped lines are rendered independently so each of them restores the style
+        assert_eq!(
+            lines,
+            vec![
+                format!("{}a very{}", termion::style::Bold, termion::style::Reset),
+                format!("{} long{} ", termion::style::Bold, termion::style::NoBold),
+                "text".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retract_message() {
+        // Given
+        let from = Jid::from_str("paul@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("me@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Oops".to_string());
+        let message =
+            Message::incoming_chat("id", LocalTz::now().into(), &from, &to, bodies, None, false);
+        let Message::Xmpp(mut message) = message else {
+            panic!("Expected an XMPP message");
+        };
+
+        // When
+        message.retract(Retraction {
+            by: Some("moderator".to_string()),
+            reason: Some("Spam".to_string()),
+        });
+
+        // Then
+        assert_eq!(message.get_last_body(vec![]), "");
+        assert_eq!(
+            message.retraction.unwrap().tombstone(),
+            "This message has been moderated by moderator: Spam"
+        );
+    }
+
+    #[test]
+    fn test_reply_fallback_is_stripped() {
+        // Given
+        let message: xmpp_parsers::Element = r#"<message xmlns="jabber:client" from="paul@fariello.eu/aparte" to="me@fariello.eu" id="reply-id" type="chat">
+            <body>> paul: Hello
+Hi!</body>
+            <reply xmlns="urn:xmpp:reply:0" to="paul@fariello.eu" id="message-id"/>
+            <fallback xmlns="urn:xmpp:fallback:0" for="urn:xmpp:reply:0">
+                <body start="0" end="14"/>
+            </fallback>
+        </message>"#
+            .parse()
+            .unwrap();
+        let message = XmppParsersMessage::try_from(message).unwrap();
+
+        // When
+        let bodies = Message::get_bodies_from_xmpp(&message);
+        let reply = Message::get_reply_from_xmpp(&message).unwrap();
+
+        // Then
+        assert_eq!(bodies.get(""), Some(&"Hi!".to_string()));
+        assert_eq!(reply.id, "message-id");
+        assert_eq!(reply.preview, Some("paul: Hello".to_string()));
+    }
+
+    #[test]
+    fn test_outgoing_chat_requests_receipts() {
+        // Given
+        let from = Jid::from_str("me@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("paul@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Hello".to_string());
+        let message =
+            Message::outgoing_chat("id", LocalTz::now().into(), &from, &to, bodies, None, false);
+
+        // When
+        let element = xmpp_parsers::Element::try_from(message).unwrap();
+
+        // Then
+        assert!(element.has_child("request", ns::RECEIPTS));
+        assert!(element.has_child("markable", NS_CHAT_MARKERS));
+    }
+
+    #[test]
+    fn test_corrected_outgoing_chat() {
+        // Given
+        let from = Jid::from_str("me@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("paul@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Helo".to_string());
+        let timestamp = DateTime::parse_from_rfc3339("2024-06-01T10:00:00+00:00").unwrap();
+        let mut message = Message::outgoing_chat("id", timestamp, &from, &to, bodies, None, false);
+        let mut correction = XmppParsersMessage::new(None);
+        correction.id = Some("correction-id".to_string());
+        correction.bodies.insert(
+            "".to_string(),
+            xmpp_parsers::message::Body("Hello".to_string()),
+        );
+        if let Message::Xmpp(message) = &mut message {
+            message.add_version_from_xmpp(&correction);
+        }
+
+        // When
+        let element = xmpp_parsers::Element::try_from(message).unwrap();
+
+        // Then
+        let sent = XmppParsersMessage::try_from(element).unwrap();
+        assert_eq!(sent.id, Some("correction-id".to_string()));
+        assert_eq!(sent.bodies[""].0, "Hello");
+        assert!(sent.payloads.iter().any(
+            |payload| matches!(Replace::try_from(payload.clone()), Ok(Replace { id }) if id == "id")
+        ));
+    }
+
+    #[test]
+    fn test_retracted_outgoing_chat() {
+        // Given
+        let from = Jid::from_str("me@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("paul@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Oops".to_string());
+        let mut message =
+            Message::outgoing_chat("id", LocalTz::now().into(), &from, &to, bodies, None, false);
+        if let Message::Xmpp(message) = &mut message {
+            message.retract(Retraction {
+                by: None,
+                reason: None,
+            });
+        }
+
+        // When
+        let encryption = message.encryption_recipient();
+        let element = xmpp_parsers::Element::try_from(message).unwrap();
+
+        // Then
+        assert_eq!(encryption, None);
+        assert_eq!(
+            element
+                .get_child("retract", NS_RETRACT)
+                .and_then(|retract| retract.attr("id")),
+            Some("id")
+        );
+        assert!(element.has_child("fallback", NS_FALLBACK));
+        assert!(element.has_child("store", ns::HINTS));
+    }
+
+    #[test]
+    fn test_normal_message_subject() {
+        // Given
+        let message: xmpp_parsers::Element = r#"<message xmlns="jabber:client" from="fariello.eu" to="me@fariello.eu/aparte" id="motd">
+            <subject>Maintenance</subject>
+            <body>Server will restart at 22:00</body>
+        </message>"#
+            .parse()
+            .unwrap();
+        let message = XmppParsersMessage::try_from(message).unwrap();
+        let account = xmpp_parsers::FullJid::from_str("me@fariello.eu/aparte").unwrap();
+
+        // When
+        let message = Message::from_xmpp(&account, &message, &None, false).unwrap();
+
+        // Then
+        let Message::Xmpp(message) = message else {
+            panic!("Expected an XMPP message");
+        };
+        assert_eq!(message.direction, Direction::Incoming);
+        assert_eq!(message.from.to_string(), "fariello.eu");
+        assert_eq!(message.get_best_subject(vec![]), Some("Maintenance"));
+        assert_eq!(
+            message.get_last_body(vec![]),
+            "Server will restart at 22:00"
+        );
+    }
+
+    #[test]
+    fn test_prefered_body_language() {
+        // Given
+        let from = Jid::from_str("paul@fariello.eu/aparte").unwrap();
+        let to = Jid::from_str("me@fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Hello".to_string());
+        bodies.insert("fr".to_string(), "Bonjour".to_string());
+        let message =
+            Message::incoming_chat("id", LocalTz::now().into(), &from, &to, bodies, None, false);
+        let Message::Xmpp(mut message) = message else {
+            panic!("Expected an XMPP message");
+        };
+
+        // When
+        let default = message.get_last_body(vec![]).to_string();
+        let prefered = message.get_last_body(vec!["de", "fr"]).to_string();
+        message.set_default_lang("en");
+
+        // Then
+        assert_eq!(default, "Hello");
+        assert_eq!(prefered, "Bonjour");
+        assert_eq!(message.get_last_body(vec!["en"]), "Hello");
+        assert_eq!(message.get_last_bodies().count(), 2);
+    }
 }
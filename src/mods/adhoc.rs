@@ -0,0 +1,543 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+//! XEP-0050: Ad-Hoc Commands
+//!
+//! Each stage of a command expecting input is shown as a form in the window of the command,
+//! notes and results are gathered in the adhoc window.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{ns, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::data_form;
+use crate::i18n;
+use crate::mods::ui::UIMod;
+
+const NS_COMMANDS: &str = "http://jabber.org/protocol/commands";
+
+/// Window in which the forms of a command are filled
+fn command_window(jid: &Jid, node: &str) -> String {
+    format!("adhoc:{jid}/{node}")
+}
+
+command_def!(adhoc_list,
+r#"/adhoc list <jid>
+
+    jid           Entity exposing commands, usually a server or a bot
+
+Description:
+    List the ad-hoc commands available on an entity.
+
+Example:
+    /adhoc list fariello.eu"#,
+{
+    jid: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).context("Invalid JID")?;
+    AdhocMod::list(aparte, &account, &jid);
+    Ok(())
+});
+
+command_def!(adhoc_run,
+r#"/adhoc run <jid> <node>
+
+    jid           Entity exposing the command
+    node          Node of the command, as given by /adhoc list
+
+Description:
+    Run an ad-hoc command. Each stage expecting input is shown as a form, see
+    /help form. Submitting it with /form submit proceeds to the default next
+    stage, /adhoc next, /adhoc prev and /adhoc complete choose another one.
+
+Example:
+    /adhoc run fariello.eu http://jabber.org/protocol/admin#announce"#,
+{
+    jid: String,
+    node: String,
+},
+|aparte, _command| {
+    let account = aparte.current_account().context("No connection found")?;
+    let jid = Jid::from_str(&jid).context("Invalid JID")?;
+    let mut adhoc = aparte.get_mod_mut::<AdhocMod>();
+    adhoc.run(aparte, &account, jid, node);
+    Ok(())
+});
+
+command_def!(
+    adhoc_next,
+    r#"/adhoc next
+
+Description:
+    Send the form of the current command window and proceed to the next stage."#,
+    {},
+    |aparte, command| { AdhocMod::proceed(aparte, &command.context, "next") }
+);
+
+command_def!(
+    adhoc_prev,
+    r#"/adhoc prev
+
+Description:
+    Go back to the previous stage of the command of the current window."#,
+    {},
+    |aparte, command| { AdhocMod::proceed(aparte, &command.context, "prev") }
+);
+
+command_def!(
+    adhoc_complete,
+    r#"/adhoc complete
+
+Description:
+    Send the form of the current command window and complete the command."#,
+    {},
+    |aparte, command| { AdhocMod::proceed(aparte, &command.context, "complete") }
+);
+
+command_def!(adhoc,
+r#"/adhoc list|run|next|prev|complete"#,
+{
+    action: Command = {
+        children: {
+            "list": adhoc_list,
+            "run": adhoc_run,
+            "next": adhoc_next,
+            "prev": adhoc_prev,
+            "complete": adhoc_complete,
+        }
+    },
+});
+
+#[derive(Debug, Clone)]
+pub enum AdhocEvent {
+    /// Response of the entity to an action on the command of a window
+    Response {
+        window: String,
+        result: Result<Element, String>,
+    },
+    /// Text shown in the adhoc window
+    Note(String),
+}
+
+struct Session {
+    account: Account,
+    jid: Jid,
+    node: String,
+    /// Assigned by the entity in its first response
+    id: Option<String>,
+    /// Actions allowed at the current stage, empty if the command has a single stage
+    actions: Vec<String>,
+    /// Action taken when the form is submitted
+    default_action: Option<String>,
+    /// Whether the command window is open
+    shown: bool,
+}
+
+struct Response {
+    session_id: Option<String>,
+    status: String,
+    actions: Vec<String>,
+    default_action: Option<String>,
+    /// Notes as their type and their text
+    notes: Vec<(String, String)>,
+    form: Option<DataForm>,
+}
+
+impl Response {
+    fn parse(command: &Element) -> Result<Self> {
+        if !command.is("command", NS_COMMANDS) {
+            bail!("invalid response");
+        }
+        let actions = command.get_child("actions", NS_COMMANDS);
+        let form = match command.get_child("x", ns::DATA_FORMS) {
+            Some(form) => Some(DataForm::try_from(form.clone())?),
+            None => None,
+        };
+
+        Ok(Self {
+            session_id: command.attr("sessionid").map(String::from),
+            status: command.attr("status").unwrap_or("completed").to_string(),
+            actions: actions
+                .iter()
+                .flat_map(|actions| actions.children())
+                .map(|action| action.name().to_string())
+                .collect(),
+            default_action: actions
+                .and_then(|actions| actions.attr("execute"))
+                .map(String::from),
+            notes: command
+                .children()
+                .filter(|child| child.is("note", NS_COMMANDS))
+                .map(|note| {
+                    let type_ = note.attr("type").unwrap_or("info").to_string();
+                    (type_, terminus::clean_str(&note.text()))
+                })
+                .collect(),
+            form,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct AdhocMod {
+    /// Running commands indexed by their window
+    sessions: HashMap<String, Session>,
+}
+
+impl AdhocMod {
+    fn iq(jid: &Jid, payload: IqType) -> Iq {
+        Iq {
+            from: None,
+            to: Some(jid.clone()),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload,
+        }
+    }
+
+    fn list(aparte: &Aparte, account: &Account, jid: &Jid) {
+        let query = Element::builder("query", ns::DISCO_ITEMS)
+            .attr("node", NS_COMMANDS)
+            .build();
+        let iq = Self::iq(jid, IqType::Get(query));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let jid = jid.clone();
+            async move {
+                let body = match Self::send_iq(&mut aparte, &account, iq).await {
+                    Ok(query) => {
+                        let commands = query.as_ref().map(Self::parse_items).unwrap_or_default();
+                        if commands.is_empty() {
+                            format!("No command available on {jid}")
+                        } else {
+                            format!("Commands available on {jid}:\n{}", commands.join("\n"))
+                        }
+                    }
+                    Err(err) => format!("Can't list commands of {jid}: {err:#}"),
+                };
+                aparte.schedule(Event::Adhoc(AdhocEvent::Note(body)));
+            }
+        });
+    }
+
+    /// Commands listed in a disco#items result, with their name when known
+    fn parse_items(query: &Element) -> Vec<String> {
+        query
+            .children()
+            .filter(|item| item.is("item", ns::DISCO_ITEMS))
+            .filter_map(|item| {
+                let node = item.attr("node")?;
+                let item = match item.attr("name") {
+                    Some(name) => format!("{node} ({name})"),
+                    None => node.to_string(),
+                };
+                Some(terminus::clean_str(&item))
+            })
+            .collect()
+    }
+
+    fn run(&mut self, aparte: &Aparte, account: &Account, jid: Jid, node: String) {
+        let window = command_window(&jid, &node);
+        let session = Session {
+            account: account.clone(),
+            jid,
+            node,
+            id: None,
+            actions: vec![],
+            default_action: None,
+            shown: false,
+        };
+        Self::send_action(aparte, &window, &session, "execute", None);
+        self.sessions.insert(window, session);
+    }
+
+    /// Take a given action on the command of a window, with the form filled in it
+    fn proceed(aparte: &mut Aparte, window: &str, action: &str) -> Result<()> {
+        let form = match action {
+            "prev" => None,
+            _ => {
+                let form = aparte.get_mod_mut::<UIMod>().get_form(window);
+                let (_, form) = form.context("No form in this window")?;
+                Some(data_form::submit_form(&form))
+            }
+        };
+
+        let adhoc = aparte.get_mod::<AdhocMod>();
+        let session = adhoc
+            .sessions
+            .get(window)
+            .context("No command running in this window")?;
+        if !session.actions.is_empty() && !session.actions.iter().any(|known| known == action) {
+            bail!("Can't {action} at this stage of the command");
+        }
+        Self::send_action(aparte, window, session, action, form);
+        Ok(())
+    }
+
+    fn send_action(
+        aparte: &Aparte,
+        window: &str,
+        session: &Session,
+        action: &str,
+        form: Option<DataForm>,
+    ) {
+        let mut command = Element::builder("command", NS_COMMANDS)
+            .attr("node", session.node.clone())
+            .attr("sessionid", session.id.clone())
+            .attr("action", action)
+            .build();
+        if let Some(form) = form {
+            command.append_child(form.into());
+        }
+        let iq = Self::iq(&session.jid, IqType::Set(command));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = session.account.clone();
+            let window = window.to_string();
+            async move {
+                let result = match Self::send_iq(&mut aparte, &account, iq).await {
+                    Ok(Some(command)) => Ok(command),
+                    Ok(None) => Err(String::from("empty response")),
+                    Err(err) => Err(format!("{err:#}")),
+                };
+                aparte.schedule(Event::Adhoc(AdhocEvent::Response { window, result }));
+            }
+        });
+    }
+
+    async fn send_iq(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        iq: Iq,
+    ) -> Result<Option<Element>> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(payload) => Ok(payload),
+            IqType::Error(err) => {
+                // The error text comes from the entity, like the notes
+                let (_, text) = i18n::xmpp_err_to_string(&err, aparte.config.prefered_langs());
+                Err(anyhow!("{}", terminus::clean_str(&text)))
+            }
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    fn handle_response(
+        &mut self,
+        aparte: &mut Aparte,
+        window: &str,
+        result: &Result<Element, String>,
+    ) {
+        let Some(session) = self.sessions.get(window) else {
+            return;
+        };
+        let name = format!("{} on {}", session.node, session.jid);
+        let response = match result {
+            Ok(command) => Response::parse(command).map_err(|err| format!("{err:#}")),
+            Err(err) => Err(err.clone()),
+        };
+        let (executing, has_form) = match &response {
+            Ok(response) => (
+                response.status == "executing",
+                matches!(&response.form, Some(form) if form.type_ == DataFormType::Form),
+            ),
+            Err(_) => (false, false),
+        };
+
+        if !executing || !has_form {
+            // The command is over, close its window before showing the outcome
+            let mut session = self.sessions.remove(window).unwrap();
+            if session.shown {
+                aparte.schedule(Event::Close(window.to_string()));
+            }
+
+            // Nothing can be filled in to go on, don't leave the session open on the entity
+            if executing {
+                if let Ok(Response {
+                    session_id: Some(id),
+                    ..
+                }) = &response
+                {
+                    session.id = Some(id.clone());
+                }
+                if session.id.is_some() {
+                    Self::send_action(aparte, window, &session, "cancel", None);
+                }
+            }
+        }
+
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                let body = format!("{name} failed: {err}");
+                aparte.schedule(Event::Adhoc(AdhocEvent::Note(body)));
+                return;
+            }
+        };
+
+        for (type_, text) in response.notes.iter() {
+            let body = match type_.as_str() {
+                "warn" => format!("{name}: warning: {text}"),
+                "error" => format!("{name}: error: {text}"),
+                _ => format!("{name}: {text}"),
+            };
+            aparte.schedule(Event::Adhoc(AdhocEvent::Note(body)));
+        }
+
+        match (executing && has_form, response.form) {
+            (true, Some(form)) => {
+                let session = self.sessions.get_mut(window).unwrap();
+                session.id = response.session_id.or(session.id.take());
+                session.actions = response.actions;
+                session.default_action = response.default_action;
+                session.shown = true;
+                aparte.schedule(Event::DataForm {
+                    account: session.account.clone(),
+                    window: window.to_string(),
+                    form,
+                });
+            }
+            (_, form) => {
+                let body = match (response.status.as_str(), form) {
+                    ("canceled", _) => format!("{name} canceled"),
+                    ("executing", _) => format!("{name} canceled: nothing to fill in"),
+                    (_, Some(result)) => {
+                        format!("{name} completed:\n{}", data_form::format_form(&result))
+                    }
+                    (_, None) if response.notes.is_empty() => format!("{name} completed"),
+                    (_, None) => return,
+                };
+                aparte.schedule(Event::Adhoc(AdhocEvent::Note(body)));
+            }
+        }
+    }
+
+    fn handle_submit(&mut self, aparte: &mut Aparte, window: &str, form: &Option<DataForm>) {
+        match form {
+            Some(form) => {
+                if let Some(session) = self.sessions.get_mut(window) {
+                    // Submitting the form closed its window
+                    session.shown = false;
+                    let action = session.default_action.as_deref().unwrap_or("execute");
+                    Self::send_action(aparte, window, session, action, Some(form.clone()));
+                }
+            }
+            None => {
+                if let Some(session) = self.sessions.remove(window) {
+                    if session.id.is_some() {
+                        Self::send_action(aparte, window, &session, "cancel", None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ModTrait for AdhocMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(adhoc::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Adhoc(AdhocEvent::Response { window, result }) => {
+                self.handle_response(aparte, window, result)
+            }
+            Event::DataFormSubmit { window, form, .. } => self.handle_submit(aparte, window, form),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for AdhocMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0050: Ad-Hoc Commands")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        // Given
+        let command: Element = r#"<command xmlns="http://jabber.org/protocol/commands" node="http://jabber.org/protocol/admin#announce" sessionid="session" status="executing">
+            <actions execute="next"><prev/><next/></actions>
+            <note type="warn">Announcing to all users</note>
+            <x xmlns="jabber:x:data" type="form">
+                <field var="body" type="text-multi" label="Announcement"/>
+            </x>
+        </command>"#
+            .parse()
+            .unwrap();
+
+        // When
+        let response = Response::parse(&command).unwrap();
+
+        // Then
+        assert_eq!(response.session_id, Some(String::from("session")));
+        assert_eq!(response.status, "executing");
+        assert_eq!(
+            response.actions,
+            vec![String::from("prev"), String::from("next")]
+        );
+        assert_eq!(response.default_action, Some(String::from("next")));
+        assert_eq!(
+            response.notes,
+            vec![(
+                String::from("warn"),
+                String::from("Announcing to all users")
+            )]
+        );
+        assert_eq!(response.form.unwrap().fields[0].var, "body");
+    }
+
+    #[test]
+    fn test_parse_strips_control_sequences() {
+        // Given
+        let command = Element::builder("command", NS_COMMANDS)
+            .attr("status", "completed")
+            .append(
+                Element::builder("note", NS_COMMANDS)
+                    .attr("type", "info")
+                    .append(String::from("Done\u{1b}[2J"))
+                    .build(),
+            )
+            .build();
+        let query = Element::builder("query", ns::DISCO_ITEMS)
+            .append(
+                Element::builder("item", ns::DISCO_ITEMS)
+                    .attr("jid", "fariello.eu")
+                    .attr("node", "ping")
+                    .attr("name", "\u{1b}[31mPing")
+                    .build(),
+            )
+            .build();
+
+        // When
+        let response = Response::parse(&command).unwrap();
+        let items = AdhocMod::parse_items(&query);
+
+        // Then
+        assert_eq!(
+            response.notes,
+            vec![(String::from("info"), String::from("Done"))]
+        );
+        assert_eq!(items, vec![String::from("ping (Ping)")]);
+    }
+}
@@ -0,0 +1,520 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::hints::NoStore;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::{ns, BareJid, Jid};
+
+use crate::account::Account;
+use crate::conversation::{Conversation, Role};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message, XmppMessageType};
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco;
+
+/// Delay without keystroke after which we consider the user stopped typing
+const PAUSED_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay after which a participant still composing is considered paused, in case its client
+/// never tells it stopped
+const COMPOSING_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub enum ChatStatesEvent {
+    /// Content of the input changed in a conversation window
+    Input {
+        account: Account,
+        conversation: BareJid,
+        empty: bool,
+    },
+    Timeout {
+        account: Account,
+        conversation: BareJid,
+        generation: u64,
+    },
+    /// Composing state of a participant expired
+    Expired {
+        account: Account,
+        conversation: BareJid,
+        from: String,
+        generation: u64,
+    },
+}
+
+/// Last chat state sent in a conversation
+struct Outgoing {
+    state: ChatState,
+    type_: MessageType,
+    /// Incremented on each input, allows to discard outdated timeouts
+    generation: u64,
+}
+
+#[derive(Default)]
+pub struct ChatStatesMod {
+    /// Conversations in which chat states have been received, meaning the contact supports
+    /// them or the channel relays them
+    supported: HashSet<(Account, BareJid)>,
+    outgoing: HashMap<(Account, BareJid), Outgoing>,
+    /// Participants currently composing, indexed by account and conversation, with the
+    /// generation of their last composing state, allows to discard outdated expiries
+    composing: HashMap<(Account, BareJid, String), u64>,
+    generation: u64,
+}
+
+impl ChatStatesMod {
+    fn get_chat_state(message: &XmppParsersMessage) -> Option<ChatState> {
+        message
+            .payloads
+            .iter()
+            .find_map(|payload| ChatState::try_from(payload.clone()).ok())
+    }
+
+    /// Conversation and participant name of an incoming message
+    fn get_sender(
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) -> Option<(BareJid, String)> {
+        let from = message.from.as_ref()?;
+        let conversation = from.to_bare();
+        match message.type_ {
+            MessageType::Groupchat => {
+                let nick = from.try_as_full().ok()?.resource().to_string();
+                // Ignore reflection of our own notifications
+                match aparte
+                    .get_mod::<ConversationMod>()
+                    .get(account, &conversation)
+                {
+                    Some(Conversation::Channel(channel)) if channel.nick == nick => None,
+                    _ => Some((conversation, nick)),
+                }
+            }
+            MessageType::Chat | MessageType::Normal => {
+                let name = conversation.to_string();
+                Some((conversation, name))
+            }
+            _ => None,
+        }
+    }
+
+    /// Remember who is composing, returns whether it changed
+    fn update_composing(
+        &mut self,
+        account: &Account,
+        conversation: &BareJid,
+        from: &str,
+        state: &ChatState,
+    ) -> bool {
+        let key = (account.clone(), conversation.clone(), from.to_string());
+        match state {
+            ChatState::Composing => {
+                self.generation += 1;
+                self.composing.insert(key, self.generation).is_none()
+            }
+            _ => self.composing.remove(&key).is_some(),
+        }
+    }
+
+    fn handle_chat_state(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        conversation: BareJid,
+        from: String,
+        state: ChatState,
+    ) {
+        let changed = self.update_composing(account, &conversation, &from, &state);
+        if state == ChatState::Composing {
+            Aparte::spawn({
+                let mut aparte = aparte.proxy();
+                let account = account.clone();
+                let conversation = conversation.clone();
+                let from = from.clone();
+                let generation = self.generation;
+                async move {
+                    tokio::time::sleep(COMPOSING_TIMEOUT).await;
+                    aparte.schedule(Event::ChatStates(ChatStatesEvent::Expired {
+                        account,
+                        conversation,
+                        from,
+                        generation,
+                    }));
+                }
+            });
+        }
+
+        if changed {
+            aparte.schedule(Event::ChatStateChanged {
+                account: account.clone(),
+                conversation,
+                from,
+                state,
+            });
+        }
+    }
+
+    /// Whether standalone notifications can be sent in the given conversation
+    fn can_send(&self, aparte: &mut Aparte, account: &Account, conversation: &BareJid) -> bool {
+        if !aparte.config.chat_states
+            || !self
+                .supported
+                .contains(&(account.clone(), conversation.clone()))
+        {
+            return false;
+        }
+
+        match aparte
+            .get_mod::<ConversationMod>()
+            .get(account, conversation)
+        {
+            Some(Conversation::Chat(_)) => true,
+            Some(Conversation::Channel(channel)) => channel
+                .occupants
+                .get(&channel.nick)
+                .map(|occupant| occupant.role != Role::Visitor)
+                .unwrap_or(false),
+            None => false,
+        }
+    }
+
+    fn send(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        conversation: &BareJid,
+        state: ChatState,
+    ) {
+        let type_ = match self.outgoing.get(&(account.clone(), conversation.clone())) {
+            Some(outgoing) => outgoing.type_.clone(),
+            None => match aparte
+                .get_mod::<ConversationMod>()
+                .get(account, conversation)
+            {
+                Some(Conversation::Channel(_)) => MessageType::Groupchat,
+                _ => MessageType::Chat,
+            },
+        };
+
+        let mut message = XmppParsersMessage::new(Some(Jid::from(conversation.clone())));
+        message.type_ = type_.clone();
+        message.payloads.push(state.clone().into());
+        message.payloads.push(NoStore.into());
+        aparte.send(account, message);
+
+        let outgoing = self
+            .outgoing
+            .entry((account.clone(), conversation.clone()))
+            .or_insert(Outgoing {
+                state: ChatState::Active,
+                type_,
+                generation: 0,
+            });
+        outgoing.state = state;
+    }
+
+    fn handle_input(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        conversation: &BareJid,
+        empty: bool,
+    ) {
+        if !self.can_send(aparte, account, conversation) {
+            return;
+        }
+
+        let key = (account.clone(), conversation.clone());
+        let state = self
+            .outgoing
+            .get(&key)
+            .map(|outgoing| outgoing.state.clone());
+        if empty {
+            if matches!(state, Some(ChatState::Composing) | Some(ChatState::Paused)) {
+                self.send(aparte, account, conversation, ChatState::Active);
+            }
+        } else {
+            if state != Some(ChatState::Composing) {
+                self.send(aparte, account, conversation, ChatState::Composing);
+            }
+
+            let outgoing = self.outgoing.get_mut(&key).unwrap();
+            outgoing.generation += 1;
+
+            Aparte::spawn({
+                let mut aparte = aparte.proxy();
+                let account = account.clone();
+                let conversation = conversation.clone();
+                let generation = outgoing.generation;
+                async move {
+                    tokio::time::sleep(PAUSED_TIMEOUT).await;
+                    aparte.schedule(Event::ChatStates(ChatStatesEvent::Timeout {
+                        account,
+                        conversation,
+                        generation,
+                    }));
+                }
+            });
+        }
+    }
+
+    fn handle_timeout(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        conversation: &BareJid,
+        generation: u64,
+    ) {
+        match self.outgoing.get(&(account.clone(), conversation.clone())) {
+            Some(outgoing)
+                if outgoing.generation == generation && outgoing.state == ChatState::Composing =>
+            {
+                self.send(aparte, account, conversation, ChatState::Paused);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ModTrait for ChatStatesMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(ns::CHATSTATES);
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        if Self::get_chat_state(message).is_none() {
+            return 0f64;
+        }
+
+        // Every received message goes through here, including the ones handled by
+        // MessagesMod, which is the only way to learn that a contact supports chat states
+        if let Some(from) = &message.from {
+            if let Ok(Direction::Incoming) = Message::get_direction_from_xmpp(account, message) {
+                self.supported.insert((account.clone(), from.to_bare()));
+            }
+        }
+
+        if message.bodies.is_empty() {
+            0.1f64
+        } else {
+            0f64
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+        archive: bool,
+    ) {
+        if archive || delay.is_some() {
+            return;
+        }
+
+        match Message::get_direction_from_xmpp(account, message) {
+            Ok(Direction::Incoming) => {}
+            _ => return,
+        }
+
+        if let (Some(state), Some((conversation, from))) = (
+            Self::get_chat_state(message),
+            Self::get_sender(aparte, account, message),
+        ) {
+            self.handle_chat_state(aparte, account, conversation, from, state);
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::ChatStates(ChatStatesEvent::Input {
+                account,
+                conversation,
+                empty,
+            }) => self.handle_input(aparte, account, conversation, *empty),
+            Event::ChatStates(ChatStatesEvent::Timeout {
+                account,
+                conversation,
+                generation,
+            }) => self.handle_timeout(aparte, account, conversation, *generation),
+            Event::ChatStates(ChatStatesEvent::Expired {
+                account,
+                conversation,
+                from,
+                generation,
+            }) => {
+                let key = (account.clone(), conversation.clone(), from.clone());
+                if self.composing.get(&key) == Some(generation) {
+                    self.handle_chat_state(
+                        aparte,
+                        account,
+                        conversation.clone(),
+                        from.clone(),
+                        ChatState::Paused,
+                    );
+                }
+            }
+            Event::SendMessage(account, Message::Xmpp(message)) => {
+                // Sent messages carry the active state
+                if let Some(outgoing) = self
+                    .outgoing
+                    .get_mut(&(account.clone(), message.to.clone()))
+                {
+                    outgoing.state = ChatState::Active;
+                    outgoing.generation += 1;
+                }
+            }
+            Event::Message(Some(account), Message::Xmpp(message))
+                if message.direction == Direction::Incoming && !message.archive =>
+            {
+                // A received message means its author is done composing
+                let from = match message.type_ {
+                    XmppMessageType::Chat => message.from.to_string(),
+                    XmppMessageType::Channel => match message.from_full.try_as_full() {
+                        Ok(full) => full.resource().to_string(),
+                        Err(_) => return,
+                    },
+                };
+                if self.composing.contains_key(&(
+                    account.clone(),
+                    message.from.clone(),
+                    from.clone(),
+                )) {
+                    self.handle_chat_state(
+                        aparte,
+                        account,
+                        message.from.clone(),
+                        from,
+                        ChatState::Active,
+                    );
+                }
+            }
+            Event::Close(window) => {
+                let Ok(conversation) = BareJid::from_str(window) else {
+                    return;
+                };
+                let closed = self
+                    .outgoing
+                    .iter()
+                    .filter(|((_, jid), outgoing)| {
+                        jid == &conversation && outgoing.type_ == MessageType::Chat
+                    })
+                    .map(|((account, _), _)| account.clone())
+                    .collect::<Vec<_>>();
+                for account in closed {
+                    if self.can_send(aparte, &account, &conversation) {
+                        self.send(aparte, &account, &conversation, ChatState::Gone);
+                    }
+                    self.outgoing.remove(&(account, conversation.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ChatStatesMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0085: Chat State Notifications")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use xmpp_parsers::Element;
+
+    use super::*;
+
+    #[test]
+    fn test_get_chat_state() {
+        // Given
+        let composing: Element =
+            r#"<message xmlns="jabber:client" from="contact@example.org/phone" type="chat">
+            <composing xmlns="http://jabber.org/protocol/chatstates"/>
+        </message>"#
+                .parse()
+                .unwrap();
+        let body: Element =
+            r#"<message xmlns="jabber:client" from="contact@example.org/phone" type="chat">
+            <body>Hello</body>
+        </message>"#
+                .parse()
+                .unwrap();
+
+        // When
+        let composing =
+            ChatStatesMod::get_chat_state(&XmppParsersMessage::try_from(composing).unwrap());
+        let body = ChatStatesMod::get_chat_state(&XmppParsersMessage::try_from(body).unwrap());
+
+        // Then
+        assert_eq!(composing, Some(ChatState::Composing));
+        assert_eq!(body, None);
+    }
+
+    #[test]
+    fn test_update_composing() {
+        // Given
+        let mut chatstates = ChatStatesMod::default();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let room = BareJid::from_str("room@conference.example.org").unwrap();
+
+        // When
+        let composing = chatstates.update_composing(&account, &room, "paul", &ChatState::Composing);
+        let composing_again =
+            chatstates.update_composing(&account, &room, "paul", &ChatState::Composing);
+        let other = chatstates.update_composing(&account, &room, "john", &ChatState::Paused);
+        let paused = chatstates.update_composing(&account, &room, "paul", &ChatState::Paused);
+
+        // Then
+        assert!(composing);
+        assert!(!composing_again);
+        assert!(!other);
+        assert!(paused);
+        assert!(chatstates.composing.is_empty());
+    }
+
+    #[test]
+    fn test_composing_refreshes_expiry() {
+        // Given
+        let mut chatstates = ChatStatesMod::default();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let contact = BareJid::from_str("contact@example.org").unwrap();
+        let key = (account.clone(), contact.clone(), contact.to_string());
+        chatstates.update_composing(
+            &account,
+            &contact,
+            &contact.to_string(),
+            &ChatState::Composing,
+        );
+        let first = chatstates.composing[&key];
+
+        // When
+        chatstates.update_composing(
+            &account,
+            &contact,
+            &contact.to_string(),
+            &ChatState::Composing,
+        );
+
+        // Then
+        assert_ne!(chatstates.composing[&key], first);
+    }
+}
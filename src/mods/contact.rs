@@ -49,6 +49,13 @@ pub struct ContactMod {
 }
 
 impl ContactMod {
+    pub fn get(&self, account: &Account, jid: &BareJid) -> Option<&contact::Contact> {
+        self.contacts.get(&ContactIndex {
+            account: account.clone(),
+            jid: jid.clone(),
+        })
+    }
+
     async fn get_roster(aparte: &mut AparteAsync, account: &Account) -> Result<()> {
         let response = aparte.iq(account, Self::get_roster_iq()).await?;
 
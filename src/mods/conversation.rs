@@ -4,16 +4,63 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
+use anyhow::{bail, Context};
 use unicode_segmentation::UnicodeSegmentation as _;
 
+use xmpp_parsers::muc::user::{Actor, MucUser, Status};
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+use xmpp_parsers::stanza_error::{DefinedCondition, StanzaError};
 use xmpp_parsers::{muc, BareJid, Jid};
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
 use crate::conversation;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
 use crate::message;
 
+command_def!(nick,
+r#"/nick <nick>
+
+    nick          New nick
+
+Description:
+    Change your nick in the current channel.
+
+Example:
+    /nick needle"#,
+{
+    nick: String,
+},
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .context("Can't change nick in non XMPP window")?;
+    let jid = BareJid::from_str(&command.context)
+        .context("Can't change nick in non XMPP window")?;
+    match aparte.get_mod::<ConversationMod>().get(&account, &jid) {
+        Some(conversation::Conversation::Channel(channel)) if channel.nick == nick => {
+            bail!("You are already known as {nick}")
+        }
+        Some(conversation::Conversation::Channel(_)) => {}
+        _ => bail!("Nick can only be changed in channels"),
+    }
+
+    let to = jid.with_resource_str(&nick).context("Invalid nick")?;
+    aparte.get_mod_mut::<ConversationMod>().nick_changes.insert(
+        ConversationIndex {
+            account: account.clone(),
+            jid,
+        },
+        nick,
+    );
+    aparte.send(&account, Presence::new(PresenceType::None).with_to(Jid::from(to)));
+    Ok(())
+});
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct ConversationIndex {
     account: Account,
@@ -24,6 +71,8 @@ struct ConversationIndex {
 pub struct ConversationMod {
     /// Collections of currently opened conversations.
     conversations: HashMap<ConversationIndex, conversation::Conversation>,
+    /// Nick we asked for in channels, until the room accepts or rejects it
+    nick_changes: HashMap<ConversationIndex, String>,
 }
 
 impl ConversationMod {
@@ -38,6 +87,245 @@ impl ConversationMod {
         };
         self.conversations.get(&index)
     }
+
+    /// New nick announced by a status code 303 presence, and whether it is ours
+    fn get_nick_change(muc_user: &MucUser) -> Option<(String, bool)> {
+        if !muc_user
+            .status
+            .iter()
+            .any(|status| matches!(status, Status::NewNick))
+        {
+            return None;
+        }
+
+        let nick = muc_user.items.iter().find_map(|item| item.nick.clone())?;
+        let ours = muc_user
+            .status
+            .iter()
+            .any(|status| matches!(status, Status::SelfPresence));
+        Some((nick, ours))
+    }
+
+    /// Reason of the removal of an occupant from a channel, None if it simply left
+    fn get_removal(muc_user: &MucUser) -> Option<String> {
+        let mut removal = if muc_user
+            .status
+            .iter()
+            .any(|status| matches!(status, Status::Kicked))
+        {
+            String::from("kicked")
+        } else if muc_user
+            .status
+            .iter()
+            .any(|status| matches!(status, Status::Banned))
+        {
+            String::from("banned")
+        } else {
+            return None;
+        };
+
+        if let Some(item) = muc_user.items.first() {
+            match &item.actor {
+                Some(Actor::Nick(nick)) => removal.push_str(&format!(" by {nick}")),
+                Some(Actor::Jid(jid)) => removal.push_str(&format!(" by {jid}")),
+                None => {}
+            }
+            if let Some(reason) = &item.reason {
+                removal.push_str(&format!(" ({})", reason.0));
+            }
+        }
+
+        Some(removal)
+    }
+
+    /// Log lines quote remote nicks, statuses and reasons, strip any terminal sequence from them
+    fn channel_log(aparte: &mut Aparte, index: &ConversationIndex, body: String) {
+        aparte.schedule(Event::ChannelLog {
+            account: index.account.clone(),
+            conversation: index.jid.clone(),
+            body: terminus::clean_str(&body),
+        });
+    }
+
+    fn handle_occupant_presence(
+        &mut self,
+        aparte: &mut Aparte,
+        index: &ConversationIndex,
+        nick: String,
+        presence: &Presence,
+    ) {
+        let Some(conversation::Conversation::Channel(channel)) = self.conversations.get_mut(index)
+        else {
+            return;
+        };
+        let Some(muc_user) = presence
+            .payloads
+            .iter()
+            .find_map(|payload| MucUser::try_from(payload.clone()).ok())
+        else {
+            return;
+        };
+        let Some(item) = muc_user.items.first() else {
+            return;
+        };
+
+        let occupant = conversation::Occupant {
+            nick: nick.clone(),
+            jid: item.jid.as_ref().map(|full| full.to_bare()),
+            affiliation: item.affiliation.clone().into(),
+            role: item.role.clone().into(),
+        };
+        let ours = nick == channel.nick
+            || muc_user
+                .status
+                .iter()
+                .any(|status| matches!(status, Status::SelfPresence));
+        let verbose = aparte.config.channel_events;
+
+        if presence.type_ == PresenceType::Unavailable {
+            let occupant = channel.occupants.remove(&nick).unwrap_or(occupant);
+            if let Some((new_nick, _)) = Self::get_nick_change(&muc_user) {
+                // The occupant comes back with an available presence under its new nick, which
+                // must not be announced as a join
+                channel.occupants.insert(
+                    new_nick.clone(),
+                    conversation::Occupant {
+                        nick: new_nick.clone(),
+                        ..occupant.clone()
+                    },
+                );
+                if ours {
+                    channel.nick = new_nick.clone();
+                    self.nick_changes.remove(index);
+                    Self::channel_log(aparte, index, format!("You are now known as {new_nick}"));
+                } else if verbose {
+                    Self::channel_log(aparte, index, format!("{nick} is now known as {new_nick}"));
+                }
+                aparte.schedule(Event::NickChanged {
+                    account: index.account.clone(),
+                    conversation: index.jid.clone(),
+                    occupant,
+                    nick: new_nick,
+                });
+                return;
+            }
+
+            match (Self::get_removal(&muc_user), ours) {
+                (Some(removal), true) => {
+                    Self::channel_log(aparte, index, format!("You were {removal}"))
+                }
+                (Some(removal), false) if verbose => {
+                    Self::channel_log(aparte, index, format!("{nick} was {removal}"))
+                }
+                (None, false) if verbose => {
+                    let status = i18n::get_best(&presence.statuses, aparte.config.prefered_langs())
+                        .map(|(_, status)| format!(" ({status})"))
+                        .unwrap_or_default();
+                    Self::channel_log(aparte, index, format!("{nick} has left{status}"));
+                }
+                _ => {}
+            }
+            aparte.schedule(Event::OccupantLeft {
+                account: index.account.clone(),
+                conversation: index.jid.clone(),
+                occupant,
+            });
+            return;
+        }
+
+        // The channel can enforce another nick than the one we asked for when joining
+        if ours
+            && nick != channel.nick
+            && muc_user
+                .status
+                .iter()
+                .any(|status| matches!(status, Status::AssignedNick))
+        {
+            aparte.schedule(Event::NickChanged {
+                account: index.account.clone(),
+                conversation: index.jid.clone(),
+                occupant: conversation::Occupant {
+                    nick: channel.nick.clone(),
+                    ..occupant.clone()
+                },
+                nick: nick.clone(),
+            });
+            channel.nick = nick.clone();
+            Self::channel_log(
+                aparte,
+                index,
+                format!("The channel changed your nick to {nick}"),
+            );
+        }
+
+        // Occupants already there are sent before our own presence, don't announce them
+        let joined = channel.occupants.contains_key(&channel.nick);
+        match channel.occupants.get(&nick) {
+            None if joined && verbose => {
+                Self::channel_log(aparte, index, format!("{nick} has joined"));
+            }
+            Some(known) if known.role != occupant.role && (ours || verbose) => {
+                let who = match ours {
+                    true => String::from("You are"),
+                    false => format!("{nick} is"),
+                };
+                let line = format!("{who} now {}", occupant.role.as_str());
+                Self::channel_log(aparte, index, line);
+            }
+            Some(known) if known.affiliation != occupant.affiliation && (ours || verbose) => {
+                let who = match ours {
+                    true => String::from("You are"),
+                    false => format!("{nick} is"),
+                };
+                let line = match occupant.affiliation {
+                    conversation::Affiliation::None => {
+                        format!("{who} no longer {}", known.affiliation.as_str())
+                    }
+                    affiliation => format!("{who} now {}", affiliation.as_str()),
+                };
+                Self::channel_log(aparte, index, line);
+            }
+            _ => {}
+        }
+
+        aparte.schedule(Event::Occupant {
+            account: index.account.clone(),
+            conversation: index.jid.clone(),
+            occupant: occupant.clone(),
+        });
+        channel.occupants.insert(nick, occupant);
+    }
+
+    fn handle_presence_error(
+        &mut self,
+        aparte: &mut Aparte,
+        index: &ConversationIndex,
+        nick: &str,
+        presence: &Presence,
+    ) {
+        match self.nick_changes.get(index) {
+            Some(requested) if requested == nick => {
+                self.nick_changes.remove(index);
+            }
+            _ => return,
+        }
+
+        let error = presence
+            .payloads
+            .iter()
+            .find_map(|payload| StanzaError::try_from(payload.clone()).ok());
+        match error {
+            Some(error) if error.defined_condition == DefinedCondition::Conflict => {
+                aparte.log(format!("Nick {nick} is already used in {}", index.jid));
+            }
+            Some(error) => aparte.log(format!(
+                "Can't change nick to {nick} in {}: {}",
+                index.jid,
+                i18n::xmpp_err_to_string(&error, vec![]).1
+            )),
+            None => aparte.log(format!("Can't change nick to {nick} in {}", index.jid)),
+        }
+    }
 }
 
 impl From<muc::user::Role> for conversation::Role {
@@ -64,7 +352,8 @@ impl From<muc::user::Affiliation> for conversation::Affiliation {
 }
 
 impl ModTrait for ConversationMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(nick::new());
         Ok(())
     }
 
@@ -110,7 +399,7 @@ impl ModTrait for ConversationMod {
                             conversation::Conversation::Channel(channel) => {
                                 // Look for mentions
                                 let mut mention = false;
-                                let body = message.get_last_body();
+                                let body = message.get_last_body(aparte.config.prefered_langs());
                                 for word in body.split_word_bounds() {
                                     if channel.nick == word {
                                         mention = true;
@@ -150,28 +439,11 @@ impl ModTrait for ConversationMod {
                         account: account.clone(),
                         jid: from.to_bare(),
                     };
-                    if let Some(conversation::Conversation::Channel(channel)) =
-                        self.conversations.get_mut(&index)
-                    {
-                        for payload in presence.clone().payloads {
-                            if let Ok(muc_user) = muc::user::MucUser::try_from(payload) {
-                                for item in muc_user.items {
-                                    let occupant_jid = item.jid.map(|full| full.to_bare());
-                                    let occupant = conversation::Occupant {
-                                        nick: from.resource().to_string(),
-                                        jid: occupant_jid,
-                                        affiliation: item.affiliation.into(),
-                                        role: item.role.into(),
-                                    };
-                                    aparte.schedule(Event::Occupant {
-                                        account: index.account.clone(),
-                                        conversation: index.jid.clone(),
-                                        occupant: occupant.clone(),
-                                    });
-                                    channel.occupants.insert(occupant.nick.clone(), occupant);
-                                }
-                            }
-                        }
+                    let nick = from.resource().to_string();
+                    if presence.type_ == PresenceType::Error {
+                        self.handle_presence_error(aparte, &index, &nick, presence);
+                    } else {
+                        self.handle_occupant_presence(aparte, &index, nick, presence);
                     }
                 }
             }
@@ -215,3 +487,55 @@ impl fmt::Display for ConversationMod {
         write!(f, "Conversations management")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::Element;
+
+    #[test]
+    fn test_nick_change() {
+        // Given
+        let presence: Element = r#"<presence xmlns="jabber:client" from="aparte@conference.fariello.eu/paul" type="unavailable">
+            <x xmlns="http://jabber.org/protocol/muc#user">
+                <item affiliation="member" nick="needle" role="participant"/>
+                <status code="303"/>
+                <status code="110"/>
+            </x>
+        </presence>"#
+            .parse()
+            .unwrap();
+        let presence = Presence::try_from(presence).unwrap();
+        let muc_user = MucUser::try_from(presence.payloads[0].clone()).unwrap();
+
+        // When
+        let nick_change = ConversationMod::get_nick_change(&muc_user);
+
+        // Then
+        assert_eq!(nick_change, Some(("needle".to_string(), true)));
+    }
+
+    #[test]
+    fn test_kick_removal() {
+        // Given
+        let presence: Element = r#"<presence xmlns="jabber:client" from="aparte@conference.fariello.eu/bob" type="unavailable">
+            <x xmlns="http://jabber.org/protocol/muc#user">
+                <item affiliation="none" role="none">
+                    <actor nick="alice"/>
+                    <reason>Spam</reason>
+                </item>
+                <status code="307"/>
+            </x>
+        </presence>"#
+            .parse()
+            .unwrap();
+        let presence = Presence::try_from(presence).unwrap();
+        let muc_user = MucUser::try_from(presence.payloads[0].clone()).unwrap();
+
+        // When
+        let removal = ConversationMod::get_removal(&muc_user);
+
+        // Then
+        assert_eq!(removal, Some("kicked by alice (Spam)".to_string()));
+    }
+}
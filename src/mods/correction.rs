@@ -5,23 +5,309 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 
+use anyhow::{bail, Result};
+use termion::{color, style};
+use unicode_segmentation::UnicodeSegmentation as _;
+use uuid::Uuid;
 use xmpp_parsers::delay::Delay;
-use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::message::{Body, Message as XmppParsersMessage};
 use xmpp_parsers::message_correct::Replace;
 use xmpp_parsers::ns;
+use xmpp_parsers::BareJid;
 
 use crate::account::Account;
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message::Message;
+use crate::message::{DeliveryState, Message};
 use crate::mods::disco;
 use crate::mods::messages;
 
+mod correct {
+    use std::str::FromStr;
+
+    use anyhow::{bail, Context, Result};
+    use xmpp_parsers::BareJid;
+
+    use crate::account::Account;
+    use crate::command::{Command, CommandParser};
+    use crate::core::{Aparte, Event};
+    use crate::mods::messages::MessagesMod;
+
+    fn parse(account: &Option<Account>, context: &str, buf: &str) -> Result<Command> {
+        let body = buf.strip_prefix("/correct").unwrap_or(buf).trim_start();
+        Ok(Command {
+            account: account.clone(),
+            context: context.to_string(),
+            args: vec![body.to_string()],
+            cursor: 0,
+        })
+    }
+
+    fn exec(aparte: &mut Aparte, command: Command) -> Result<()> {
+        let account = command
+            .account
+            .context("Can't use /correct in non XMPP window")?;
+        let peer =
+            BareJid::from_str(&command.context).context("Can't use /correct in non XMPP window")?;
+        let body = command.args[0].clone();
+        if body.is_empty() {
+            bail!("Missing corrected message");
+        }
+
+        let id = aparte
+            .get_mod::<MessagesMod>()
+            .get_last_outgoing(&account, &peer)
+            .map(|message| message.id.clone())
+            .context("No message to correct")?;
+        aparte.schedule(Event::SendCorrection {
+            account,
+            peer,
+            id,
+            body,
+        });
+        Ok(())
+    }
+
+    pub fn new() -> CommandParser {
+        CommandParser {
+            name: "correct",
+            help: r#"/correct <message>
+
+    message       Corrected message
+
+Description:
+    Replace the last message sent in the current conversation.
+    Pressing Up with an empty input loads it for edition too.
+
+Example:
+    /correct Hello world"#
+                .to_string(),
+            parse,
+            exec,
+            autocompletions: vec![],
+        }
+    }
+}
+
+mod history {
+    use std::str::FromStr;
+
+    use anyhow::{bail, Context, Result};
+    use chrono::Local;
+    use xmpp_parsers::BareJid;
+
+    use crate::account::Account;
+    use crate::command::{Command, CommandParser};
+    use crate::core::Aparte;
+    use crate::i18n;
+    use crate::message::Message;
+    use crate::mods::messages::MessagesMod;
+    use crate::mods::ui::UIMod;
+
+    fn parse(account: &Option<Account>, context: &str, buf: &str) -> Result<Command> {
+        let id = buf.strip_prefix("/history").unwrap_or(buf).trim();
+        Ok(Command {
+            account: account.clone(),
+            context: context.to_string(),
+            args: vec![id.to_string()],
+            cursor: 0,
+        })
+    }
+
+    fn exec(aparte: &mut Aparte, command: Command) -> Result<()> {
+        let account = command
+            .account
+            .context("Can't use /history in non XMPP window")?;
+        let id = &command.args[0];
+
+        let message = match id.is_empty() {
+            true => aparte
+                .get_mod_mut::<UIMod>()
+                .get_selection()
+                .context("No message selected, select one with Ctrl+Up/Ctrl+Down")?,
+            false => {
+                let peer = BareJid::from_str(&command.context)
+                    .context("Can't use /history in non XMPP window")?;
+                aparte
+                    .get_mod::<MessagesMod>()
+                    .find(&account, &peer, id)
+                    .cloned()
+                    .with_context(|| format!("Unknown message {id}"))?
+            }
+        };
+        let Message::Xmpp(message) = message else {
+            bail!("Only XMPP messages have an history");
+        };
+        if message.retraction.is_some() {
+            bail!("Message has been retracted");
+        }
+
+        let mut versions = message.history.iter().collect::<Vec<_>>();
+        versions.sort();
+
+        let mut output = format!(
+            "History of {}:",
+            terminus::clean_str(&message.get_preview(aparte.config.prefered_langs()))
+        );
+        let mut previous: Option<String> = None;
+        for version in versions {
+            let body = i18n::get_best(&version.bodies, aparte.config.prefered_langs())
+                .map(|(_, body)| terminus::clean_str(body))
+                .unwrap_or_default();
+            let text = match &previous {
+                Some(previous) => super::format_diff(previous, &body),
+                None => body.clone(),
+            };
+            let timestamp = version.timestamp.with_timezone(&Local).format("%T");
+            output.push_str(&format!("\n{timestamp} {text}"));
+            previous = Some(body);
+        }
+        aparte.log(output);
+        Ok(())
+    }
+
+    pub fn new() -> CommandParser {
+        CommandParser {
+            name: "history",
+            help: r#"/history [<id>]
+
+    id            Id of the message
+
+Description:
+    Show every version of the selected message, or of the message with the
+    given id, along with the words changed by each correction. Select a
+    message with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /history"#
+                .to_string(),
+            parse,
+            exec,
+            autocompletions: vec![],
+        }
+    }
+}
+
+/// Word level change between two versions of a text
+#[derive(Debug, PartialEq)]
+enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Changes turning `old` into `new`, based on the longest common subsequence of their words
+fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let old = old.split_word_bounds().collect::<Vec<_>>();
+    let new = new.split_word_bounds().collect::<Vec<_>>();
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|word| Change::Removed(word)));
+    changes.extend(new[j..].iter().map(|word| Change::Added(word)));
+
+    changes
+}
+
+/// Show `new` with words removed since `old` crossed out in red and added ones in green
+fn format_diff(old: &str, new: &str) -> String {
+    diff_words(old, new)
+        .into_iter()
+        .map(|change| match change {
+            Change::Same(word) => word.to_string(),
+            // Changes in whitespaces can't be seen, removed line breaks would break the layout
+            Change::Removed(word) if word.trim().is_empty() => String::new(),
+            Change::Added(word) if word.trim().is_empty() => word.to_string(),
+            Change::Removed(word) => format!(
+                "{}{}{}{}{}",
+                color::Fg(color::Red),
+                style::CrossedOut,
+                word,
+                style::NoCrossedOut,
+                color::Fg(color::Reset)
+            ),
+            Change::Added(word) => format!(
+                "{}{}{}",
+                color::Fg(color::Green),
+                word,
+                color::Fg(color::Reset)
+            ),
+        })
+        .collect()
+}
+
 #[derive(Default)]
 pub struct CorrectionMod {
     waiting_corrections: HashMap<String, Vec<XmppParsersMessage>>,
 }
 
 impl CorrectionMod {
+    /// Add the corrected version to the message and send it through the outbox like any other
+    fn send_correction(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        peer: &BareJid,
+        id: &str,
+        body: &str,
+    ) -> Result<()> {
+        let mut corrected =
+            match aparte
+                .get_mod::<messages::MessagesMod>()
+                .get(&Some(account.clone()), peer, id)
+            {
+                Some(Message::Xmpp(original)) => original.clone(),
+                _ => bail!("Unknown message {id}"),
+            };
+
+        if corrected.retraction.is_some() {
+            bail!("Message has been retracted");
+        }
+        // The outbox only sends the last version, the original would never be sent
+        if corrected.delivery == Some(DeliveryState::Pending) {
+            bail!("Message hasn't been sent yet");
+        }
+        if aparte.has_crypto_engine(account, &corrected.to) {
+            bail!("Can't correct encrypted messages");
+        }
+
+        let mut correction = XmppParsersMessage::new(None);
+        correction.id = Some(Uuid::new_v4().to_string());
+        correction
+            .bodies
+            .insert(String::new(), Body(body.to_string()));
+        corrected.add_version_from_xmpp(&correction);
+
+        aparte.schedule(Event::SendMessage(
+            account.clone(),
+            Message::Xmpp(corrected),
+        ));
+        Ok(())
+    }
+
     fn handle_replace(
         &mut self,
         aparte: &mut Aparte,
@@ -30,9 +316,16 @@ impl CorrectionMod {
         replace: Replace,
         archive: bool,
     ) {
+        let peer: BareJid = match Message::get_local_destination_from_xmpp(account, message) {
+            Ok(peer) => peer.to_bare(),
+            Err(err) => {
+                log::warn!("Cannot handle correction of {}: {}", replace.id, err);
+                return;
+            }
+        };
         let event = {
             let mut messages = aparte.get_mod_mut::<messages::MessagesMod>();
-            if let Some(original) = messages.get_mut(&Some(account.clone()), &replace.id) {
+            if let Some(original) = messages.find_mut(account, &peer, &replace.id) {
                 match original {
                     Message::Xmpp(original) => {
                         original.add_version_from_xmpp(message);
@@ -98,6 +391,9 @@ impl CorrectionMod {
 
 impl ModTrait for CorrectionMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(correct::new());
+        aparte.add_command(history::new());
+
         let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
         disco.add_feature(ns::MESSAGE_CORRECT);
 
@@ -148,18 +444,30 @@ impl ModTrait for CorrectionMod {
     }
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
-        if let Event::RawMessage {
-            account,
-            message,
-            delay: _,
-            archive,
-        } = event
-        {
-            for payload in message.payloads.iter() {
-                if let Ok(replace) = Replace::try_from(payload.clone()) {
-                    self.handle_replace(aparte, account, message, replace, *archive);
+        match event {
+            Event::RawMessage {
+                account,
+                message,
+                delay: _,
+                archive,
+            } => {
+                for payload in message.payloads.iter() {
+                    if let Ok(replace) = Replace::try_from(payload.clone()) {
+                        self.handle_replace(aparte, account, message, replace, *archive);
+                    }
+                }
+            }
+            Event::SendCorrection {
+                account,
+                peer,
+                id,
+                body,
+            } => {
+                if let Err(err) = self.send_correction(aparte, account, peer, id, body) {
+                    crate::error!(aparte, err, "Cannot correct message");
                 }
             }
+            _ => {}
         }
     }
 }
@@ -169,3 +477,39 @@ impl fmt::Display for CorrectionMod {
         write!(f, "XEP-0280: Message Correction")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_words() {
+        // Given
+        let old = "Hello wrold, how are you?";
+        let new = "Hello world, how are you doing?";
+
+        // When
+        let changes = diff_words(old, new);
+
+        // Then
+        assert_eq!(
+            changes,
+            vec![
+                Change::Same("Hello"),
+                Change::Same(" "),
+                Change::Removed("wrold"),
+                Change::Added("world"),
+                Change::Same(","),
+                Change::Same(" "),
+                Change::Same("how"),
+                Change::Same(" "),
+                Change::Same("are"),
+                Change::Same(" "),
+                Change::Same("you"),
+                Change::Added(" "),
+                Change::Added("doing"),
+                Change::Same("?"),
+            ]
+        );
+    }
+}
@@ -1,67 +1,227 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use anyhow::Context;
 use chrono::{DateTime, FixedOffset};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::iq::{Iq, IqType};
-use xmpp_parsers::mam;
+use xmpp_parsers::mam as xmpp_mam;
 use xmpp_parsers::message::Message as XmppParsersMessage;
 use xmpp_parsers::ns;
 use xmpp_parsers::rsm::SetQuery;
+use xmpp_parsers::stanza_id::StanzaId;
 use xmpp_parsers::{BareJid, Jid};
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::Conversation;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
+use crate::message::{Direction, Message, XmppMessageType};
+use crate::mods::conversation::ConversationMod;
+
+/// Full text search fields known to be supported by archives, by order of preference
+const FULL_TEXT_SEARCH_FIELDS: [&str; 3] = [
+    "{urn:xmpp:fulltext:0}fulltext",
+    "{urn:xmpp:mam:2}full-text-search",
+    "withtext",
+];
+
+/// Maximum number of matches retrieved by an archive search
+const SEARCH_MAX_RESULTS: usize = 100;
+
+/// Number of messages retrieved when opening a conversation
+const HISTORY_COUNT: usize = 100;
+
+/// Page size used when catching up with an archive after a reconnection
+const CATCH_UP_PAGE_SIZE: usize = 100;
+
+command_def!(mam_search,
+r#"/mam search <text> [since=<date>]
+
+    text    Text to look for
+    since   Only search messages archived after the given RFC 3339 date
+
+Description:
+    Search the server side archive of the current conversation.
+    Matches are shown in the search window.
+
+Examples:
+    /mam search release
+    /mam search "next release"
+    /mam search release since=2024-01-01T00:00:00Z
+"#,
+{
+    since: Named<DateTime<FixedOffset>>,
+    text: String
+},
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .context("Can't search archive in non XMPP window")?;
+    let jid = BareJid::from_str(&command.context)
+        .context("Can't search archive in non XMPP window")?;
+    let conversation = aparte
+        .get_mod::<ConversationMod>()
+        .get(&account, &jid)
+        .cloned()
+        .context("Unknown conversation")?;
+    let query = match conversation {
+        Conversation::Chat(chat) => {
+            Query::search(account.to_bare(), Some(chat.contact), text, since)
+        }
+        Conversation::Channel(channel) => Query::search(channel.jid, None, text, since),
+    };
+
+    let mut mam = aparte.get_mod_mut::<MamMod>();
+    mam.search(aparte, &account, query);
+    Ok(())
+});
+
+command_def!(mam,
+r#"/mam search"#,
+{
+    action: Command = {
+        children: {
+            "search": mam_search,
+        }
+    },
+});
 
 struct Query {
     jid: BareJid,
     with: Option<BareJid>,
+    /// Only retrieve messages archived after this date
+    start: Option<DateTime<FixedOffset>>,
     from: Option<DateTime<FixedOffset>>,
+    /// Full text search as the supported form field and the searched text
+    text: Option<(String, String)>,
+    /// Page forward from the oldest message instead of backward from the most recent one
+    forward: bool,
+    /// Archive id after which a forward query starts
+    after: Option<String>,
+    /// Keep paging until the archive is exhausted, count is then only the page size
+    catch_up: bool,
     count: usize,
+    /// Matches of a search query, they are not displayed in the conversation
+    results: Option<Vec<Message>>,
 }
 
 impl Query {
+    /// Backward query retrieving the most recent messages, optionally up to a given date
+    fn history(jid: BareJid, with: Option<BareJid>, from: Option<DateTime<FixedOffset>>) -> Self {
+        Self {
+            jid,
+            with,
+            start: None,
+            from,
+            text: None,
+            forward: false,
+            after: None,
+            catch_up: false,
+            count: HISTORY_COUNT,
+            results: None,
+        }
+    }
+
+    /// Forward query retrieving every message archived after the given archive id
+    fn catch_up(jid: BareJid, after: String) -> Self {
+        Self {
+            jid,
+            with: None,
+            start: None,
+            from: None,
+            text: None,
+            forward: true,
+            after: Some(after),
+            catch_up: true,
+            count: CATCH_UP_PAGE_SIZE,
+            results: None,
+        }
+    }
+
+    /// Forward query matching the given text, the full text search field must be discovered
+    /// before sending it
+    fn search(
+        jid: BareJid,
+        with: Option<BareJid>,
+        text: String,
+        start: Option<DateTime<FixedOffset>>,
+    ) -> Self {
+        Self {
+            jid,
+            with,
+            start,
+            from: None,
+            text: Some((String::new(), text)),
+            forward: true,
+            after: None,
+            catch_up: false,
+            count: SEARCH_MAX_RESULTS,
+            results: Some(Vec::new()),
+        }
+    }
+
     pub fn start(&self) -> (String, Iq) {
-        // Start with before set to empty string in order to force xmpp_parser to generate a
-        // <before/> element and to ensure we get last page first
-        self.query(Some("".to_string()))
+        if self.forward {
+            self.query(self.after.clone(), None)
+        } else {
+            // Start with before set to empty string in order to force xmpp_parser to generate a
+            // <before/> element and to ensure we get last page first
+            self.query(None, Some("".to_string()))
+        }
     }
 
-    pub fn cont(&self, before: String) -> (String, Iq) {
-        self.query(Some(before))
+    pub fn cont(&self, fin: &xmpp_mam::Fin) -> Option<(String, Iq)> {
+        if self.forward {
+            fin.set
+                .last
+                .clone()
+                .map(|last| self.query(Some(last), None))
+        } else {
+            fin.set
+                .first
+                .clone()
+                .map(|first| self.query(None, Some(first)))
+        }
     }
 
-    fn query(&self, before: Option<String>) -> (String, Iq) {
+    fn field(var: &str, value: String) -> Field {
+        Field {
+            var: var.to_string(),
+            type_: FieldType::default(),
+            label: None,
+            required: false,
+            options: vec![],
+            values: vec![value],
+            media: vec![],
+        }
+    }
+
+    fn query(&self, after: Option<String>, before: Option<String>) -> (String, Iq) {
         let mut fields = Vec::new();
 
+        if let Some(start) = self.start {
+            fields.push(Self::field("start", start.to_rfc3339()));
+        }
+
         if let Some(end) = self.from {
-            let datetime = end.to_rfc3339();
-            fields.push(Field {
-                var: "end".to_string(),
-                type_: FieldType::default(),
-                label: None,
-                required: false,
-                options: vec![],
-                values: vec![datetime],
-                media: vec![],
-            });
+            fields.push(Self::field("end", end.to_rfc3339()));
         }
 
         if let Some(with) = &self.with {
-            fields.push(Field {
-                var: "with".to_string(),
-                type_: FieldType::default(),
-                label: None,
-                required: false,
-                options: vec![],
-                values: vec![with.to_string()],
-                media: vec![],
-            });
+            fields.push(Self::field("with", with.to_string()));
+        }
+
+        if let Some((var, text)) = &self.text {
+            fields.push(Self::field(var, text.clone()));
         }
 
         let form = DataForm {
@@ -74,14 +234,14 @@ impl Query {
 
         let set = SetQuery {
             max: Some(self.count),
-            after: None,
+            after,
             before,
             index: None,
         };
 
         let queryid = Uuid::new_v4().hyphenated().to_string();
-        let query = mam::Query {
-            queryid: Some(mam::QueryId(queryid.clone())),
+        let query = xmpp_mam::Query {
+            queryid: Some(xmpp_mam::QueryId(queryid.clone())),
             node: None,
             form: Some(form),
             set: Some(set),
@@ -94,6 +254,20 @@ impl Query {
             Iq::from_set(id, query).with_to(Jid::from(self.jid.clone())),
         )
     }
+
+    /// Request the query form of the archive in order to discover supported fields
+    fn form_request(&self) -> Iq {
+        let query = xmpp_mam::Query {
+            queryid: None,
+            node: None,
+            form: None,
+            set: None,
+            flip_page: false,
+        };
+
+        let id = Uuid::new_v4().hyphenated().to_string();
+        Iq::from_get(id, query).with_to(Jid::from(self.jid.clone()))
+    }
 }
 
 #[derive(Default)]
@@ -103,6 +277,12 @@ pub struct MamMod {
 
     /// Mapping between iq ids and query ids
     iq2id: HashMap<String, String>,
+
+    /// Search queries waiting for the archive query form, indexed by iq id
+    pending_searches: HashMap<String, Query>,
+
+    /// Last known archive id and its timestamp, indexed by account and archive jid
+    last_stanza_ids: HashMap<(Account, BareJid), (String, DateTime<FixedOffset>)>,
 }
 
 impl MamMod {
@@ -113,34 +293,174 @@ impl MamMod {
         aparte.send(account, iq);
     }
 
-    fn handle_result(&mut self, aparte: &mut Aparte, account: &Account, result: mam::Result_) {
+    /// Remember the most recent archive id seen for an archive, older ones are ignored as
+    /// history can be retrieved in any order
+    fn track(
+        &mut self,
+        account: &Account,
+        archive: BareJid,
+        id: String,
+        timestamp: DateTime<FixedOffset>,
+    ) {
+        let key = (account.clone(), archive);
+        match self.last_stanza_ids.get(&key) {
+            Some((_, last)) if *last > timestamp => {}
+            _ => {
+                self.last_stanza_ids.insert(key, (id, timestamp));
+            }
+        }
+    }
+
+    /// Retrieve everything archived since the last known message of each archive
+    fn catch_up(&mut self, aparte: &mut Aparte, account: &Account) {
+        let archives: Vec<(BareJid, String)> = self
+            .last_stanza_ids
+            .iter()
+            .filter(|((archive_account, _), _)| archive_account == account)
+            .map(|((_, archive), (id, _))| (archive.clone(), id.clone()))
+            .collect();
+
+        for (archive, after) in archives {
+            log::info!("Catching up with {} archive after {}", archive, after);
+            self.query(aparte, account, Query::catch_up(archive, after));
+        }
+    }
+
+    /// Whether everything archived since the last known message of this archive is already
+    /// being retrieved
+    fn is_catching_up(&self, archive: &BareJid) -> bool {
+        self.queries
+            .values()
+            .any(|query| query.catch_up && &query.jid == archive)
+    }
+
+    fn search(&mut self, aparte: &Aparte, account: &Account, query: Query) {
+        let iq = query.form_request();
+        self.pending_searches.insert(iq.id.clone(), query);
+        aparte.proxy().send(account, iq.into());
+    }
+
+    fn handle_search_form(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        mut query: Query,
+        iq: &Iq,
+    ) {
+        let form = match &iq.payload {
+            IqType::Result(Some(payload)) => xmpp_mam::Query::try_from(payload.clone())
+                .ok()
+                .and_then(|query| query.form),
+            IqType::Error(err) => {
+                aparte.log(format!(
+                    "Can't search {} archive: {}",
+                    query.jid,
+                    terminus::clean_str(
+                        &i18n::xmpp_err_to_string(err, aparte.config.prefered_langs()).1
+                    )
+                ));
+                return;
+            }
+            _ => None,
+        };
+
+        let field = form.and_then(|form| {
+            FULL_TEXT_SEARCH_FIELDS
+                .iter()
+                .find(|supported| form.fields.iter().any(|field| &field.var == *supported))
+                .map(|supported| supported.to_string())
+        });
+
+        match (field, query.text.take()) {
+            (Some(field), Some((_, text))) => {
+                query.text = Some((field, text));
+                self.query(aparte, account, query);
+            }
+            _ => crate::info!(
+                aparte,
+                "{} archive doesn't support full text search",
+                query.jid
+            ),
+        }
+    }
+
+    fn handle_result(&mut self, aparte: &mut Aparte, account: &Account, result: xmpp_mam::Result_) {
         if let Some(id) = &result.queryid {
             if let Some(query) = self.queries.get_mut(&id.0) {
-                query.count -= 1;
-                if let (Some(delay), Some(message)) =
+                if !query.catch_up {
+                    query.count = query.count.saturating_sub(1);
+                }
+                if let (Some(delay), Some(mut message)) =
                     (result.forwarded.delay, result.forwarded.stanza)
                 {
-                    aparte.schedule(Event::RawMessage {
-                        account: account.clone(),
-                        message,
-                        delay: Some(delay),
-                        archive: true,
-                    });
+                    // The result id is the stanza-id the archive gave to the message, it isn't
+                    // always repeated in the forwarded stanza
+                    if Message::get_stanza_id_from_xmpp(account, &message).is_none() {
+                        message.payloads.push(
+                            StanzaId {
+                                id: result.id.clone(),
+                                by: Jid::from(query.jid.clone()),
+                            }
+                            .into(),
+                        );
+                    }
+                    let archive = match query.results {
+                        Some(_) => None,
+                        None => Some((query.jid.clone(), delay.stamp.0)),
+                    };
+                    match &mut query.results {
+                        Some(results) => {
+                            if let Ok(message) =
+                                Message::from_xmpp(account, &message, &Some(delay), true)
+                            {
+                                results.push(message);
+                            }
+                        }
+                        None => aparte.schedule(Event::RawMessage {
+                            account: account.clone(),
+                            message,
+                            delay: Some(delay),
+                            archive: true,
+                        }),
+                    }
+
+                    if let Some((archive, timestamp)) = archive {
+                        self.track(account, archive, result.id, timestamp);
+                    }
                 }
             }
         }
     }
 
-    fn handle_fin(&mut self, aparte: &mut Aparte, account: &Account, query: Query, fin: mam::Fin) {
-        if fin.complete == mam::Complete::False {
-            if let Some(start) = fin.set.first {
+    fn handle_fin(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        query: Query,
+        fin: xmpp_mam::Fin,
+    ) {
+        if let (Some(results), Some((_, text))) = (&query.results, &query.text) {
+            aparte.schedule(Event::SearchResults {
+                query: text.clone(),
+                results: results
+                    .iter()
+                    .map(|message| (account.clone(), message.clone()))
+                    .collect(),
+            });
+
+            if query.count == 0 {
+                return;
+            }
+        }
+
+        if fin.complete == xmpp_mam::Complete::False {
+            if let Some((queryid, iq)) = query.cont(&fin) {
                 log::info!(
                     "Continuing MAM retrieval for {} with {:?} from {:?}",
                     query.jid,
                     query.with.clone().map(|jid| jid.to_string()),
                     query.from
                 );
-                let (queryid, iq) = query.cont(start);
                 self.queries.insert(queryid.clone(), query);
                 self.iq2id.insert(iq.id.clone(), queryid);
                 aparte.send(account, iq);
@@ -150,7 +470,9 @@ impl MamMod {
 }
 
 impl ModTrait for MamMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(mam::new());
+
         Ok(())
     }
 
@@ -162,7 +484,7 @@ impl ModTrait for MamMod {
         _delay: &Option<Delay>,
     ) -> f64 {
         for payload in message.payloads.iter() {
-            if mam::Result_::try_from(payload.clone()).is_ok() {
+            if xmpp_mam::Result_::try_from(payload.clone()).is_ok() {
                 return 1f64;
             }
         }
@@ -178,7 +500,7 @@ impl ModTrait for MamMod {
         _archive: bool,
     ) {
         for payload in message.payloads.iter() {
-            if let Ok(result) = mam::Result_::try_from(payload.clone()) {
+            if let Ok(result) = xmpp_mam::Result_::try_from(payload.clone()) {
                 self.handle_result(aparte, account, result);
             }
         }
@@ -186,33 +508,26 @@ impl ModTrait for MamMod {
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            Event::Connected(account, _jid) => self.catch_up(aparte, account),
             Event::Join {
                 account, channel, ..
             } => {
-                let query = Query {
-                    jid: channel.to_bare(),
-                    with: None,
-                    from: None,
-                    count: 100,
+                let key = (account.clone(), channel.to_bare());
+                let query = match self.last_stanza_ids.get(&key) {
+                    // After a reconnection the catch up may already be running
+                    Some(_) if self.is_catching_up(&key.1) => return,
+                    // Already retrieved history, only fetch what was missed while away
+                    Some((after, _)) => Query::catch_up(channel.to_bare(), after.clone()),
+                    None => Query::history(channel.to_bare(), None, None),
                 };
                 self.query(aparte, account, query);
             }
             Event::Chat { account, contact } => {
-                let query = Query {
-                    jid: account.to_bare(),
-                    with: Some(contact.clone()),
-                    from: None,
-                    count: 100,
-                };
+                let query = Query::history(account.to_bare(), Some(contact.clone()), None);
                 self.query(aparte, account, query);
             }
             Event::LoadChannelHistory { account, jid, from } => {
-                let query = Query {
-                    jid: jid.clone(),
-                    with: None,
-                    from: *from,
-                    count: 100,
-                };
+                let query = Query::history(jid.clone(), None, *from);
                 self.query(aparte, account, query);
             }
             Event::LoadChatHistory {
@@ -220,23 +535,49 @@ impl ModTrait for MamMod {
                 contact,
                 from,
             } => {
-                let query = Query {
-                    jid: account.to_bare(),
-                    with: Some(contact.clone()),
-                    from: *from,
-                    count: 100,
-                };
+                let query = Query::history(account.to_bare(), Some(contact.clone()), *from);
                 self.query(aparte, account, query);
             }
+            Event::Message(Some(account), Message::Xmpp(message)) => {
+                if let Some(stanza_id) = &message.stanza_id {
+                    let archive = match (&message.type_, &message.direction) {
+                        (XmppMessageType::Channel, Direction::Incoming) => message.from.clone(),
+                        (XmppMessageType::Channel, Direction::Outgoing) => message.to.clone(),
+                        (XmppMessageType::Chat, _) => account.to_bare(),
+                    };
+                    let timestamp = *message.get_original_timestamp();
+                    self.track(account, archive, stanza_id.clone(), timestamp);
+                }
+            }
             Event::Iq(account, iq) => {
-                if let Some(id) = self.iq2id.remove(&iq.id) {
+                if let Some(query) = self.pending_searches.remove(&iq.id) {
+                    self.handle_search_form(aparte, account, query, iq);
+                } else if let Some(id) = self.iq2id.remove(&iq.id) {
                     if let Some(query) = self.queries.remove(&id) {
-                        if let IqType::Result(Some(payload)) = &iq.payload {
-                            if let Ok(fin) = mam::Fin::try_from(payload.clone()) {
-                                self.handle_fin(aparte, account, query, fin);
-                            } else {
-                                log::warn!("Incorrect IQ response for MAM query");
+                        match &iq.payload {
+                            IqType::Result(Some(payload)) => {
+                                if let Ok(fin) = xmpp_mam::Fin::try_from(payload.clone()) {
+                                    self.handle_fin(aparte, account, query, fin);
+                                } else {
+                                    log::warn!("Incorrect IQ response for MAM query");
+                                }
+                            }
+                            IqType::Error(err) => {
+                                let err = terminus::clean_str(
+                                    &i18n::xmpp_err_to_string(err, aparte.config.prefered_langs())
+                                        .1,
+                                );
+                                match query.results {
+                                    Some(_) => aparte.log(format!(
+                                        "Can't search {} archive: {}",
+                                        query.jid, err
+                                    )),
+                                    None => {
+                                        log::warn!("Can't retrieve {} archive: {}", query.jid, err)
+                                    }
+                                }
                             }
+                            _ => {}
                         }
                     }
                 }
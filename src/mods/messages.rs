@@ -1,39 +1,644 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
-use std::collections::HashMap;
+use anyhow::{bail, Context};
+use chrono::{DateTime, FixedOffset};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
 use xmpp_parsers::ns;
+use xmpp_parsers::stanza_error::StanzaError;
+use xmpp_parsers::BareJid;
 
 use crate::account::Account;
+use crate::command::{Command, CommandParser};
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message::Message;
+use crate::i18n;
+use crate::message::{DeliveryState, Direction, Message, VersionedXmppMessage, XmppMessageType};
 use crate::mods::disco;
+use crate::mods::ui::UIMod;
+
+command_def!(
+    retry,
+    r#"/retry
+
+Description:
+    Send again the selected message, or the last message of the current
+    conversation that failed to be delivered if none is selected. Select a
+    message with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /retry"#,
+    {},
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .context("Can't use /retry in non XMPP window")?;
+        let peer =
+            BareJid::from_str(&command.context).context("Can't use /retry in non XMPP window")?;
+
+        let selected = aparte.get_mod_mut::<UIMod>().get_selection();
+        let mut message = {
+            let messages = aparte.get_mod::<MessagesMod>();
+            match selected {
+                Some(selected) => {
+                    match messages.get(&Some(account.clone()), &peer, selected.id()) {
+                        Some(Message::Xmpp(message)) => message.clone(),
+                        _ => bail!("Only XMPP messages can be sent again"),
+                    }
+                }
+                None => messages
+                    .get_last_failed(&account, &peer)
+                    .cloned()
+                    .context("No failed message to send again")?,
+            }
+        };
+
+        if message.error.take().is_none() {
+            bail!("This message didn't fail");
+        }
+        aparte.schedule(Event::SendMessage(account, Message::Xmpp(message)));
+        Ok(())
+    }
+);
+
+command_def!(
+    lang,
+    r#"/lang
+
+Description:
+    Show every language version of the selected message, or of the last
+    message of the current conversation if none is selected. Select a message
+    with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /lang"#,
+    {},
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .context("Can't use /lang in non XMPP window")?;
+
+        // Headlines aren't kept by MessagesMod, the selection holds the whole message anyway
+        let message = match aparte.get_mod_mut::<UIMod>().get_selection() {
+            Some(Message::Xmpp(message)) => message,
+            Some(Message::Log(_)) => bail!("Only XMPP messages have language versions"),
+            None => {
+                let peer = BareJid::from_str(&command.context)
+                    .context("Can't use /lang in non XMPP window")?;
+                aparte
+                    .get_mod::<MessagesMod>()
+                    .get_last(&account, &peer)
+                    .cloned()
+                    .context("No message")?
+            }
+        };
+
+        let mut versions = message
+            .subjects
+            .iter()
+            .map(|(lang, subject)| (lang, format!("(subject) {subject}")))
+            .chain(
+                message
+                    .get_last_bodies()
+                    .map(|(lang, body)| (lang, body.clone())),
+            )
+            .collect::<Vec<_>>();
+        versions.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        // Subjects and bodies are remote text, strip any terminal sequence they may carry
+        let mut output = format!(
+            "Language versions of {}:",
+            terminus::clean_str(&message.get_preview(aparte.config.prefered_langs()))
+        );
+        for (lang, text) in versions {
+            let lang = match lang.as_str() {
+                "" => "default".to_string(),
+                lang => terminus::clean_str(lang),
+            };
+            for line in text.lines() {
+                output.push_str(&format!("\n[{lang}] {}", terminus::clean_str(line)));
+            }
+        }
+        aparte.log(output);
+        Ok(())
+    }
+);
+
+mod search {
+    use std::str::FromStr;
+
+    use anyhow::{anyhow, bail, Context, Result};
+    use chrono::{DateTime, FixedOffset, Local as LocalTz, NaiveDate, TimeZone};
+    use xmpp_parsers::BareJid;
+
+    use crate::account::Account;
+    use crate::command::{Command, CommandParser};
+    use crate::core::{Aparte, Event};
+    use crate::storage::SearchQuery;
+
+    const MAX_RESULTS: i64 = 100;
+
+    fn parse(account: &Option<Account>, context: &str, buf: &str) -> Result<Command> {
+        Command::new(account.clone(), context.to_string(), buf.to_string())
+    }
+
+    fn parse_date(date: &str) -> Result<DateTime<FixedOffset>> {
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+            return Ok(datetime);
+        }
+
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid date {date}"))?;
+        let midnight = day.and_hms_opt(0, 0, 0).context("Invalid date")?;
+        LocalTz
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|datetime| datetime.into())
+            .ok_or(anyhow!("Invalid local date {date}"))
+    }
+
+    pub(super) fn parse_query(args: &[String]) -> Result<SearchQuery> {
+        let mut query = SearchQuery::default();
+        let mut words = Vec::new();
+
+        for arg in args {
+            if let Some(jid) = arg.strip_prefix("in:") {
+                query.peer = Some(BareJid::from_str(jid)?);
+            } else if let Some(from) = arg.strip_prefix("from:") {
+                query.from = Some(from.to_string());
+            } else if let Some(before) = arg.strip_prefix("before:") {
+                query.before = Some(parse_date(before)?);
+            } else if let Some(after) = arg.strip_prefix("after:") {
+                query.after = Some(parse_date(after)?);
+            } else {
+                words.push(arg.as_str());
+            }
+        }
+
+        if words.is_empty() {
+            bail!("Missing search query");
+        }
+
+        query.text = words.join(" ");
+        Ok(query)
+    }
+
+    fn exec(aparte: &mut Aparte, command: Command) -> Result<()> {
+        let query = parse_query(&command.args[1..])?;
+        let results =
+            aparte
+                .storage
+                .search_messages(command.account.as_ref(), &query, MAX_RESULTS)?;
+        aparte.schedule(Event::SearchResults {
+            query: command.args[1..].join(" "),
+            results,
+        });
+        Ok(())
+    }
+
+    pub fn new() -> CommandParser {
+        CommandParser {
+            name: "search",
+            help: r#"/search <query> [in:<jid>] [from:<nick>] [before:<date>] [after:<date>]
+
+    query         Words to look for in message bodies
+    in            Only search in the conversation with the given contact or channel
+    from          Only search messages sent by the given nick or jid
+    before        Only search messages sent before the given date
+    after         Only search messages sent after the given date
+
+Description:
+    Search through locally stored message history. Results are shown in the
+    search window, select a result with Ctrl+Up/Ctrl+Down and press Enter
+    to jump to it in its conversation.
+
+    Dates are either YYYY-MM-DD or RFC 3339 timestamps.
+
+Examples:
+    /search release
+    /search release in:aparte@conference.fariello.eu
+    /search "next release" from:paul after:2024-01-01"#
+                .to_string(),
+            parse,
+            exec,
+            autocompletions: vec![],
+        }
+    }
+}
+
+/// Number of locally stored messages loaded at once
+const HISTORY_PAGE_SIZE: i64 = 100;
+/// Direct channel invitations (XEP-0249)
+const NS_CONFERENCE: &str = "jabber:x:conference";
+
+/// Identifiers allowing to recognize a message delivered several times (live, carbon, MAM)
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum MessageKey {
+    /// XEP-0359 stanza-id, unique within the archive holding the message
+    StanzaId { archive: BareJid, id: String },
+    /// XEP-0359 origin-id, chosen by the sending client
+    OriginId { peer: BareJid, id: String },
+}
+
+impl MessageKey {
+    fn from_message(account: &Account, message: &VersionedXmppMessage) -> Vec<MessageKey> {
+        let peer = get_peer(message);
+        let archive = match message.type_ {
+            XmppMessageType::Chat => account.to_bare(),
+            XmppMessageType::Channel => peer.clone(),
+        };
+
+        let mut keys = Vec::new();
+        if let Some(id) = &message.stanza_id {
+            keys.push(MessageKey::StanzaId {
+                archive,
+                id: id.clone(),
+            });
+        }
+        if let Some(id) = &message.origin_id {
+            keys.push(MessageKey::OriginId {
+                peer,
+                id: id.clone(),
+            });
+        }
+        keys
+    }
+}
+
+/// Whether the message is a direct (XEP-0249) or mediated (XEP-0045) channel invitation
+fn is_invitation(message: &XmppParsersMessage) -> bool {
+    message.payloads.iter().any(|payload| {
+        payload.is("x", NS_CONFERENCE)
+            || (payload.is("x", ns::MUC_USER) && payload.has_child("invite", ns::MUC_USER))
+    })
+}
+
+/// Contact or channel a message was exchanged with, message ids are only unique within it
+fn get_peer(message: &VersionedXmppMessage) -> BareJid {
+    match message.direction {
+        Direction::Incoming => message.from.clone(),
+        Direction::Outgoing => message.to.clone(),
+    }
+}
 
 #[derive(Default)]
 pub struct MessagesMod {
-    messages: HashMap<Option<Account>, HashMap<String, Message>>,
+    /// XMPP messages indexed by their peer and id
+    messages: HashMap<Option<Account>, HashMap<(BareJid, String), Message>>,
+    /// Peer and id of known messages indexed by their deduplication keys
+    keys: HashMap<Option<Account>, HashMap<MessageKey, (BareJid, String)>>,
+    /// Peer and id of messages loaded from storage, they must not be stored again once dispatched
+    replayed: HashSet<(Account, BareJid, String)>,
+    /// Conversations whose latest history was already loaded from storage
+    loaded: HashSet<(Account, BareJid)>,
 }
 
 impl MessagesMod {
-    pub fn get<'a>(&'a self, account: &Option<Account>, id: &String) -> Option<&'a Message> {
-        self.messages.get(account)?.get(id)
+    pub fn get<'a>(
+        &'a self,
+        account: &Option<Account>,
+        peer: &BareJid,
+        id: &str,
+    ) -> Option<&'a Message> {
+        self.messages
+            .get(account)?
+            .get(&(peer.clone(), id.to_string()))
     }
 
     pub fn get_mut<'a>(
         &'a mut self,
         account: &Option<Account>,
-        id: &String,
+        peer: &BareJid,
+        id: &str,
     ) -> Option<&'a mut Message> {
-        self.messages.get_mut(account)?.get_mut(id)
+        self.messages
+            .get_mut(account)?
+            .get_mut(&(peer.clone(), id.to_string()))
+    }
+
+    /// Peer and id of a message referenced by another one (retraction, reaction...), either by
+    /// its stanza-id, its origin-id or its id
+    fn resolve(&self, account: &Account, peer: &BareJid, id: &str) -> (BareJid, String) {
+        let candidates = [
+            MessageKey::StanzaId {
+                archive: peer.clone(),
+                id: id.to_string(),
+            },
+            MessageKey::StanzaId {
+                archive: account.to_bare(),
+                id: id.to_string(),
+            },
+            MessageKey::OriginId {
+                peer: peer.clone(),
+                id: id.to_string(),
+            },
+        ];
+
+        self.keys
+            .get(&Some(account.clone()))
+            .and_then(|keys| candidates.iter().find_map(|key| keys.get(key)))
+            .cloned()
+            .unwrap_or((peer.clone(), id.to_string()))
+    }
+
+    pub fn find<'a>(&'a self, account: &Account, peer: &BareJid, id: &str) -> Option<&'a Message> {
+        let (peer, id) = self.resolve(account, peer, id);
+        self.get(&Some(account.clone()), &peer, &id)
+    }
+
+    pub fn find_mut<'a>(
+        &'a mut self,
+        account: &Account,
+        peer: &BareJid,
+        id: &str,
+    ) -> Option<&'a mut Message> {
+        let (peer, id) = self.resolve(account, peer, id);
+        self.get_mut(&Some(account.clone()), &peer, &id)
+    }
+
+    /// Messages we sent in the conversation with the given peer
+    pub fn get_outgoing_mut<'a>(
+        &'a mut self,
+        account: &Account,
+        peer: &'a BareJid,
+    ) -> impl Iterator<Item = &'a mut VersionedXmppMessage> {
+        self.messages
+            .get_mut(&Some(account.clone()))
+            .into_iter()
+            .flat_map(|messages| messages.values_mut())
+            .filter_map(move |message| match message {
+                Message::Xmpp(message)
+                    if message.direction == Direction::Outgoing && &message.to == peer =>
+                {
+                    Some(message)
+                }
+                _ => None,
+            })
+    }
+
+    /// Most recent message of the conversation with the given peer
+    pub fn get_last<'a>(
+        &'a self,
+        account: &Account,
+        peer: &BareJid,
+    ) -> Option<&'a VersionedXmppMessage> {
+        self.messages
+            .get(&Some(account.clone()))?
+            .values()
+            .filter_map(|message| match message {
+                Message::Xmpp(message) => match message.direction {
+                    Direction::Incoming if &message.from == peer => Some(message),
+                    Direction::Outgoing if &message.to == peer => Some(message),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .max_by_key(|message| *message.get_original_timestamp())
+    }
+
+    /// Most recent message we sent in the conversation with the given peer that can still be
+    /// corrected or retracted: sent and not retracted yet
+    pub fn get_last_outgoing<'a>(
+        &'a self,
+        account: &Account,
+        peer: &BareJid,
+    ) -> Option<&'a VersionedXmppMessage> {
+        self.messages
+            .get(&Some(account.clone()))?
+            .values()
+            .filter_map(|message| match message {
+                Message::Xmpp(message)
+                    if message.direction == Direction::Outgoing
+                        && &message.to == peer
+                        && message.retraction.is_none()
+                        && message.delivery != Some(DeliveryState::Pending) =>
+                {
+                    Some(message)
+                }
+                _ => None,
+            })
+            .max_by_key(|message| *message.get_original_timestamp())
+    }
+
+    /// Most recent message we sent in the conversation with the given peer that bounced
+    pub fn get_last_failed<'a>(
+        &'a self,
+        account: &Account,
+        peer: &BareJid,
+    ) -> Option<&'a VersionedXmppMessage> {
+        self.messages
+            .get(&Some(account.clone()))?
+            .values()
+            .filter_map(|message| match message {
+                Message::Xmpp(message)
+                    if message.direction == Direction::Outgoing
+                        && &message.to == peer
+                        && message.error.is_some() =>
+                {
+                    Some(message)
+                }
+                _ => None,
+            })
+            .max_by_key(|message| *message.get_original_timestamp())
     }
 
     pub fn handle_message(&mut self, account: &Option<Account>, message: &Message) {
+        // Log messages can't be referenced by other messages
+        let Message::Xmpp(xmpp_message) = message else {
+            return;
+        };
+        let key = (get_peer(xmpp_message), xmpp_message.id.clone());
+
+        if let Some(bare_account) = account {
+            let keys = self.keys.entry(account.clone()).or_default();
+            for dedup_key in MessageKey::from_message(bare_account, xmpp_message) {
+                keys.insert(dedup_key, key.clone());
+            }
+        }
+
         let messages = self.messages.entry(account.clone()).or_default();
-        messages.insert(message.id().to_string(), message.clone());
+        messages.insert(key, message.clone());
+    }
+
+    /// Recognize messages already received through another path, by their stanza-id, their
+    /// origin-id or their id within the conversation. Returns None when the message brings
+    /// nothing new, or the known message enriched with the new stanza-id.
+    fn deduplicate(&mut self, account: &Account, mut message: Message) -> Option<Message> {
+        let account_key = Some(account.clone());
+        if let Message::Xmpp(xmpp_message) = &message {
+            let (peer, id) = self
+                .keys
+                .get(&account_key)
+                .and_then(|keys| {
+                    MessageKey::from_message(account, xmpp_message)
+                        .iter()
+                        .find_map(|key| keys.get(key))
+                        .cloned()
+                })
+                .unwrap_or_else(|| (get_peer(xmpp_message), xmpp_message.id.clone()));
+
+            if let Some(Message::Xmpp(known)) = self.get(&account_key, &peer, &id) {
+                if xmpp_message.stanza_id.is_none() || known.stanza_id.is_some() {
+                    return None;
+                }
+                let mut merged = known.clone();
+                merged.stanza_id = xmpp_message.stanza_id.clone();
+                // A stanza-id proves the server got our message
+                if merged.delivery == Some(DeliveryState::Sent) {
+                    merged.delivery = Some(DeliveryState::Acked);
+                }
+                message = Message::Xmpp(merged);
+            }
+        }
+
+        // Register right away so that a copy handled before this message is dispatched is
+        // recognized too
+        self.handle_message(&account_key, &message);
+        Some(message)
+    }
+
+    /// Preview the message answered by a reply with its known content rather than the quote
+    /// sent along
+    fn resolve_reply(&self, account: &Account, message: &mut Message, prefered_langs: Vec<&str>) {
+        if let Message::Xmpp(xmpp_message) = message {
+            let peer = match xmpp_message.direction {
+                Direction::Incoming => xmpp_message.from.clone(),
+                Direction::Outgoing => xmpp_message.to.clone(),
+            };
+            if let Some(reply) = &mut xmpp_message.reply {
+                if let Some(Message::Xmpp(target)) = self.find(account, &peer, &reply.id) {
+                    reply.preview = Some(target.get_preview(prefered_langs));
+                }
+            }
+        }
+    }
+
+    fn schedule_message(&mut self, aparte: &mut Aparte, account: &Account, mut message: Message) {
+        self.resolve_reply(account, &mut message, aparte.config.prefered_langs());
+        if let Some(message) = self.deduplicate(account, message) {
+            aparte.schedule(Event::Message(Some(account.clone()), message));
+        }
+    }
+
+    fn store_message(&mut self, aparte: &mut Aparte, account: &Option<Account>, message: &Message) {
+        if let (Some(account), Message::Xmpp(message)) = (account, message) {
+            let key = (account.clone(), get_peer(message), message.id.clone());
+            if self.replayed.remove(&key) {
+                return;
+            }
+            if let Err(err) = aparte.storage.store_message(account, message) {
+                log::error!("Cannot store message {}: {}", message.id, err);
+            }
+        }
+    }
+
+    /// Show locally stored history right away, MAM will fill the gaps later on
+    fn load_history(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        peer: &BareJid,
+        before: Option<DateTime<FixedOffset>>,
+    ) {
+        match aparte
+            .storage
+            .get_messages(account, peer, before, HISTORY_PAGE_SIZE)
+        {
+            Ok(messages) => {
+                for message in messages {
+                    if let Message::Xmpp(replayed) = &message {
+                        let key = (account.clone(), get_peer(replayed), replayed.id.clone());
+                        self.replayed.insert(key);
+                    }
+                    aparte.schedule(Event::Message(Some(account.clone()), message));
+                }
+            }
+            Err(err) => log::error!("Cannot load history for {}: {}", peer, err),
+        }
+    }
+
+    /// Send messages queued while the account was disconnected, in the order they were written
+    /// Chat messages are sent once connected, channel messages once the channel is joined again
+    /// as they would otherwise bounce.
+    fn flush_outbox(&self, aparte: &mut Aparte, account: &Account, channel: Option<&BareJid>) {
+        let pending = match aparte.storage.get_pending_messages(account) {
+            Ok(pending) => pending,
+            Err(err) => {
+                crate::error!(aparte, err, "Cannot load outbox of {account}");
+                return;
+            }
+        };
+
+        for message in pending {
+            let Message::Xmpp(pending) = &message else {
+                continue;
+            };
+            let due = match (&pending.type_, channel) {
+                (XmppMessageType::Chat, None) => true,
+                (XmppMessageType::Channel, Some(channel)) => &pending.to == channel,
+                _ => false,
+            };
+            if !due {
+                continue;
+            }
+
+            // Prefer the in-memory copy, it holds what isn't stored such as the replied message
+            let known = self
+                .get(&Some(account.clone()), &pending.to, &pending.id)
+                .cloned();
+            let message = known.unwrap_or(message);
+            aparte.schedule(Event::SendMessage(account.clone(), message));
+        }
+    }
+
+    /// Put back in the outbox a message that never left
+    fn handle_unsent(&mut self, aparte: &mut Aparte, account: &Account, peer: &BareJid, id: &str) {
+        if let Some(Message::Xmpp(unsent)) = self.find_mut(account, peer, id) {
+            if unsent.direction == Direction::Outgoing {
+                unsent.delivery = Some(DeliveryState::Pending);
+                let unsent = Message::Xmpp(unsent.clone());
+                aparte.schedule(Event::Message(Some(account.clone()), unsent));
+            }
+        }
+    }
+
+    /// Mark the outgoing message an error was returned for as failed
+    fn handle_error_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) {
+        let (Some(id), Some(from)) = (&message.id, &message.from) else {
+            return;
+        };
+        let Some(error) = message
+            .payloads
+            .iter()
+            .find_map(|payload| StanzaError::try_from(payload.clone()).ok())
+        else {
+            return;
+        };
+        let (_, error) = i18n::xmpp_err_to_string(&error, vec![]);
+
+        let failed = match self.find_mut(account, &from.to_bare(), id) {
+            Some(Message::Xmpp(failed)) if failed.direction == Direction::Outgoing => {
+                failed.error = Some(error);
+                Message::Xmpp(failed.clone())
+            }
+            // Errors for chat states, receipts... aren't worth bothering the user
+            _ => {
+                log::info!("Error for message {id} from {from}: {error}");
+                return;
+            }
+        };
+        aparte.schedule(Event::Message(Some(account.clone()), failed));
     }
 
     fn handle_headline_message(
@@ -41,8 +646,15 @@ impl MessagesMod {
         aparte: &mut Aparte,
         account: &Account,
         message: &XmppParsersMessage,
-        _delay: &Option<Delay>,
+        delay: &Option<Delay>,
     ) {
+        // Headlines aren't meant to be stored, they are only shown in the notices window
+        if !message.bodies.is_empty() {
+            if let Ok(notice) = Message::from_xmpp(account, message, delay, false) {
+                aparte.schedule(Event::Notice(account.clone(), notice));
+            }
+        }
+
         for payload in message.payloads.iter().cloned() {
             if let Ok(pubsub_event) = xmpp_parsers::pubsub::event::PubSubEvent::try_from(payload) {
                 // TODO move to pubsub mod
@@ -58,8 +670,13 @@ impl MessagesMod {
 
 impl ModTrait for MessagesMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(search::new());
+        aparte.add_command(retry::new());
+        aparte.add_command(lang::new());
+
         let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
         disco.add_feature(ns::MESSAGE_CORRECT);
+        disco.add_feature(ns::SID);
 
         Ok(())
     }
@@ -72,7 +689,9 @@ impl ModTrait for MessagesMod {
         _delay: &Option<Delay>,
     ) -> f64 {
         match message.type_ {
-            XmppParsersMessageType::Chat => {
+            // Channel invitations aren't messages from the room
+            XmppParsersMessageType::Normal if is_invitation(message) => 0f64,
+            XmppParsersMessageType::Chat | XmppParsersMessageType::Normal => {
                 if message.bodies.is_empty() {
                     0f64
                 } else {
@@ -87,17 +706,18 @@ impl ModTrait for MessagesMod {
                 }
             }
             XmppParsersMessageType::Headline => {
-                if message
-                    .payloads
-                    .iter()
-                    .any(|p| p.is("event", ns::PUBSUB_EVENT))
+                if !message.bodies.is_empty()
+                    || message
+                        .payloads
+                        .iter()
+                        .any(|p| p.is("event", ns::PUBSUB_EVENT))
                 {
                     0.01f64
                 } else {
                     0f64
                 }
             }
-            _ => 0f64,
+            XmppParsersMessageType::Error => 0.01f64,
         }
     }
 
@@ -110,15 +730,15 @@ impl ModTrait for MessagesMod {
         archive: bool,
     ) {
         match message.type_ {
-            XmppParsersMessageType::Chat => {
+            XmppParsersMessageType::Chat | XmppParsersMessageType::Normal => {
                 if let Ok(message) = Message::from_xmpp(account, message, delay, archive) {
-                    aparte.schedule(Event::Message(Some(account.clone()), message));
+                    self.schedule_message(aparte, account, message);
                 }
             }
             XmppParsersMessageType::Groupchat => {
                 if !message.bodies.is_empty() {
                     if let Ok(message) = Message::from_xmpp(account, message, delay, archive) {
-                        aparte.schedule(Event::Message(Some(account.clone()), message));
+                        self.schedule_message(aparte, account, message);
                     }
                 }
 
@@ -141,14 +761,46 @@ impl ModTrait for MessagesMod {
             XmppParsersMessageType::Headline => {
                 self.handle_headline_message(aparte, account, message, delay)
             }
-            XmppParsersMessageType::Error => {}
-            XmppParsersMessageType::Normal => {}
+            XmppParsersMessageType::Error => self.handle_error_message(aparte, account, message),
         };
     }
 
-    fn on_event(&mut self, _aparte: &mut Aparte, event: &Event) {
-        if let Event::Message(account, message) = event {
-            self.handle_message(account, message)
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(account, message) => {
+                self.handle_message(account, message);
+                self.store_message(aparte, account, message);
+            }
+            Event::Connected(account, _) => {
+                self.flush_outbox(aparte, account, None);
+            }
+            Event::Unsent { account, peer, id } => {
+                self.handle_unsent(aparte, account, peer, id);
+            }
+            Event::Chat { account, contact } => {
+                if self.loaded.insert((account.clone(), contact.clone())) {
+                    self.load_history(aparte, account, contact, None);
+                }
+            }
+            Event::Joined {
+                account, channel, ..
+            } => {
+                if self.loaded.insert((account.clone(), channel.to_bare())) {
+                    self.load_history(aparte, account, &channel.to_bare(), None);
+                }
+                self.flush_outbox(aparte, account, Some(&channel.to_bare()));
+            }
+            Event::LoadChatHistory {
+                account,
+                contact,
+                from,
+            } => {
+                self.load_history(aparte, account, contact, *from);
+            }
+            Event::LoadChannelHistory { account, jid, from } => {
+                self.load_history(aparte, account, jid, *from);
+            }
+            _ => {}
         }
     }
 }
@@ -158,3 +810,181 @@ impl fmt::Display for MessagesMod {
         write!(f, "Message store")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use std::collections::HashMap;
+
+    use chrono::{Datelike, Local as LocalTz};
+    use xmpp_parsers::message::Message as XmppParsersMessage;
+    use xmpp_parsers::{BareJid, Element, FullJid, Jid};
+
+    use super::search::parse_query;
+    use super::{is_invitation, MessagesMod};
+    use crate::message::Message;
+
+    fn channel_message(id: &str, from: &str, outgoing: bool) -> Message {
+        let from = Jid::from_str(from).unwrap();
+        let room = Jid::from_str("aparte@conference.fariello.eu").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), "Hello".to_string());
+        let timestamp = LocalTz::now().into();
+        if outgoing {
+            Message::outgoing_channel(id, timestamp, &from, &room, bodies, None, false)
+        } else {
+            Message::incoming_channel(id, timestamp, &from, &room, bodies, None, false)
+        }
+    }
+
+    fn args(buf: &str) -> Vec<String> {
+        buf.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_is_invitation() {
+        // Given
+        let direct: Element =
+            r#"<message xmlns="jabber:client" from="paul@fariello.eu/aparte" to="me@fariello.eu">
+            <x xmlns="jabber:x:conference" jid="aparte@conference.fariello.eu"/>
+        </message>"#
+                .parse()
+                .unwrap();
+        let mediated: Element = r#"<message xmlns="jabber:client" from="aparte@conference.fariello.eu" to="me@fariello.eu">
+            <x xmlns="http://jabber.org/protocol/muc#user">
+                <invite from="paul@fariello.eu/aparte"><reason>Join us</reason></invite>
+            </x>
+            <body>paul@fariello.eu invited you to aparte@conference.fariello.eu</body>
+        </message>"#
+            .parse()
+            .unwrap();
+        let normal: Element =
+            r#"<message xmlns="jabber:client" from="paul@fariello.eu/aparte" to="me@fariello.eu">
+            <body>Hello</body>
+        </message>"#
+                .parse()
+                .unwrap();
+
+        // When
+        let direct = is_invitation(&XmppParsersMessage::try_from(direct).unwrap());
+        let mediated = is_invitation(&XmppParsersMessage::try_from(mediated).unwrap());
+        let normal = is_invitation(&XmppParsersMessage::try_from(normal).unwrap());
+
+        // Then
+        assert!(direct);
+        assert!(mediated);
+        assert!(!normal);
+    }
+
+    #[test]
+    fn test_parse_search_query() {
+        // Given
+        let args = args("next release in:aparte@conference.fariello.eu from:paul after:2024-01-02");
+
+        // When
+        let query = parse_query(&args).unwrap();
+
+        // Then
+        assert_eq!(query.text, "next release");
+        assert_eq!(
+            query.peer,
+            Some(BareJid::from_str("aparte@conference.fariello.eu").unwrap())
+        );
+        assert_eq!(query.from, Some("paul".to_string()));
+        assert_eq!(query.after.map(|after| after.day()), Some(2));
+        assert!(query.before.is_none());
+    }
+
+    #[test]
+    fn test_parse_search_query_without_words() {
+        // Given
+        let args = args("in:aparte@conference.fariello.eu");
+
+        // When
+        let query = parse_query(&args);
+
+        // Then
+        assert!(query.is_err());
+    }
+
+    #[test]
+    fn test_deduplicate_reflection_and_archive() {
+        // Given
+        let account = FullJid::from_str("me@fariello.eu/aparte").unwrap();
+        let mut messages = MessagesMod::default();
+        let echo = channel_message("echo-id", "me@fariello.eu/needle", true);
+        messages.handle_message(&Some(account.clone()), &echo);
+
+        let mut reflection =
+            channel_message("server-id", "aparte@conference.fariello.eu/needle", false);
+        let mut archived = reflection.clone();
+        if let Message::Xmpp(reflection) = &mut reflection {
+            reflection.origin_id = Some("echo-id".to_string());
+        }
+        if let Message::Xmpp(archived) = &mut archived {
+            archived.origin_id = Some("echo-id".to_string());
+            archived.stanza_id = Some("archive-id".to_string());
+        }
+
+        // When
+        let reflection = messages.deduplicate(&account, reflection);
+        let archived = messages.deduplicate(&account, archived);
+        let archived_again = messages.deduplicate(&account, archived.clone().unwrap());
+
+        // Then
+        assert!(reflection.is_none());
+        match archived {
+            Some(Message::Xmpp(archived)) => {
+                assert_eq!(archived.id, "echo-id");
+                assert_eq!(archived.stanza_id, Some("archive-id".to_string()));
+            }
+            _ => panic!("Archived copy should enrich the local echo"),
+        }
+        assert!(archived_again.is_none());
+    }
+
+    #[test]
+    fn test_get_last_failed() {
+        // Given
+        let account = FullJid::from_str("me@fariello.eu/aparte").unwrap();
+        let room = BareJid::from_str("aparte@conference.fariello.eu").unwrap();
+        let mut messages = MessagesMod::default();
+        let failed = channel_message("failed", "me@fariello.eu/needle", true);
+        let delivered = channel_message("delivered", "me@fariello.eu/needle", true);
+        messages.handle_message(&Some(account.clone()), &failed);
+        messages.handle_message(&Some(account.clone()), &delivered);
+
+        // When
+        if let Some(Message::Xmpp(failed)) = messages.find_mut(&account, &room, "failed") {
+            failed.error = Some("forbidden: You are not allowed to talk".to_string());
+        }
+
+        // Then
+        assert_eq!(
+            messages
+                .get_last_failed(&account, &room)
+                .map(|message| message.id.as_str()),
+            Some("failed")
+        );
+    }
+
+    #[test]
+    fn test_deduplicate_same_id_other_peer() {
+        // Given
+        let account = FullJid::from_str("me@fariello.eu/aparte").unwrap();
+        let mut messages = MessagesMod::default();
+        let first = channel_message("1", "aparte@conference.fariello.eu/paul", false);
+        let second = channel_message("1", "aparte@conference.fariello.eu/john", false);
+        let other_room = channel_message("1", "other@conference.fariello.eu/john", false);
+        messages.deduplicate(&account, first).unwrap();
+
+        // When
+        let second = messages.deduplicate(&account, second);
+        let other_room = messages.deduplicate(&account, other_room);
+
+        // Then
+        assert!(second.is_none());
+        assert_eq!(other_room.unwrap().id(), "1");
+    }
+}
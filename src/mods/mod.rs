@@ -1,8 +1,10 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+pub mod adhoc;
 pub mod bookmarks;
 pub mod carbons;
+pub mod chatstates;
 pub mod completion;
 pub mod contact;
 pub mod conversation;
@@ -10,5 +12,10 @@ pub mod correction;
 pub mod disco;
 pub mod mam;
 pub mod messages;
+pub mod muc;
 pub mod omemo;
+pub mod reactions;
+pub mod receipts;
+pub mod reply;
+pub mod retraction;
 pub mod ui;
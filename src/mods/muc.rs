@@ -0,0 +1,707 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+//! XEP-0045: Multi-User Chat administration
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::muc::user::{MucUser, Status};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::{Affiliation, Channel, Conversation, Role};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::mods::conversation::ConversationMod;
+
+const NS_MUC_ADMIN: &str = "http://jabber.org/protocol/muc#admin";
+const NS_MUC_OWNER: &str = "http://jabber.org/protocol/muc#owner";
+
+/// Window in which the configuration form of a channel is filled
+fn config_window(room: &BareJid) -> String {
+    format!("config:{room}")
+}
+
+/// Channel of the window a command is run in
+fn get_channel(aparte: &Aparte, command: &Command) -> Result<Channel> {
+    let account = command
+        .account
+        .clone()
+        .context("Can't administrate a channel in non XMPP window")?;
+    let jid = BareJid::from_str(&command.context)
+        .context("Can't administrate a channel in non XMPP window")?;
+    match aparte.get_mod::<ConversationMod>().get(&account, &jid) {
+        Some(Conversation::Channel(channel)) => Ok(channel.clone()),
+        _ => bail!("Not in a channel"),
+    }
+}
+
+/// Nicks of the occupants of the channel a command is run in
+fn complete_nick(aparte: &Aparte, command: &Command) -> Vec<String> {
+    match get_channel(aparte, command) {
+        Ok(channel) => channel.occupants.keys().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// JID designated by a nick of an occupant whose real JID is known, or by itself
+fn get_jid(channel: &Channel, target: &str) -> Result<BareJid> {
+    match channel.occupants.get(target) {
+        Some(occupant) => occupant
+            .jid
+            .clone()
+            .with_context(|| format!("Real JID of {target} is unknown")),
+        // Any nick is a valid JID, only accept targets that look like one
+        None if target.contains('@') => {
+            BareJid::from_str(target).with_context(|| format!("Invalid JID {target}"))
+        }
+        None => bail!("Unknown occupant {target}"),
+    }
+}
+
+command_def!(kick,
+r#"/kick <nick> [<reason>]
+
+    nick          Occupant to kick
+    reason        Reason given to the occupant
+
+Description:
+    Kick an occupant out of the current channel. Requires the moderator role.
+
+Examples:
+    /kick paul
+    /kick paul "Stop spamming""#,
+{
+    nick: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+    reason: Option<String>,
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    MucMod::set_role(aparte, &channel, nick, Role::None, reason);
+    Ok(())
+});
+
+command_def!(ban,
+r#"/ban <nick|jid> [<reason>]
+
+    nick|jid      Occupant or JID to ban
+    reason        Reason given to the occupant
+
+Description:
+    Ban a user from the current channel. Requires the admin affiliation.
+
+Examples:
+    /ban paul
+    /ban paul@fariello.eu "Stop spamming""#,
+{
+    target: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+    reason: Option<String>,
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    let jid = get_jid(&channel, &target)?;
+    MucMod::set_affiliation(aparte, &channel, jid, Affiliation::Outcast, reason);
+    Ok(())
+});
+
+command_def!(voice,
+r#"/voice <nick>
+
+    nick          Occupant allowed to speak
+
+Description:
+    Allow a visitor to speak in the current channel. Requires the moderator role.
+
+Example:
+    /voice paul"#,
+{
+    nick: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    MucMod::set_role(aparte, &channel, nick, Role::Participant, None);
+    Ok(())
+});
+
+command_def!(devoice,
+r#"/devoice <nick>
+
+    nick          Occupant not allowed to speak anymore
+
+Description:
+    Prevent an occupant from speaking in the current channel. Requires the
+    moderator role.
+
+Example:
+    /devoice paul"#,
+{
+    nick: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    MucMod::set_role(aparte, &channel, nick, Role::Visitor, None);
+    Ok(())
+});
+
+command_def!(role,
+r#"/role <nick> <role>
+
+    nick          Occupant whose role is changed
+    role          One of moderator, participant, visitor or none
+
+Description:
+    Change the role of an occupant of the current channel. Requires the
+    moderator role, or the admin affiliation to grant the moderator role.
+
+Example:
+    /role paul moderator"#,
+{
+    nick: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+    role: Role = {
+        completion: |_aparte, _command| {
+            ["moderator", "participant", "visitor", "none"].iter().map(|role| role.to_string()).collect()
+        }
+    },
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    MucMod::set_role(aparte, &channel, nick, role, None);
+    Ok(())
+});
+
+command_def!(affiliation,
+r#"/affiliation <nick|jid> <affiliation>
+
+    nick|jid      Occupant or JID whose affiliation is changed
+    affiliation   One of owner, admin, member, outcast or none
+
+Description:
+    Change the affiliation of a user with the current channel. Requires the
+    admin affiliation, or the owner affiliation to grant the admin and owner
+    affiliations.
+
+Examples:
+    /affiliation paul member
+    /affiliation paul@fariello.eu none"#,
+{
+    target: String = {
+        completion: |aparte, command| {
+            complete_nick(aparte, &command)
+        }
+    },
+    affiliation: Affiliation = {
+        completion: |_aparte, _command| {
+            ["owner", "admin", "member", "outcast", "none"].iter().map(|affiliation| affiliation.to_string()).collect()
+        }
+    },
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    let jid = get_jid(&channel, &target)?;
+    MucMod::set_affiliation(aparte, &channel, jid, affiliation, None);
+    Ok(())
+});
+
+command_def!(affiliations_list,
+r#"/affiliations list <affiliation>
+
+    affiliation   One of owner, admin, member or outcast
+
+Description:
+    List the users with the given affiliation with the current channel.
+
+Example:
+    /affiliations list member"#,
+{
+    affiliation: Affiliation,
+},
+|aparte, command| {
+    let channel = get_channel(aparte, &command)?;
+    if affiliation == Affiliation::None {
+        bail!("Users without affiliation can't be listed");
+    }
+    MucMod::list_affiliation(aparte, &channel, affiliation);
+    Ok(())
+});
+
+command_def!(affiliations,
+r#"/affiliations list"#,
+{
+    action: Command = {
+        children: {
+            "list": affiliations_list,
+        }
+    },
+});
+
+command_def!(
+    room_config,
+    r#"/room config
+
+Description:
+    Edit the configuration of the current channel in a form, see /help form.
+    Requires the owner affiliation.
+
+Example:
+    /room config"#,
+    {},
+    |aparte, command| {
+        let channel = get_channel(aparte, &command)?;
+        if let Some(occupant) = channel.occupants.get(&channel.nick) {
+            if occupant.affiliation != Affiliation::Owner {
+                bail!("Only owners can configure {}", channel.jid);
+            }
+        }
+        let mut muc = aparte.get_mod_mut::<MucMod>();
+        muc.configure(aparte, &channel.account, &channel.jid);
+        Ok(())
+    }
+);
+
+command_def!(room,
+r#"/room config"#,
+{
+    action: Command = {
+        children: {
+            "config": room_config,
+        }
+    },
+});
+
+#[derive(Default)]
+pub struct MucMod {
+    /// Channel configured in each configuration window
+    configs: HashMap<String, (Account, BareJid)>,
+}
+
+impl MucMod {
+    fn admin_iq(channel: &Channel, payload: IqType) -> Iq {
+        Self::room_iq(&channel.jid, payload)
+    }
+
+    fn room_iq(room: &BareJid, payload: IqType) -> Iq {
+        Iq {
+            from: None,
+            to: Some(Jid::from(room.clone())),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload,
+        }
+    }
+
+    fn owner_query(form: Option<DataForm>) -> Element {
+        let query = Element::builder("query", NS_MUC_OWNER);
+        match form {
+            Some(form) => query.append(Element::from(form)).build(),
+            None => query.build(),
+        }
+    }
+
+    /// Fetch the configuration form of a channel and show it to the user
+    fn configure(&mut self, aparte: &Aparte, account: &Account, room: &BareJid) {
+        let window = config_window(room);
+        self.configs
+            .insert(window.clone(), (account.clone(), room.clone()));
+        let iq = Self::room_iq(room, IqType::Get(Self::owner_query(None)));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let room = room.clone();
+            async move {
+                let form = Self::send_admin(&mut aparte, &account, iq)
+                    .await
+                    .and_then(|query| Self::parse_config(query.as_ref()));
+                match form {
+                    Ok(form) => aparte.schedule(Event::DataForm {
+                        account,
+                        window,
+                        form,
+                    }),
+                    Err(err) => aparte.schedule(Event::ChannelLog {
+                        account,
+                        conversation: room.clone(),
+                        body: format!("Can't get configuration of {room}: {err:#}"),
+                    }),
+                }
+            }
+        });
+    }
+
+    fn parse_config(query: Option<&Element>) -> Result<DataForm> {
+        let form = query
+            .and_then(|query| query.get_child("x", ns::DATA_FORMS))
+            .context("No configuration form")?;
+        Ok(DataForm::try_from(form.clone())?)
+    }
+
+    /// Send the configuration filled by the user, or cancel the configuration
+    fn submit_config(aparte: &Aparte, account: &Account, room: &BareJid, form: Option<DataForm>) {
+        let cancelled = form.is_none();
+        let form = form.unwrap_or(DataForm {
+            type_: DataFormType::Cancel,
+            form_type: None,
+            title: None,
+            instructions: None,
+            fields: vec![],
+        });
+        let iq = Self::room_iq(room, IqType::Set(Self::owner_query(Some(form))));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let room = room.clone();
+            async move {
+                let body = match Self::send_admin(&mut aparte, &account, iq).await {
+                    Ok(_) if cancelled => return,
+                    Ok(_) => format!("Configuration of {room} saved"),
+                    Err(err) => format!("Can't configure {room}: {err:#}"),
+                };
+                aparte.schedule(Event::ChannelLog {
+                    account,
+                    conversation: room,
+                    body,
+                });
+            }
+        });
+    }
+
+    /// Whether the presence tells us we just created the channel it comes from
+    fn is_room_created(muc_user: &MucUser) -> bool {
+        muc_user
+            .status
+            .iter()
+            .any(|status| matches!(status, Status::SelfPresence))
+            && muc_user
+                .status
+                .iter()
+                .any(|status| matches!(status, Status::RoomHasBeenCreated))
+    }
+
+    fn query(mut item: Element, reason: Option<String>) -> Element {
+        if let Some(reason) = reason {
+            item.append_child(
+                Element::builder("reason", NS_MUC_ADMIN)
+                    .append(reason)
+                    .build(),
+            );
+        }
+        Element::builder("query", NS_MUC_ADMIN).append(item).build()
+    }
+
+    fn set_role(
+        aparte: &Aparte,
+        channel: &Channel,
+        nick: String,
+        role: Role,
+        reason: Option<String>,
+    ) {
+        let item = Element::builder("item", NS_MUC_ADMIN)
+            .attr("nick", nick.clone())
+            .attr("role", role.as_str())
+            .build();
+        let iq = Self::admin_iq(channel, IqType::Set(Self::query(item, reason)));
+        // The channel broadcasts the new role of the occupant, nothing to show on success
+        Self::send(aparte, channel, iq, format!("Can't change role of {nick}"));
+    }
+
+    fn set_affiliation(
+        aparte: &Aparte,
+        channel: &Channel,
+        jid: BareJid,
+        affiliation: Affiliation,
+        reason: Option<String>,
+    ) {
+        let item = Element::builder("item", NS_MUC_ADMIN)
+            .attr("jid", jid.to_string())
+            .attr("affiliation", affiliation.as_str())
+            .build();
+        let iq = Self::admin_iq(channel, IqType::Set(Self::query(item, reason)));
+        Self::send(
+            aparte,
+            channel,
+            iq,
+            format!("Can't change affiliation of {jid}"),
+        );
+    }
+
+    fn list_affiliation(aparte: &Aparte, channel: &Channel, affiliation: Affiliation) {
+        let item = Element::builder("item", NS_MUC_ADMIN)
+            .attr("affiliation", affiliation.as_str())
+            .build();
+        let iq = Self::admin_iq(channel, IqType::Get(Self::query(item, None)));
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = channel.account.clone();
+            let room = channel.jid.clone();
+            async move {
+                let body = match Self::send_admin(&mut aparte, &account, iq).await {
+                    Ok(Some(query)) => {
+                        let users = Self::parse_users(&query);
+                        if users.is_empty() {
+                            format!("No {} in {room}", affiliation.as_str())
+                        } else {
+                            format!(
+                                "Users with {} affiliation in {room}:\n{}",
+                                affiliation.as_str(),
+                                users.join("\n")
+                            )
+                        }
+                    }
+                    Ok(None) => format!("No {} in {room}", affiliation.as_str()),
+                    Err(err) => format!("Can't list {} of {room}: {err:#}", affiliation.as_str()),
+                };
+                aparte.schedule(Event::ChannelLog {
+                    account,
+                    conversation: room,
+                    body,
+                });
+            }
+        });
+    }
+
+    fn send(aparte: &Aparte, channel: &Channel, iq: Iq, error: String) {
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = channel.account.clone();
+            let room = channel.jid.clone();
+            async move {
+                if let Err(err) = Self::send_admin(&mut aparte, &account, iq).await {
+                    aparte.schedule(Event::ChannelLog {
+                        account,
+                        conversation: room,
+                        body: format!("{error}: {err:#}"),
+                    });
+                }
+            }
+        });
+    }
+
+    async fn send_admin(
+        aparte: &mut AparteAsync,
+        account: &Account,
+        iq: Iq,
+    ) -> Result<Option<Element>> {
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(payload) => Ok(payload),
+            IqType::Error(err) => {
+                let (_, text) = i18n::xmpp_err_to_string(&err, aparte.config.prefered_langs());
+                Err(anyhow!("{}", terminus::clean_str(&text)))
+            }
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+
+    /// Users listed in a muc#admin query result, with their nick when known
+    fn parse_users(query: &Element) -> Vec<String> {
+        query
+            .children()
+            .filter(|item| item.is("item", NS_MUC_ADMIN))
+            .filter_map(|item| {
+                let jid = BareJid::from_str(item.attr("jid")?).ok()?;
+                Some(match item.attr("nick") {
+                    Some(nick) => format!("{jid} ({})", terminus::clean_str(nick)),
+                    None => jid.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ModTrait for MucMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(kick::new());
+        aparte.add_command(ban::new());
+        aparte.add_command(voice::new());
+        aparte.add_command(devoice::new());
+        aparte.add_command(role::new());
+        aparte.add_command(affiliation::new());
+        aparte.add_command(affiliations::new());
+        aparte.add_command(room::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Presence(account, presence) => {
+                let created = presence
+                    .payloads
+                    .iter()
+                    .filter_map(|payload| MucUser::try_from(payload.clone()).ok())
+                    .any(|muc_user| Self::is_room_created(&muc_user));
+                if let (true, Some(from)) = (created, &presence.from) {
+                    // The channel stays locked until its configuration is submitted
+                    let room = from.to_bare();
+                    aparte.schedule(Event::ChannelLog {
+                        account: account.clone(),
+                        conversation: room.clone(),
+                        body: format!(
+                            "{room} has been created, submit its configuration to open it"
+                        ),
+                    });
+                    self.configure(aparte, account, &room);
+                }
+            }
+            Event::DataFormSubmit {
+                account,
+                window,
+                form,
+            } => {
+                if let Some((config_account, room)) = self.configs.remove(window) {
+                    if &config_account == account {
+                        Self::submit_config(aparte, account, &room, form.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for MucMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0045: Multi-User Chat administration")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Occupant;
+
+    #[test]
+    fn test_parse_users() {
+        // Given
+        let query: Element = r#"<query xmlns="http://jabber.org/protocol/muc#admin">
+            <item affiliation="member" jid="paul@fariello.eu" nick="paul" role="participant"/>
+            <item affiliation="member" jid="needle@fariello.eu"/>
+        </query>"#
+            .parse()
+            .unwrap();
+
+        // When
+        let users = MucMod::parse_users(&query);
+
+        // Then
+        assert_eq!(
+            users,
+            vec![
+                "paul@fariello.eu (paul)".to_string(),
+                "needle@fariello.eu".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_users_sanitized() {
+        // Given
+        let query = Element::builder("query", NS_MUC_ADMIN)
+            .append(
+                Element::builder("item", NS_MUC_ADMIN)
+                    .attr("jid", "paul@fariello.eu")
+                    .attr("nick", "paul\x1b[2J")
+                    .build(),
+            )
+            .append(
+                Element::builder("item", NS_MUC_ADMIN)
+                    .attr("jid", "")
+                    .build(),
+            )
+            .build();
+
+        // When
+        let users = MucMod::parse_users(&query);
+
+        // Then
+        assert_eq!(users, vec!["paul@fariello.eu (paul)".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_config() {
+        // Given
+        let query: Element = r#"<query xmlns="http://jabber.org/protocol/muc#owner">
+            <x xmlns="jabber:x:data" type="form">
+                <title>Configuration for aparte@conference.fariello.eu</title>
+                <field var="FORM_TYPE" type="hidden"><value>http://jabber.org/protocol/muc#roomconfig</value></field>
+                <field var="muc#roomconfig_roomname" type="text-single" label="Name"><value>Aparté</value></field>
+            </x>
+        </query>"#
+            .parse()
+            .unwrap();
+
+        // When
+        let form = MucMod::parse_config(Some(&query)).unwrap();
+        let missing = MucMod::parse_config(None);
+
+        // Then
+        assert_eq!(
+            form.form_type,
+            Some(String::from("http://jabber.org/protocol/muc#roomconfig"))
+        );
+        assert_eq!(form.fields.len(), 1);
+        assert_eq!(form.fields[0].values, vec![String::from("Aparté")]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_get_jid() {
+        // Given
+        let mut channel = Channel {
+            account: Account::from_str("me@fariello.eu/aparte").unwrap(),
+            jid: BareJid::from_str("aparte@conference.fariello.eu").unwrap(),
+            nick: "needle".to_string(),
+            name: None,
+            occupants: HashMap::new(),
+        };
+        channel.occupants.insert(
+            "paul".to_string(),
+            Occupant {
+                nick: "paul".to_string(),
+                jid: Some(BareJid::from_str("paul@fariello.eu").unwrap()),
+                affiliation: Affiliation::Member,
+                role: Role::Participant,
+            },
+        );
+
+        // When
+        let occupant = get_jid(&channel, "paul");
+        let jid = get_jid(&channel, "john@fariello.eu");
+        let unknown = get_jid(&channel, "john");
+
+        // Then
+        assert_eq!(
+            occupant.unwrap(),
+            BareJid::from_str("paul@fariello.eu").unwrap()
+        );
+        assert_eq!(jid.unwrap(), BareJid::from_str("john@fariello.eu").unwrap());
+        assert!(unknown.is_err());
+    }
+}
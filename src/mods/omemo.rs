@@ -315,7 +315,7 @@ impl CryptoEngineTrait for OmemoEngine {
         let dek = Aes128Gcm::generate_key(OsRng);
 
         let cipher = Aes128Gcm::new(&dek);
-        let body = message.get_last_body();
+        let body = message.get_last_body(aparte.config.prefered_langs());
         let encrypted = cipher
             .encrypt(&nonce, body.as_bytes())
             .map_err(|e| anyhow!("{e}"))?;
@@ -0,0 +1,340 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::hints::Store;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::Conversation;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message, VersionedXmppMessage, XmppMessageType};
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco;
+use crate::mods::messages::MessagesMod;
+use crate::mods::ui::UIMod;
+
+const NS_REACTIONS: &str = "urn:xmpp:reactions:0";
+
+command_def!(react,
+r#"/react <emoji>
+
+    emoji         Reaction to add, or to remove if already sent
+
+Description:
+    React to the selected message, or to the last message of the current
+    conversation if none is selected. Select a message with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /react 👍"#,
+{
+    emoji: String,
+},
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .context("Can't react in non XMPP window")?;
+    let peer = BareJid::from_str(&command.context)
+        .context("Can't react in non XMPP window")?;
+
+    let selected = aparte.get_mod_mut::<UIMod>().get_selection();
+    let message = {
+        let messages = aparte.get_mod::<MessagesMod>();
+        match selected {
+            Some(selected) => match messages.get(&Some(account.clone()), &peer, selected.id()) {
+                Some(Message::Xmpp(message)) => message.clone(),
+                _ => bail!("Only XMPP messages can be reacted to"),
+            },
+            None => messages
+                .get_last(&account, &peer)
+                .cloned()
+                .context("No message to react to")?,
+        }
+    };
+
+    let mut reactions = aparte.get_mod_mut::<ReactionsMod>();
+    reactions.react(aparte, &account, &message, emoji)
+});
+
+#[derive(Default)]
+pub struct ReactionsMod {
+    /// Reactions received before the message they target, indexed by account, conversation
+    /// and target id
+    pending: HashMap<(Account, BareJid, String), HashMap<String, Vec<String>>>,
+}
+
+impl ReactionsMod {
+    fn parse(message: &XmppParsersMessage) -> Option<(String, Vec<String>)> {
+        let reactions = message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("reactions", NS_REACTIONS))?;
+        let id = reactions.attr("id")?.to_string();
+        let mut emojis = Vec::new();
+        for reaction in reactions.children() {
+            if reaction.is("reaction", NS_REACTIONS) {
+                let emoji = reaction.text();
+                if !emoji.is_empty() && !emojis.contains(&emoji) {
+                    emojis.push(emoji);
+                }
+            }
+        }
+
+        Some((id, emojis))
+    }
+
+    /// Name under which reactions of the sender of a message are indexed: its nick in
+    /// channels, its bare JID otherwise
+    fn get_author(message: &XmppParsersMessage) -> Option<String> {
+        let from = message.from.as_ref()?;
+        match message.type_ {
+            MessageType::Groupchat => Some(from.try_as_full().ok()?.resource().to_string()),
+            _ => Some(from.to_bare().to_string()),
+        }
+    }
+
+    fn apply(message: &mut VersionedXmppMessage, author: String, emojis: Vec<String>) -> Message {
+        // Each update carries the full set of reactions of its author, an empty set is kept so
+        // that storage forgets the previous one
+        message.reactions.insert(author, emojis);
+        Message::Xmpp(message.clone())
+    }
+
+    fn handle_reactions(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) {
+        let (Some((id, emojis)), Some(author), Ok(peer)) = (
+            Self::parse(message),
+            Self::get_author(message),
+            Message::get_local_destination_from_xmpp(account, message),
+        ) else {
+            return;
+        };
+        let peer = peer.to_bare();
+
+        let updated = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            match messages.find_mut(account, &peer, &id) {
+                Some(Message::Xmpp(target)) => Some(Self::apply(target, author, emojis)),
+                Some(Message::Log(_)) => None,
+                None => {
+                    self.pending
+                        .entry((account.clone(), peer, id))
+                        .or_default()
+                        .insert(author, emojis);
+                    None
+                }
+            }
+        };
+
+        if let Some(updated) = updated {
+            aparte.schedule(Event::Message(Some(account.clone()), updated));
+        }
+    }
+
+    /// Apply reactions received before the message they target
+    fn handle_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) {
+        let peer = match message.direction {
+            Direction::Incoming => message.from.clone(),
+            Direction::Outgoing => message.to.clone(),
+        };
+        let pending = [
+            Some(&message.id),
+            message.stanza_id.as_ref(),
+            message.origin_id.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        .find_map(|&id| {
+            self.pending
+                .remove(&(account.clone(), peer.clone(), id.clone()))
+        });
+
+        if let Some(pending) = pending {
+            let mut message = message.clone();
+            for (author, emojis) in pending {
+                Self::apply(&mut message, author, emojis);
+            }
+            aparte.schedule(Event::Message(
+                Some(account.clone()),
+                Message::Xmpp(message),
+            ));
+        }
+    }
+
+    fn react(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+        emoji: String,
+    ) -> Result<()> {
+        if message.retraction.is_some() {
+            bail!("Can't react to a retracted message");
+        }
+
+        let peer = match message.direction {
+            Direction::Incoming => message.from.clone(),
+            Direction::Outgoing => message.to.clone(),
+        };
+        let (type_, id, author) = match message.type_ {
+            XmppMessageType::Chat => {
+                let id = match message.direction {
+                    Direction::Incoming => message.id.clone(),
+                    Direction::Outgoing => message.origin_id.clone().unwrap_or(message.id.clone()),
+                };
+                (MessageType::Chat, id, account.to_bare().to_string())
+            }
+            XmppMessageType::Channel => {
+                // Channels reference messages by the stanza-id they assigned
+                let id = message
+                    .stanza_id
+                    .clone()
+                    .context("Message not acknowledged by the channel yet")?;
+                let nick = match aparte.get_mod::<ConversationMod>().get(account, &peer) {
+                    Some(Conversation::Channel(channel)) => channel.nick.clone(),
+                    _ => bail!("Not in channel {peer}"),
+                };
+                (MessageType::Groupchat, id, nick)
+            }
+        };
+
+        let mut emojis = message.reactions.get(&author).cloned().unwrap_or_default();
+        match emojis.iter().position(|known| known == &emoji) {
+            Some(index) => {
+                emojis.remove(index);
+            }
+            None => emojis.push(emoji),
+        }
+
+        let mut reactions = Element::builder("reactions", NS_REACTIONS).attr("id", id);
+        for emoji in emojis.iter() {
+            reactions = reactions.append(
+                Element::builder("reaction", NS_REACTIONS)
+                    .append(emoji.as_str())
+                    .build(),
+            );
+        }
+
+        let mut stanza = XmppParsersMessage::new(Some(Jid::from(peer)));
+        stanza.id = Some(Uuid::new_v4().to_string());
+        stanza.type_ = type_;
+        stanza.payloads.push(reactions.build());
+        stanza.payloads.push(Store.into());
+        let mut aparte = aparte.proxy();
+        aparte.send(account, stanza.into());
+
+        let mut message = message.clone();
+        let updated = Self::apply(&mut message, author, emojis);
+        aparte.schedule(Event::Message(Some(account.clone()), updated));
+
+        Ok(())
+    }
+}
+
+impl ModTrait for ReactionsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(react::new());
+
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(NS_REACTIONS);
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        if message
+            .payloads
+            .iter()
+            .any(|payload| payload.is("reactions", NS_REACTIONS))
+        {
+            1f64
+        } else {
+            0f64
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        _archive: bool,
+    ) {
+        self.handle_reactions(aparte, account, message);
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if let Event::Message(Some(account), Message::Xmpp(message)) = event {
+            if !self.pending.is_empty() {
+                self.handle_message(aparte, account, message);
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReactionsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0444: Message Reactions")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_reactions() {
+        // Given
+        let message: Element = r#"<message xmlns="jabber:client" from="aparte@conference.fariello.eu/paul" type="groupchat">
+            <reactions xmlns="urn:xmpp:reactions:0" id="stanza-id">
+                <reaction>👍</reaction>
+                <reaction>🐢</reaction>
+                <reaction>👍</reaction>
+            </reactions>
+        </message>"#
+            .parse()
+            .unwrap();
+        let message = XmppParsersMessage::try_from(message).unwrap();
+
+        // When
+        let reactions = ReactionsMod::parse(&message);
+        let author = ReactionsMod::get_author(&message);
+
+        // Then
+        assert_eq!(
+            reactions,
+            Some((
+                "stanza-id".to_string(),
+                vec!["👍".to_string(), "🐢".to_string()]
+            ))
+        );
+        assert_eq!(author, Some("paul".to_string()));
+    }
+}
@@ -0,0 +1,267 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::hints::Store;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType};
+use xmpp_parsers::roster::Subscription;
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{
+    DeliveryState, Direction, Message, VersionedXmppMessage, XmppMessageType, NS_CHAT_MARKERS,
+};
+use crate::mods::contact::ContactMod;
+use crate::mods::disco;
+use crate::mods::messages::MessagesMod;
+
+#[derive(Default)]
+pub struct ReceiptsMod {
+    /// Messages for which a delivery receipt has already been sent
+    acknowledged: HashSet<(Account, String)>,
+    /// Timestamp of the last message marked as displayed in each conversation
+    displayed: HashMap<(Account, BareJid), DateTime<FixedOffset>>,
+}
+
+impl ReceiptsMod {
+    /// Receipt or marker carried by a message, with the id of the message it refers to. Markers
+    /// also apply to every previous message.
+    fn parse(message: &XmppParsersMessage) -> Option<(String, DeliveryState, bool)> {
+        message.payloads.iter().find_map(|payload| {
+            let state = if payload.is("received", ns::RECEIPTS) {
+                (DeliveryState::Delivered, false)
+            } else if payload.is("received", NS_CHAT_MARKERS) {
+                (DeliveryState::Delivered, true)
+            } else if payload.is("displayed", NS_CHAT_MARKERS)
+                || payload.is("acknowledged", NS_CHAT_MARKERS)
+            {
+                (DeliveryState::Displayed, true)
+            } else {
+                return None;
+            };
+            let id = payload.attr("id")?.to_string();
+            Some((id, state.0, state.1))
+        })
+    }
+
+    /// Receipts and markers leak our presence, only send them to contacts allowed to see it
+    fn is_allowed(aparte: &mut Aparte, account: &Account, contact: &BareJid) -> bool {
+        match aparte.get_mod::<ContactMod>().get(account, contact) {
+            Some(contact) => matches!(
+                contact.subscription,
+                Subscription::From | Subscription::Both
+            ),
+            None => false,
+        }
+    }
+
+    fn update_delivery(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        peer: &BareJid,
+        id: &str,
+        state: DeliveryState,
+        cumulative: bool,
+    ) {
+        let updated = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            let (target, timestamp) = match messages.find(account, peer, id) {
+                Some(Message::Xmpp(target)) => {
+                    (target.id.clone(), *target.get_original_timestamp())
+                }
+                _ => return,
+            };
+
+            messages
+                .get_outgoing_mut(account, peer)
+                .filter(|message| match cumulative {
+                    true => message.get_original_timestamp() <= &timestamp,
+                    false => message.id == target,
+                })
+                .filter(|message| matches!(message.delivery, Some(delivery) if delivery < state))
+                .map(|message| {
+                    message.delivery = Some(state);
+                    Message::Xmpp(message.clone())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for message in updated {
+            aparte.schedule(Event::Message(Some(account.clone()), message));
+        }
+    }
+
+    fn send_receipt(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) {
+        if !aparte.config.receipts
+            || !Self::is_allowed(aparte, account, &message.from)
+            || !self
+                .acknowledged
+                .insert((account.clone(), message.id.clone()))
+        {
+            return;
+        }
+
+        let mut receipt = XmppParsersMessage::new(Some(message.from_full.clone()));
+        receipt.id = Some(Uuid::new_v4().to_string());
+        receipt.type_ = MessageType::Chat;
+        receipt.payloads.push(
+            Element::builder("received", ns::RECEIPTS)
+                .attr("id", message.id.clone())
+                .build(),
+        );
+        receipt.payloads.push(Store.into());
+        aparte.send(account, receipt);
+    }
+
+    fn send_displayed(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) {
+        if !aparte.config.displayed_markers || !Self::is_allowed(aparte, account, &message.from) {
+            return;
+        }
+
+        let timestamp = *message.get_original_timestamp();
+        match self.displayed.get(&(account.clone(), message.from.clone())) {
+            Some(displayed) if displayed >= &timestamp => return,
+            _ => {}
+        }
+        self.displayed
+            .insert((account.clone(), message.from.clone()), timestamp);
+
+        let mut marker = XmppParsersMessage::new(Some(Jid::from(message.from.clone())));
+        marker.id = Some(Uuid::new_v4().to_string());
+        marker.type_ = MessageType::Chat;
+        marker.payloads.push(
+            Element::builder("displayed", NS_CHAT_MARKERS)
+                .attr("id", message.id.clone())
+                .build(),
+        );
+        marker.payloads.push(Store.into());
+        aparte.send(account, marker);
+    }
+}
+
+impl ModTrait for ReceiptsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(ns::RECEIPTS);
+        disco.add_feature(NS_CHAT_MARKERS);
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match Self::parse(message) {
+            Some(_) if message.bodies.is_empty() => 1f64,
+            _ => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        _archive: bool,
+    ) {
+        // Markers sent by our other clients and channel markers are not tracked
+        if message.type_ == MessageType::Groupchat
+            || Message::get_direction_from_xmpp(account, message) != Ok(Direction::Incoming)
+        {
+            return;
+        }
+
+        if let (Some((id, state, cumulative)), Some(from)) = (Self::parse(message), &message.from) {
+            self.update_delivery(aparte, account, &from.to_bare(), &id, state, cumulative);
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(Some(account), Message::Xmpp(message))
+                if message.direction == Direction::Incoming
+                    && message.type_ == XmppMessageType::Chat
+                    && message.receipt_request
+                    && !message.archive =>
+            {
+                self.send_receipt(aparte, account, message);
+            }
+            Event::Displayed {
+                account,
+                message: Message::Xmpp(message),
+            } if message.direction == Direction::Incoming
+                && message.type_ == XmppMessageType::Chat
+                && message.markable =>
+            {
+                self.send_displayed(aparte, account, message);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ReceiptsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0184: Message Delivery Receipts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_receipts_and_markers() {
+        // Given
+        let receipt: Element =
+            r#"<message xmlns="jabber:client" from="contact@example.org/phone" type="chat">
+            <received xmlns="urn:xmpp:receipts" id="message-1"/>
+        </message>"#
+                .parse()
+                .unwrap();
+        let marker: Element =
+            r#"<message xmlns="jabber:client" from="contact@example.org/phone" type="chat">
+            <displayed xmlns="urn:xmpp:chat-markers:0" id="message-2"/>
+        </message>"#
+                .parse()
+                .unwrap();
+
+        // When
+        let receipt = ReceiptsMod::parse(&XmppParsersMessage::try_from(receipt).unwrap());
+        let marker = ReceiptsMod::parse(&XmppParsersMessage::try_from(marker).unwrap());
+
+        // Then
+        assert_eq!(
+            receipt,
+            Some(("message-1".to_string(), DeliveryState::Delivered, false))
+        );
+        assert_eq!(
+            marker,
+            Some(("message-2".to_string(), DeliveryState::Displayed, true))
+        );
+    }
+}
@@ -0,0 +1,165 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::NS_REPLY;
+use crate::mods::disco;
+
+mod reply {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use anyhow::{bail, Context, Result};
+    use chrono::Local as LocalTz;
+    use uuid::Uuid;
+    use xmpp_parsers::{BareJid, Jid};
+
+    use crate::account::Account;
+    use crate::command::{Command, CommandParser};
+    use crate::conversation::Conversation;
+    use crate::core::{Aparte, Event};
+    use crate::message::{Direction, Message, Reply, XmppMessageType};
+    use crate::mods::conversation::ConversationMod;
+    use crate::mods::messages::MessagesMod;
+    use crate::mods::ui::UIMod;
+
+    fn parse(account: &Option<Account>, context: &str, buf: &str) -> Result<Command> {
+        let body = buf.strip_prefix("/reply").unwrap_or(buf).trim_start();
+        Ok(Command {
+            account: account.clone(),
+            context: context.to_string(),
+            args: vec![body.to_string()],
+            cursor: 0,
+        })
+    }
+
+    fn exec(aparte: &mut Aparte, command: Command) -> Result<()> {
+        let account = command
+            .account
+            .context("Can't use /reply in non XMPP window")?;
+        let peer =
+            BareJid::from_str(&command.context).context("Can't use /reply in non XMPP window")?;
+        let body = command.args[0].clone();
+        if body.is_empty() {
+            bail!("Missing reply");
+        }
+
+        let selected = aparte.get_mod_mut::<UIMod>().get_selection();
+        let target = {
+            let messages = aparte.get_mod::<MessagesMod>();
+            match selected {
+                Some(selected) => {
+                    match messages.get(&Some(account.clone()), &peer, selected.id()) {
+                        Some(Message::Xmpp(message)) => message.clone(),
+                        _ => bail!("Only XMPP messages can be replied to"),
+                    }
+                }
+                None => messages
+                    .get_last(&account, &peer)
+                    .cloned()
+                    .context("No message to reply to")?,
+            }
+        };
+
+        let conversation = aparte
+            .get_mod::<ConversationMod>()
+            .get(&account, &peer)
+            .cloned()
+            .context("Unknown conversation")?;
+
+        let id = Uuid::new_v4().to_string();
+        let timestamp = LocalTz::now().into();
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), body);
+        let (mut message, reply) = match (conversation, &target.type_) {
+            (Conversation::Chat(chat), XmppMessageType::Chat) => {
+                let from: Jid = account.clone().into();
+                let to: Jid = chat.contact.clone().into();
+                let reply = Reply {
+                    to: Some(target.from.clone().into()),
+                    id: match target.direction {
+                        Direction::Incoming => target.id.clone(),
+                        Direction::Outgoing => {
+                            target.origin_id.clone().unwrap_or(target.id.clone())
+                        }
+                    },
+                    preview: Some(target.get_preview(aparte.config.prefered_langs())),
+                };
+                let message =
+                    Message::outgoing_chat(id, timestamp, &from, &to, bodies, None, false);
+                (message, reply)
+            }
+            (Conversation::Channel(channel), XmppMessageType::Channel) => {
+                let from: Jid = account
+                    .to_bare()
+                    .with_resource_str(&channel.nick)
+                    .context("Invalid nick")?
+                    .into();
+                let to: Jid = channel.jid.clone().into();
+                // Channels reference messages by the stanza-id they assigned
+                let reply = Reply {
+                    to: Some(target.from_full.clone()),
+                    id: target
+                        .stanza_id
+                        .clone()
+                        .context("Message not acknowledged by the channel yet")?,
+                    preview: Some(target.get_preview(aparte.config.prefered_langs())),
+                };
+                let message =
+                    Message::outgoing_channel(id, timestamp, &from, &to, bodies, None, false);
+                (message, reply)
+            }
+            _ => bail!("Message doesn't belong to this conversation"),
+        };
+
+        if let Message::Xmpp(message) = &mut message {
+            message.reply = Some(reply);
+        }
+        aparte.schedule(Event::SendMessage(account, message));
+        Ok(())
+    }
+
+    pub fn new() -> CommandParser {
+        CommandParser {
+            name: "reply",
+            help: r#"/reply <message>
+
+    message       Reply
+
+Description:
+    Reply to the selected message, or to the last message of the current
+    conversation if none is selected. Select a message with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /reply I agree"#
+                .to_string(),
+            parse,
+            exec,
+            autocompletions: vec![],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ReplyMod {}
+
+impl ModTrait for ReplyMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(reply::new());
+
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(NS_REPLY);
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for ReplyMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0461: Message Replies")
+    }
+}
@@ -0,0 +1,451 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use uuid::Uuid;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::{Conversation, Role};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::i18n;
+use crate::message::{
+    DeliveryState, Direction, Message, Retraction, VersionedXmppMessage, XmppMessageType,
+    NS_RETRACT,
+};
+use crate::mods::conversation::ConversationMod;
+use crate::mods::disco;
+use crate::mods::messages::MessagesMod;
+use crate::mods::ui::UIMod;
+
+const NS_MODERATE: &str = "urn:xmpp:message-moderate:1";
+
+command_def!(
+    retract,
+    r#"/retract
+
+Description:
+    Retract the selected message, or the last message sent in the current
+    conversation if none is selected. Select a message with Ctrl+Up/Ctrl+Down.
+
+Example:
+    /retract"#,
+    {},
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .context("Can't retract in non XMPP window")?;
+        let peer =
+            BareJid::from_str(&command.context).context("Can't retract in non XMPP window")?;
+
+        let selected = aparte.get_mod_mut::<UIMod>().get_selection();
+        let message = {
+            let messages = aparte.get_mod::<MessagesMod>();
+            match selected {
+                Some(selected) => {
+                    match messages.get(&Some(account.clone()), &peer, selected.id()) {
+                        Some(Message::Xmpp(message)) => message.clone(),
+                        _ => bail!("Only XMPP messages can be retracted"),
+                    }
+                }
+                None => messages
+                    .get_last_outgoing(&account, &peer)
+                    .cloned()
+                    .context("No message to retract")?,
+            }
+        };
+
+        let mut retraction = aparte.get_mod_mut::<RetractionMod>();
+        retraction.retract(aparte, &account, &message)
+    }
+);
+
+command_def!(moderate,
+r#"/moderate <id> [<reason>]
+
+    id            Id of the message to remove
+    reason        Reason given to the occupants
+
+Description:
+    Remove a message from the current channel, for everyone. Requires the
+    moderator role.
+
+Examples:
+    /moderate 5f1fe0a6-8a47-4c15-a3e8-4e5a7a7e2f51
+    /moderate 5f1fe0a6-8a47-4c15-a3e8-4e5a7a7e2f51 Spam"#,
+{
+    id: String,
+    reason: Option<String>,
+},
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .context("Can't moderate in non XMPP window")?;
+    let room = BareJid::from_str(&command.context)
+        .context("Can't moderate in non XMPP window")?;
+
+    let channel = match aparte.get_mod::<ConversationMod>().get(&account, &room) {
+        Some(Conversation::Channel(channel)) => channel.clone(),
+        _ => bail!("Messages can only be moderated in channels"),
+    };
+    match channel.occupants.get(&channel.nick) {
+        Some(occupant) if occupant.role == Role::Moderator => {}
+        _ => bail!("You are not a moderator of {room}"),
+    }
+
+    // The room only knows its own stanza-ids, unknown ids are assumed to be one of them
+    let stanza_id = match aparte.get_mod::<MessagesMod>().find(&account, &room, &id) {
+        Some(Message::Xmpp(message)) => message
+            .stanza_id
+            .clone()
+            .context("This message has no stanza-id and can't be moderated")?,
+        _ => id,
+    };
+
+    let retraction = aparte.get_mod::<RetractionMod>();
+    retraction.moderate(aparte, &account, &room, stanza_id, reason);
+    Ok(())
+});
+
+/// Retraction received for a message we don't know yet, for example when history is
+/// retrieved newest first.
+struct PendingRetraction {
+    from: Jid,
+    retraction: Retraction,
+}
+
+#[derive(Default)]
+pub struct RetractionMod {
+    /// Pending retractions indexed by account, conversation and retracted id
+    pending: HashMap<(Account, BareJid, String), PendingRetraction>,
+}
+
+impl RetractionMod {
+    fn nick(jid: &Jid) -> Option<String> {
+        jid.try_as_full()
+            .ok()
+            .map(|full| full.resource().to_string())
+    }
+
+    /// Only the author of a message can retract it, or the room itself when moderating
+    fn is_allowed(original: &VersionedXmppMessage, from: &Jid, retraction: &Retraction) -> bool {
+        match (&original.type_, &retraction.by) {
+            (XmppMessageType::Channel, Some(_)) => {
+                Self::nick(from).is_none() && from.to_bare() == original.from
+            }
+            (XmppMessageType::Channel, None) => {
+                Self::nick(from).is_some() && Self::nick(from) == Self::nick(&original.from_full)
+            }
+            (XmppMessageType::Chat, _) => from.to_bare() == original.from,
+        }
+    }
+
+    fn parse(message: &XmppParsersMessage) -> Option<(String, Retraction)> {
+        let retract = message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("retract", NS_RETRACT))?;
+        let id = retract.attr("id")?.to_string();
+        let by = retract
+            .get_child("moderated", NS_MODERATE)
+            .map(|moderated| {
+                moderated
+                    .attr("by")
+                    .and_then(|by| Jid::from_str(by).ok())
+                    .and_then(|by| Self::nick(&by))
+                    .unwrap_or("a moderator".to_string())
+            });
+        let reason = retract
+            .get_child("reason", NS_RETRACT)
+            .map(|reason| reason.text())
+            .filter(|reason| !reason.is_empty());
+
+        Some((id, Retraction { by, reason }))
+    }
+
+    fn handle_retraction(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        id: String,
+        retraction: Retraction,
+    ) {
+        let (Some(from), Ok(peer)) = (
+            message.from.clone(),
+            Message::get_local_destination_from_xmpp(account, message),
+        ) else {
+            return;
+        };
+        let peer = peer.to_bare();
+
+        let event = {
+            let mut messages = aparte.get_mod_mut::<MessagesMod>();
+            match messages.find_mut(account, &peer, &id) {
+                Some(Message::Xmpp(original)) => {
+                    if original.retraction.is_some() {
+                        None
+                    } else if Self::is_allowed(original, &from, &retraction) {
+                        original.retract(retraction);
+                        Some(Event::Message(
+                            Some(account.clone()),
+                            Message::Xmpp(original.clone()),
+                        ))
+                    } else {
+                        log::warn!("Ignoring retraction of {} from {}", id, from);
+                        None
+                    }
+                }
+                Some(Message::Log(_)) => None,
+                None => {
+                    self.pending.insert(
+                        (account.clone(), peer, id),
+                        PendingRetraction { from, retraction },
+                    );
+                    None
+                }
+            }
+        };
+
+        if let Some(event) = event {
+            aparte.schedule(event);
+        }
+    }
+
+    /// Apply a retraction received before the message it targets
+    fn handle_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) {
+        if message.retraction.is_some() {
+            return;
+        }
+
+        let peer = match message.direction {
+            Direction::Incoming => message.from.clone(),
+            Direction::Outgoing => message.to.clone(),
+        };
+        let pending = [
+            Some(&message.id),
+            message.stanza_id.as_ref(),
+            message.origin_id.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        .find_map(|&id| {
+            self.pending
+                .remove(&(account.clone(), peer.clone(), id.clone()))
+        });
+
+        if let Some(PendingRetraction { from, retraction }) = pending {
+            if Self::is_allowed(message, &from, &retraction) {
+                let mut message = message.clone();
+                message.retract(retraction);
+                aparte.schedule(Event::Message(
+                    Some(account.clone()),
+                    Message::Xmpp(message),
+                ));
+            }
+        }
+    }
+
+    fn retract(
+        &mut self,
+        aparte: &Aparte,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) -> Result<()> {
+        if message.retraction.is_some() {
+            bail!("Message already retracted");
+        }
+
+        match message.type_ {
+            XmppMessageType::Chat => {
+                if message.direction != Direction::Outgoing {
+                    bail!("Only our own messages can be retracted");
+                }
+            }
+            XmppMessageType::Channel => {
+                let room = match message.direction {
+                    Direction::Incoming => message.from.clone(),
+                    Direction::Outgoing => message.to.clone(),
+                };
+                let ours = match aparte.get_mod::<ConversationMod>().get(account, &room) {
+                    Some(Conversation::Channel(channel)) => {
+                        Self::nick(&message.from_full).as_ref() == Some(&channel.nick)
+                    }
+                    _ => false,
+                };
+                if !ours {
+                    bail!("Only our own messages can be retracted");
+                }
+                // Channels reference messages by the stanza-id they assigned
+                if message.stanza_id.is_none() {
+                    bail!("Message not acknowledged by the channel yet");
+                }
+            }
+        }
+        if message.delivery == Some(DeliveryState::Pending) {
+            bail!("Message hasn't been sent yet");
+        }
+
+        // Sent as a retraction, through the outbox when offline
+        let mut retracted = message.clone();
+        retracted.retract(Retraction {
+            by: None,
+            reason: None,
+        });
+        let mut aparte = aparte.proxy();
+        aparte.schedule(Event::SendMessage(
+            account.clone(),
+            Message::Xmpp(retracted),
+        ));
+
+        Ok(())
+    }
+
+    fn moderate(
+        &self,
+        aparte: &Aparte,
+        account: &Account,
+        room: &BareJid,
+        stanza_id: String,
+        reason: Option<String>,
+    ) {
+        let mut moderate = Element::builder("moderate", NS_MODERATE)
+            .attr("id", stanza_id)
+            .append(Element::builder("retract", NS_RETRACT).build());
+        if let Some(reason) = reason {
+            moderate = moderate.append(
+                Element::builder("reason", NS_MODERATE)
+                    .append(reason)
+                    .build(),
+            );
+        }
+
+        let iq = Iq {
+            from: None,
+            to: Some(Jid::from(room.clone())),
+            id: Uuid::new_v4().hyphenated().to_string(),
+            payload: IqType::Set(moderate.build()),
+        };
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            let room = room.clone();
+            async move {
+                if let Err(err) = Self::send_moderation(&mut aparte, &account, iq).await {
+                    crate::error!(aparte, err, "Can't moderate message in {}", room);
+                }
+            }
+        });
+    }
+
+    async fn send_moderation(aparte: &mut AparteAsync, account: &Account, iq: Iq) -> Result<()> {
+        // The room notifies every occupant, including us, once the message is removed
+        match aparte.iq(account, iq).await?.payload {
+            IqType::Result(_) => Ok(()),
+            IqType::Error(err) => Err(anyhow!("{}", i18n::xmpp_err_to_string(&err, vec![]).1)),
+            _ => Err(anyhow!("invalid response")),
+        }
+    }
+}
+
+impl ModTrait for RetractionMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(retract::new());
+        aparte.add_command(moderate::new());
+
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(NS_RETRACT);
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        if message
+            .payloads
+            .iter()
+            .any(|payload| payload.is("retract", NS_RETRACT))
+        {
+            1f64
+        } else {
+            0f64
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+        _archive: bool,
+    ) {
+        if let Some((id, retraction)) = Self::parse(message) {
+            self.handle_retraction(aparte, account, message, id, retraction);
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        if let Event::Message(Some(account), Message::Xmpp(message)) = event {
+            if !self.pending.is_empty() {
+                self.handle_message(aparte, account, message);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RetractionMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0424: Message Retraction")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_moderation_notice() {
+        // Given
+        let message: Element = r#"<message xmlns="jabber:client" from="aparte@conference.fariello.eu" type="groupchat">
+            <retract xmlns="urn:xmpp:message-retract:1" id="stanza-id">
+                <moderated xmlns="urn:xmpp:message-moderate:1" by="aparte@conference.fariello.eu/paul"/>
+                <reason>Spam</reason>
+            </retract>
+        </message>"#
+            .parse()
+            .unwrap();
+        let message = XmppParsersMessage::try_from(message).unwrap();
+
+        // When
+        let (id, retraction) = RetractionMod::parse(&message).unwrap();
+
+        // Then
+        assert_eq!(id, "stanza-id");
+        assert_eq!(retraction.by, Some("paul".to_string()));
+        assert_eq!(retraction.reason, Some("Spam".to_string()));
+    }
+}
@@ -6,13 +6,16 @@ use chrono::Local as LocalTz;
 use futures::task::{AtomicWaker, Context, Poll};
 use futures::Stream;
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs::OpenOptions;
 use std::hash::{Hash, Hasher};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::io::{Read, Stdout, Write};
-use std::os::fd::AsFd;
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::OpenOptionsExt;
 use std::panic;
+use std::path::Path;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::mpsc;
@@ -36,40 +39,85 @@ use termion::get_tty;
 use termion::raw::IntoRawMode;
 use termion::screen::IntoAlternateScreen;
 use uuid::Uuid;
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::data_forms::DataForm;
 use xmpp_parsers::{BareJid, Jid};
 
+use crate::account::Account;
 use crate::color::{id_to_rgb, ColorTuple};
 use crate::command::Command;
 use crate::config::Config;
 use crate::conversation::{Channel, Chat, Conversation};
-use crate::core::{Aparte, Event, ModTrait};
+use crate::core::{Aparte, AparteAsync, Event, ModTrait};
+use crate::data_form::DataFormView;
 use crate::i18n;
 use crate::message::{Direction, Message, MessageView, XmppMessageType};
+use crate::mods::adhoc::AdhocEvent;
+use crate::mods::chatstates::ChatStatesEvent;
+use crate::mods::messages::MessagesMod;
 use crate::{contact, conversation};
 
 // Debounce rendering at 350ms pace (based on Doherty Threshold)
 const UI_DEBOUNCE_NS: u32 = 35_000_000u32;
 
+const SEARCH_WINDOW: &str = "search";
+/// Window showing notes and results of ad-hoc commands
+const ADHOC_WINDOW: &str = "adhoc";
+
+/// Prefix of the per-account windows gathering headlines
+const NOTICES_WINDOW: &str = "notices";
+
+/// Number of lines the input can grow to when composing multi-line messages
+const INPUT_MAX_HEIGHT: usize = 5;
+
+/// Delay after which the tty reader checks whether it has been paused
+const TTY_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Bracketed paste mode: pasted text is delimited by PASTE_START and PASTE_END
+const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+type MessageWin = ScrollWin<UIEvent, Stdout, MessageView>;
+
+fn notices_window(account: &Account) -> String {
+    format!("{NOTICES_WINDOW}:{}", account.to_bare())
+}
+
 enum UIEvent {
     Core(Event),
     Validate(Rc<RefCell<Option<(String, bool)>>>),
     GetInput(Rc<RefCell<Option<(String, Cursor, bool)>>>),
     AddWindow(String, Option<Box<dyn View<UIEvent, Stdout>>>),
+    GetSelection(Rc<RefCell<Option<Message>>>),
+    GetLastVisible(Rc<RefCell<Option<Message>>>),
+    Select(Message),
+    SetInput(String),
+    /// Change the selected field of the form shown in the current window
+    EditForm(String),
+    GetForm(Rc<RefCell<Option<DataForm>>>),
 }
 
 struct TitleBar {
     name: Option<String>,
     subjects: HashMap<String, HashMap<String, String>>,
+    /// Preferred languages used to choose among subjects
+    languages: Vec<String>,
+    /// Participants currently typing in each window
+    composing: HashMap<String, Vec<String>>,
     dirty: Cell<bool>,
     pub color: ColorTuple,
     dimensions: Option<Dimensions>,
 }
 
 impl TitleBar {
-    fn new(color: &ColorTuple) -> Self {
+    fn new(color: &ColorTuple, languages: &[String]) -> Self {
         Self {
             name: None,
             subjects: HashMap::new(),
+            languages: languages.to_vec(),
+            composing: HashMap::new(),
             dirty: Cell::new(true),
             color: color.clone(),
             dimensions: None,
@@ -88,6 +136,29 @@ impl TitleBar {
         }
         self.subjects.insert(jid, subjects);
     }
+
+    fn set_composing(&mut self, jid: String, from: &str, composing: bool) {
+        if Some(&jid) == self.name.as_ref() {
+            self.dirty.set(true);
+        }
+        let participants = self.composing.entry(jid).or_default();
+        participants.retain(|participant| participant != from);
+        if composing {
+            participants.push(from.to_string());
+        }
+    }
+
+    fn get_composing(&self, name: &str) -> Option<String> {
+        match self
+            .composing
+            .get(name)
+            .map(|participants| participants.as_slice())
+        {
+            None | Some([]) => None,
+            Some([participant]) => Some(format!("{participant} is typing…")),
+            Some(participants) => Some(format!("{} are typing…", participants.join(", "))),
+        }
+    }
 }
 
 impl<W> View<UIEvent, W> for TitleBar
@@ -139,13 +210,27 @@ where
                 );
                 terminus::vprint!(screen, "{}", clean_name);
 
-                let remaining = dimensions.width
-                    - terminus::term_string_visible_len(&clean_name) as u16
-                    - " – ".len() as u16;
+                let composing = self.get_composing(name).map(|composing| {
+                    terminus::term_string_visible_truncate(
+                        &composing,
+                        (dimensions.width as usize)
+                            .saturating_sub(terminus::term_string_visible_len(&clean_name) + 1),
+                        Some("…"),
+                    )
+                });
+                let composing_len = composing
+                    .as_ref()
+                    .map(|composing| terminus::term_string_visible_len(composing) as u16 + 1)
+                    .unwrap_or(0);
+
+                let remaining = (dimensions.width
+                    - terminus::term_string_visible_len(&clean_name) as u16)
+                    .saturating_sub(" – ".len() as u16 + composing_len);
                 if remaining > 0 {
                     let subjects = self.subjects.get(name).unwrap();
                     if !subjects.is_empty() {
-                        if let Some((_lang, subject)) = i18n::get_best(subjects, vec![]) {
+                        let prefered_langs = self.languages.iter().map(String::as_str).collect();
+                        if let Some((_lang, subject)) = i18n::get_best(subjects, prefered_langs) {
                             let clean_subject = terminus::term_string_visible_truncate(
                                 subject,
                                 remaining.into(),
@@ -155,6 +240,21 @@ where
                         }
                     }
                 }
+
+                if let Some(composing) = composing {
+                    terminus::goto!(
+                        screen,
+                        dimensions.left + dimensions.width - composing_len + 1,
+                        dimensions.top
+                    );
+                    terminus::vprint!(
+                        screen,
+                        "{}{}{}",
+                        termion::style::NoBold,
+                        composing,
+                        termion::style::Bold
+                    );
+                }
             }
 
             terminus::vprint!(
@@ -190,6 +290,18 @@ where
                         .collect(),
                 );
             }
+            UIEvent::Core(Event::ChatStateChanged {
+                conversation,
+                from,
+                state,
+                ..
+            }) => {
+                self.set_composing(
+                    conversation.to_string(),
+                    &terminus::clean_str(from),
+                    *state == ChatState::Composing,
+                );
+            }
             _ => {}
         }
     }
@@ -386,6 +498,9 @@ where
             }) => {
                 self.highlight_window(&conversation.get_jid().to_string(), *important);
             }
+            UIEvent::Core(Event::Notice(account, _)) => {
+                self.highlight_window(&notices_window(account), false);
+            }
             _ => {}
         }
     }
@@ -524,6 +639,98 @@ impl fmt::Display for conversation::Role {
     }
 }
 
+fn selected_message(view: &MessageWin) -> Option<&MessageView> {
+    view.iter().find(|child| child.is_selected())
+}
+
+/// Select the given message, inserting it in the window if needed, and scroll to it
+fn select_message(view: &mut MessageWin, message: MessageView) {
+    if let Some(mut previous) = selected_message(view).cloned() {
+        previous.set_selected(false);
+        view.replace(previous);
+    }
+
+    let mut selected = view.get(&message).cloned().unwrap_or(message);
+    selected.set_selected(true);
+    view.replace(selected.clone());
+    view.scroll_to(&selected);
+}
+
+/// Move the selection to the previous (older) or next (newer) XMPP message of the window. Moving
+/// past the newest message clears the selection.
+fn move_selection(view: &mut MessageWin, older: bool) {
+    let selectable = |child: &&MessageView| matches!(child.message, Message::Xmpp(_));
+    let next = match selected_message(view) {
+        Some(current) if older => view
+            .iter()
+            .rev()
+            .skip_while(|child| *child != current)
+            .skip(1)
+            .find(selectable),
+        Some(current) => view
+            .iter()
+            .skip_while(|child| *child != current)
+            .skip(1)
+            .find(selectable),
+        None if older => view.visible_children().find(selectable),
+        None => None,
+    }
+    .cloned();
+
+    match next {
+        Some(next) => select_message(view, next),
+        None if !older => {
+            if let Some(mut previous) = selected_message(view).cloned() {
+                previous.set_selected(false);
+                view.replace(previous);
+            }
+        }
+        None => {}
+    }
+}
+
+/// Insert a message, or render it again if it was updated (e.g. corrected)
+fn show_message(view: &mut MessageWin, aparte: &mut AparteAsync, message: Message) {
+    let mut message = MessageView::new(aparte, message);
+    if let Some(shown) = view.get(&message) {
+        message.set_selected(shown.is_selected());
+    }
+    view.replace(message);
+}
+
+/// Handle message selection events shared by all message windows
+fn handle_selection_event(view: &mut MessageWin, aparte: &mut AparteAsync, event: &mut UIEvent) {
+    match event {
+        UIEvent::Core(Event::Key(Key::CtrlUp)) => move_selection(view, true),
+        UIEvent::Core(Event::Key(Key::CtrlDown)) => move_selection(view, false),
+        UIEvent::GetSelection(result) => {
+            let mut result = result.borrow_mut();
+            *result = selected_message(view).map(|selected| selected.message.clone());
+        }
+        UIEvent::Select(message) => select_message(view, MessageView::new(aparte, message.clone())),
+        UIEvent::GetLastVisible(result) => {
+            let mut result = result.borrow_mut();
+            *result = view
+                .visible_children()
+                .find(|child| {
+                    matches!(&child.message, Message::Xmpp(message) if message.direction == Direction::Incoming)
+                })
+                .map(|child| child.message.clone());
+        }
+        _ => {}
+    }
+}
+
+/// Last message we sent, loaded into the input to be corrected
+struct Correction {
+    window: String,
+    account: Account,
+    peer: BareJid,
+    id: String,
+    /// Body loaded into the input, validating it unchanged sends nothing
+    body: String,
+}
+
 pub struct Scheduler {
     queue: Rc<RefCell<Vec<Event>>>,
 }
@@ -596,10 +803,21 @@ pub struct UIMod {
     current_window: Option<String>,
     unread_windows: HashMap<String, u64>,
     conversations: HashMap<String, Conversation>,
+    /// Account, peer and id of each message listed in the search window
+    search_results: HashSet<(Account, BareJid, String)>,
+    /// Account of the form shown in each form window
+    forms: HashMap<String, Account>,
     root: LinearLayout<UIEvent, Stdout>,
     last_render: Instant,
     debounced: u32,
     password_command: Option<Command>,
+    correction: Option<Correction>,
+    /// Whether the messages shown in the current window changed since the last render
+    check_displayed: bool,
+    /// Whether the terminal is handed over to an external program
+    suspended: bool,
+    /// Stop reading the terminal input while suspended
+    input_paused: Arc<Mutex<bool>>,
     outgoing_event_queue: Rc<RefCell<Vec<Event>>>,
     _panic_handler: PanicHandler, // Defining panic_handler last guarantee that it will be dropped last (after terminal restoration)
     dimensions: Dimensions,
@@ -612,7 +830,8 @@ impl UIMod {
             .unwrap()
             .into_alternate_screen()
             .unwrap();
-        let screen = BufferedScreen::new(stdout);
+        let mut screen = BufferedScreen::new(stdout);
+        terminus::vprint!(screen, "{}", ENABLE_BRACKETED_PASTE);
 
         let panic_handler = PanicHandler::new();
 
@@ -624,7 +843,7 @@ impl UIMod {
             },
         );
 
-        let title_bar = TitleBar::new(&config.theme.title_bar);
+        let title_bar = TitleBar::new(&config.theme.title_bar, &config.languages);
         let frame =
             FrameLayout::<UIEvent, Stdout, String>::new().with_event(|frame, event| match event {
                 UIEvent::Core(Event::ChangeWindow(name)) => {
@@ -650,7 +869,14 @@ impl UIMod {
                     }
                 }
                 UIEvent::Core(Event::Key(Key::PageUp))
-                | UIEvent::Core(Event::Key(Key::PageDown)) => {
+                | UIEvent::Core(Event::Key(Key::PageDown))
+                | UIEvent::Core(Event::Key(Key::CtrlUp))
+                | UIEvent::Core(Event::Key(Key::CtrlDown))
+                | UIEvent::GetSelection(_)
+                | UIEvent::GetLastVisible(_)
+                | UIEvent::Select(_)
+                | UIEvent::EditForm(_)
+                | UIEvent::GetForm(_) => {
                     if let Some(current) = frame.get_current_mut() {
                         current.event(event);
                     }
@@ -662,47 +888,67 @@ impl UIMod {
                 }
             });
         let win_bar = WinBar::new(&config.theme.win_bar);
-        let input = Input::new().with_event(|input, event| {
-            if let UIEvent::Core(Event::Key(event)) = event {
-                log::debug!("Input event: {:?}", event);
-            }
-            match event {
-                UIEvent::Core(Event::Key(Key::Char(c))) => input.key(*c),
-                UIEvent::Core(Event::Key(Key::Backspace)) => input.backspace(),
-                UIEvent::Core(Event::Key(Key::Delete)) => input.delete(),
-                UIEvent::Core(Event::Key(Key::Home)) => input.home(),
-                UIEvent::Core(Event::Key(Key::End)) => input.end(),
-                UIEvent::Core(Event::Key(Key::Up)) => input.previous(),
-                UIEvent::Core(Event::Key(Key::Down)) => input.next(),
-                UIEvent::Core(Event::Key(Key::Left)) => input.left(),
-                UIEvent::Core(Event::Key(Key::Right)) => input.right(),
-                UIEvent::Core(Event::Key(Key::Ctrl('a'))) => input.home(),
-                UIEvent::Core(Event::Key(Key::Ctrl('b'))) => input.left(),
-                UIEvent::Core(Event::Key(Key::Ctrl('e'))) => input.end(),
-                UIEvent::Core(Event::Key(Key::Ctrl('f'))) => input.right(),
-                UIEvent::Core(Event::Key(Key::Ctrl('h'))) => input.backspace(),
-                UIEvent::Core(Event::Key(Key::Ctrl('w'))) => input.backward_delete_word(),
-                UIEvent::Core(Event::Key(Key::Ctrl('u'))) => input.delete_from_cursor_to_start(),
-                UIEvent::Core(Event::Key(Key::Ctrl('k'))) => input.delete_from_cursor_to_end(),
-                UIEvent::Core(Event::Key(Key::CtrlLeft)) => input.word_left(),
-                UIEvent::Core(Event::Key(Key::CtrlRight)) => input.word_right(),
-                UIEvent::Validate(result) => {
-                    let mut result = result.borrow_mut();
-                    result.replace(input.validate());
-                }
-                UIEvent::GetInput(result) => {
-                    let mut result = result.borrow_mut();
-                    result.replace((input.buf.clone(), input.cursor.clone(), input.password));
+        let input = Input::new()
+            .with_max_height(INPUT_MAX_HEIGHT)
+            .with_event(|input, event| {
+                if let UIEvent::Core(Event::Key(event)) = event {
+                    log::debug!("Input event: {:?}", event);
                 }
-                UIEvent::Core(Event::Completed(raw_buf, cursor)) => {
-                    input.buf = raw_buf.clone();
-                    input.cursor = cursor.clone();
-                    input.dirty.set(true);
+                match event {
+                    UIEvent::Core(Event::Key(Key::Char(c))) => input.key(*c),
+                    UIEvent::Core(Event::Key(Key::Backspace)) => input.backspace(),
+                    UIEvent::Core(Event::Key(Key::Delete)) => input.delete(),
+                    UIEvent::Core(Event::Key(Key::Home)) => input.home(),
+                    UIEvent::Core(Event::Key(Key::End)) => input.end(),
+                    UIEvent::Core(Event::Key(Key::Up)) => {
+                        if !input.up() {
+                            input.previous()
+                        }
+                    }
+                    UIEvent::Core(Event::Key(Key::Down)) => {
+                        if !input.down() {
+                            input.next()
+                        }
+                    }
+                    // Alt+Enter, reported as either CR or LF depending on the terminal
+                    UIEvent::Core(Event::Key(Key::Alt('\r')))
+                    | UIEvent::Core(Event::Key(Key::Alt('\n'))) => input.newline(),
+                    UIEvent::Core(Event::Key(Key::Left)) => input.left(),
+                    UIEvent::Core(Event::Key(Key::Right)) => input.right(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('a'))) => input.home(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('b'))) => input.left(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('e'))) => input.end(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('f'))) => input.right(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('h'))) => input.backspace(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('w'))) => input.backward_delete_word(),
+                    UIEvent::Core(Event::Key(Key::Ctrl('u'))) => {
+                        input.delete_from_cursor_to_start()
+                    }
+                    UIEvent::Core(Event::Key(Key::Ctrl('k'))) => input.delete_from_cursor_to_end(),
+                    UIEvent::Core(Event::Key(Key::CtrlLeft)) => input.word_left(),
+                    UIEvent::Core(Event::Key(Key::CtrlRight)) => input.word_right(),
+                    UIEvent::Validate(result) => {
+                        let mut result = result.borrow_mut();
+                        result.replace(input.validate());
+                    }
+                    UIEvent::GetInput(result) => {
+                        let mut result = result.borrow_mut();
+                        result.replace((input.buf.clone(), input.cursor.clone(), input.password));
+                    }
+                    UIEvent::Core(Event::Completed(raw_buf, cursor)) => {
+                        input.buf = raw_buf.clone();
+                        input.cursor = cursor.clone();
+                        input.dirty.set(true);
+                    }
+                    UIEvent::Core(Event::ReadPassword(_)) => input.password(),
+                    UIEvent::Core(Event::Paste(text)) => input.paste(text),
+                    UIEvent::SetInput(buf) => {
+                        input.buf = buf.clone();
+                        input.end();
+                    }
+                    _ => {}
                 }
-                UIEvent::Core(Event::ReadPassword(_)) => input.password(),
-                _ => {}
-            }
-        });
+            });
 
         layout.push(title_bar, 0);
         layout.push(frame, 1);
@@ -718,7 +964,13 @@ impl UIMod {
             unread_windows: HashMap::new(),
             current_window: None,
             conversations: HashMap::new(),
+            search_results: HashSet::new(),
+            forms: HashMap::new(),
             password_command: None,
+            correction: None,
+            check_displayed: false,
+            suspended: false,
+            input_paused: Arc::new(Mutex::new(false)),
             outgoing_event_queue: Rc::new(RefCell::new(Vec::new())),
             _panic_handler: panic_handler,
             last_render: Instant::now(),
@@ -733,7 +985,7 @@ impl UIMod {
     }
 
     pub fn event_stream(&self) -> EventStream {
-        EventStream::default()
+        EventStream::new(Arc::clone(&self.input_paused))
     }
 
     fn get_scheduler(&self) -> Scheduler {
@@ -756,19 +1008,21 @@ impl UIMod {
                                     // TODO check to == us
                                     Direction::Incoming => {
                                         if message.from == chat_for_event.contact {
-                                            view.insert(MessageView::new(
+                                            show_message(
+                                                view,
                                                 &mut aparte,
                                                 Message::Xmpp(message.clone()),
-                                            ));
+                                            );
                                         }
                                     }
                                     Direction::Outgoing => {
                                         // TODO check from == us
                                         if message.to == chat_for_event.contact {
-                                            view.insert(MessageView::new(
+                                            show_message(
+                                                view,
                                                 &mut aparte,
                                                 Message::Xmpp(message.clone()),
-                                            ));
+                                            );
                                         }
                                     }
                                 }
@@ -787,7 +1041,7 @@ impl UIMod {
                             UIEvent::Core(Event::Key(Key::PageDown)) => {
                                 view.page_down();
                             }
-                            _ => {}
+                            event => handle_selection_event(view, &mut aparte, event),
                         }
                     }
                 });
@@ -814,23 +1068,37 @@ impl UIMod {
                                     // TODO check to == us
                                     Direction::Incoming => {
                                         if message.from == channel_for_event.jid {
-                                            view.insert(MessageView::new(
+                                            show_message(
+                                                view,
                                                 &mut aparte,
                                                 Message::Xmpp(message.clone()),
-                                            ));
+                                            );
                                         }
                                     }
                                     Direction::Outgoing => {
                                         // TODO check from == us
                                         if message.to == channel_for_event.jid {
-                                            view.insert(MessageView::new(
+                                            show_message(
+                                                view,
                                                 &mut aparte,
                                                 Message::Xmpp(message.clone()),
-                                            ));
+                                            );
                                         }
                                     }
                                 }
                             }
+                            UIEvent::Core(Event::ChannelLog {
+                                account,
+                                conversation,
+                                body,
+                            }) if *account == channel_for_event.account
+                                && *conversation == channel_for_event.jid =>
+                            {
+                                view.insert(MessageView::new(
+                                    &mut aparte,
+                                    Message::log(body.clone()),
+                                ));
+                            }
                             UIEvent::Core(Event::Key(Key::PageUp)) => {
                                 if view.page_up() {
                                     let from =
@@ -845,7 +1113,7 @@ impl UIMod {
                             UIEvent::Core(Event::Key(Key::PageDown)) => {
                                 view.page_down();
                             }
-                            _ => {}
+                            event => handle_selection_event(view, &mut aparte, event),
                         }
                     }
                 });
@@ -861,17 +1129,27 @@ impl UIMod {
                         .with_none_group()
                         .with_unique_item()
                         .with_sort_item()
-                        .with_event(move |view, event| {
-                            if let UIEvent::Core(Event::Occupant {
+                        .with_event(move |view, event| match event {
+                            UIEvent::Core(Event::Occupant {
                                 conversation,
                                 occupant,
                                 ..
-                            }) = event
-                            {
-                                if roster_jid == *conversation {
-                                    view.insert(occupant.clone(), Some(occupant.role));
-                                }
+                            }) if roster_jid == *conversation => {
+                                view.insert(occupant.clone(), Some(occupant.role));
+                            }
+                            UIEvent::Core(Event::NickChanged {
+                                conversation,
+                                occupant,
+                                ..
+                            })
+                            | UIEvent::Core(Event::OccupantLeft {
+                                conversation,
+                                occupant,
+                                ..
+                            }) if roster_jid == *conversation => {
+                                let _ = view.remove(occupant.clone(), Some(occupant.role));
                             }
+                            _ => {}
                         });
                 layout.push(roster, 3);
 
@@ -882,17 +1160,415 @@ impl UIMod {
         }
     }
 
+    fn add_search_window(&mut self, aparte: &mut Aparte) {
+        let searchwin = MessageWin::new().with_event({
+            let mut aparte = aparte.proxy();
+            move |view, event| match event {
+                UIEvent::Core(Event::SearchResults { query, results }) => {
+                    view.clear();
+                    for (_, message) in results.iter() {
+                        view.insert(MessageView::new(&mut aparte, message.clone()));
+                    }
+                    view.insert(MessageView::new(
+                        &mut aparte,
+                        Message::log(format!("{} results for: {}", results.len(), query)),
+                    ));
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                event => handle_selection_event(view, &mut aparte, event),
+            }
+        });
+
+        self.add_window(SEARCH_WINDOW.to_string(), Box::new(searchwin));
+    }
+
+    fn add_adhoc_window(&mut self, aparte: &mut Aparte) {
+        let adhocwin = MessageWin::new().with_event({
+            let mut aparte = aparte.proxy();
+            move |view, event| match event {
+                UIEvent::Core(Event::Adhoc(AdhocEvent::Note(body))) => {
+                    view.insert(MessageView::new(&mut aparte, Message::log(body.clone())));
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                event => handle_selection_event(view, &mut aparte, event),
+            }
+        });
+
+        self.add_window(ADHOC_WINDOW.to_string(), Box::new(adhocwin));
+    }
+
+    fn add_notices_window(&mut self, aparte: &mut Aparte, account: &Account) {
+        let noticeswin = MessageWin::new().with_event({
+            let mut aparte = aparte.proxy();
+            let account = account.clone();
+            move |view, event| match event {
+                UIEvent::Core(Event::Notice(notice_account, message))
+                    if notice_account == &account =>
+                {
+                    view.insert(MessageView::new(&mut aparte, message.clone()));
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                event => handle_selection_event(view, &mut aparte, event),
+            }
+        });
+
+        self.add_window(notices_window(account), Box::new(noticeswin));
+    }
+
+    /// Message selected with Ctrl+Up/Ctrl+Down in the current window
+    pub fn get_selection(&mut self) -> Option<Message> {
+        let result = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::GetSelection(Rc::clone(&result)));
+        let selected = result.borrow_mut().take();
+        selected
+    }
+
+    /// Tell the current conversation which message the user has seen last
+    fn mark_displayed(&mut self, aparte: &mut Aparte) {
+        let Some(window) = self.current_window.as_ref() else {
+            return;
+        };
+        let Some(conversation) = self.conversations.get(window) else {
+            return;
+        };
+        let account = conversation.get_account().clone();
+
+        let result = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::GetLastVisible(Rc::clone(&result)));
+        let last_visible = result.borrow_mut().take();
+        if let Some(message) = last_visible {
+            aparte.schedule(Event::Displayed { account, message });
+        }
+    }
+
+    fn get_input(&mut self) -> (String, Cursor, bool) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let input = result.borrow_mut().take().unwrap();
+        input
+    }
+
+    /// Leave the alternate screen and raw mode to hand over the terminal to another program
+    fn suspend(&mut self) -> std::io::Result<()> {
+        terminus::flush!(self.screen);
+        self.suspended = true;
+        // Waits for the tty reader to complete an ongoing read
+        *self.input_paused.lock().unwrap() = true;
+
+        let terminal = self.screen.get_mut();
+        write!(
+            terminal,
+            "{}{}",
+            DISABLE_BRACKETED_PASTE,
+            termion::screen::ToMainScreen
+        )?;
+        terminal.flush()?;
+        terminal.suspend_raw_mode()
+    }
+
+    fn resume(&mut self) -> std::io::Result<()> {
+        let terminal = self.screen.get_mut();
+        terminal.activate_raw_mode()?;
+        write!(
+            terminal,
+            "{}{}",
+            termion::screen::ToAlternateScreen,
+            ENABLE_BRACKETED_PASTE
+        )?;
+        terminal.flush()?;
+
+        self.suspended = false;
+        *self.input_paused.lock().unwrap() = false;
+        terminus::vprint!(self.screen, "{}", termion::clear::All);
+        self.root.set_dirty();
+        Ok(())
+    }
+
+    fn write_draft(path: &Path, buf: &str) -> anyhow::Result<()> {
+        // The draft may be sensitive, don't let other users read it
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(buf.as_bytes())?;
+        Ok(())
+    }
+
+    async fn run_editor(path: &Path) -> anyhow::Result<String> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or("vi".to_string());
+        let mut args = editor.split_whitespace();
+        let Some(program) = args.next() else {
+            anyhow::bail!("$EDITOR is empty");
+        };
+
+        let status = tokio::process::Command::new(program)
+            .args(args)
+            .arg(path)
+            .status()
+            .await?;
+        if !status.success() {
+            anyhow::bail!("{editor} exited with {status}");
+        }
+
+        let body = tokio::fs::read_to_string(path).await?;
+        Ok(body.trim_end_matches('\n').to_string())
+    }
+
+    /// Edit the content of the input in an external editor
+    fn open_editor(&mut self, aparte: &mut Aparte) {
+        let (buf, _, password) = self.get_input();
+        if self.suspended || password {
+            return;
+        }
+
+        let path = std::env::temp_dir().join(format!("aparte-{}.txt", Uuid::new_v4()));
+        if let Err(err) = Self::write_draft(&path, &buf) {
+            crate::error!(aparte, err, "Cannot create draft file");
+            return;
+        }
+
+        if let Err(err) = self.suspend() {
+            let _ = std::fs::remove_file(&path);
+            let err = anyhow::Error::from(err);
+            crate::error!(aparte, err, "Cannot suspend terminal");
+            aparte.schedule(Event::Edited(None));
+            return;
+        }
+
+        Aparte::spawn({
+            let mut aparte = aparte.proxy();
+            async move {
+                let body = match Self::run_editor(&path).await {
+                    Ok(body) => Some(body),
+                    Err(err) => {
+                        crate::error!(aparte, err, "Cannot edit message");
+                        None
+                    }
+                };
+                let _ = std::fs::remove_file(&path);
+                aparte.schedule(Event::Edited(body));
+            }
+        });
+    }
+
+    fn close_editor(&mut self, aparte: &mut Aparte, body: &Option<String>) {
+        if self.suspended {
+            if let Err(err) = self.resume() {
+                let err = anyhow::Error::from(err);
+                crate::error!(aparte, err, "Cannot restore terminal");
+            }
+        }
+
+        if let Some(body) = body {
+            self.root.event(&mut UIEvent::SetInput(body.clone()));
+            self.input_changed(aparte, body);
+        }
+    }
+
+    /// Notify the current conversation that the user is typing
+    fn input_changed(&mut self, aparte: &mut Aparte, buf: &str) {
+        // Clearing the input or typing a command abandons the correction
+        if buf.is_empty() || buf.starts_with('/') {
+            self.correction = None;
+        }
+
+        let Some(window) = self.current_window.as_ref() else {
+            return;
+        };
+        if let Some(conversation) = self.conversations.get(window) {
+            aparte.schedule(Event::ChatStates(ChatStatesEvent::Input {
+                account: conversation.get_account().clone(),
+                conversation: conversation.get_jid().clone(),
+                empty: buf.is_empty() || buf.starts_with('/'),
+            }));
+        }
+    }
+
+    /// Open the conversation of the message selected in the search window and select it there
+    fn jump_to_search_selection(&mut self, aparte: &mut Aparte) {
+        let Some(Message::Xmpp(message)) = self.get_selection() else {
+            return;
+        };
+
+        let peer = match message.direction {
+            Direction::Incoming => message.from.clone(),
+            Direction::Outgoing => message.to.clone(),
+        };
+        let window = peer.to_string();
+
+        if !self.windows.contains(&window) {
+            let account = self
+                .search_results
+                .iter()
+                .find(|(_, result_peer, id)| result_peer == &peer && id == &message.id)
+                .map(|(account, _, _)| account);
+            match (&message.type_, account) {
+                (XmppMessageType::Chat, Some(account)) => {
+                    self.add_conversation(
+                        aparte,
+                        Conversation::Chat(Chat {
+                            account: account.clone(),
+                            contact: peer.clone(),
+                        }),
+                    );
+                    aparte.schedule(Event::LoadChatHistory {
+                        account: account.clone(),
+                        contact: peer,
+                        from: None,
+                    });
+                }
+                (XmppMessageType::Channel, Some(account)) => {
+                    // Shows the archived conversation, joining stays up to the user
+                    self.add_conversation(
+                        aparte,
+                        Conversation::Channel(Channel {
+                            account: account.clone(),
+                            jid: peer.clone(),
+                            nick: account.resource().to_string(),
+                            name: None,
+                            occupants: HashMap::new(),
+                        }),
+                    );
+                    aparte.schedule(Event::LoadChannelHistory {
+                        account: account.clone(),
+                        jid: peer,
+                        from: None,
+                    });
+                }
+                (_, None) => return,
+            }
+        }
+
+        self.change_window(&window);
+        self.root
+            .event(&mut UIEvent::Select(Message::Xmpp(message)));
+    }
+
+    fn add_form_window(&mut self, account: &Account, window: &str, form: &DataForm) {
+        let formwin = DataFormView::new(form.clone()).with_event({
+            let window = window.to_string();
+            move |view, event| match event {
+                UIEvent::Core(Event::DataForm {
+                    window: form_window,
+                    form,
+                    ..
+                }) if form_window == &window => view.set_form(form.clone()),
+                UIEvent::Core(Event::Key(Key::CtrlUp)) => view.select(true),
+                UIEvent::Core(Event::Key(Key::CtrlDown)) => view.select(false),
+                UIEvent::EditForm(input) => {
+                    // The view shows why the edit failed
+                    let _ = view.edit(input);
+                }
+                UIEvent::GetForm(result) => {
+                    result.borrow_mut().replace(view.get_form().clone());
+                }
+                _ => {}
+            }
+        });
+
+        self.forms.insert(window.to_string(), account.clone());
+        self.add_window(window.to_string(), Box::new(formwin));
+    }
+
+    /// Whether a form is shown in the given window
+    pub fn has_form(&self, window: &str) -> bool {
+        self.forms.contains_key(window)
+    }
+
+    /// Form filled so far in the given window, which must be the current one
+    pub fn get_form(&mut self, window: &str) -> Option<(Account, DataForm)> {
+        if self.current_window.as_deref() != Some(window) {
+            return None;
+        }
+        let account = self.forms.get(window)?.clone();
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetForm(Rc::clone(&result)));
+        let form = result.borrow_mut().take()?;
+        Some((account, form))
+    }
+
+    /// Form filled in the given window, which isn't considered a form window anymore
+    pub fn take_form(&mut self, window: &str) -> Option<(Account, DataForm)> {
+        let form = self.get_form(window)?;
+        self.forms.remove(window);
+        Some(form)
+    }
+
     fn add_window(&mut self, name: String, window: Box<dyn View<UIEvent, Stdout>>) {
         self.windows.push(name.clone());
         self.root.event(&mut UIEvent::AddWindow(name, Some(window)));
     }
 
     pub fn change_window(&mut self, window: &str) {
+        self.correction = None;
+        self.check_displayed = true;
         self.root
             .event(&mut UIEvent::Core(Event::ChangeWindow(window.to_string())));
         self.current_window = Some(window.to_string());
     }
 
+    /// Load the last message sent in the current conversation into the input to correct it
+    fn edit_last_message(&mut self, aparte: &mut Aparte) -> bool {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        match result.borrow().as_ref() {
+            Some((buf, _, false)) if buf.is_empty() => {}
+            _ => return false,
+        }
+
+        let window = match &self.current_window {
+            Some(window) => window.clone(),
+            None => return false,
+        };
+        let (account, peer) = match self.conversations.get(&window) {
+            Some(Conversation::Chat(chat)) => (chat.account.clone(), chat.contact.clone()),
+            Some(Conversation::Channel(channel)) => (channel.account.clone(), channel.jid.clone()),
+            None => return false,
+        };
+
+        let last = aparte
+            .get_mod::<MessagesMod>()
+            .get_last_outgoing(&account, &peer)
+            .map(|message| {
+                let body = message.get_last_body(aparte.config.prefered_langs());
+                (message.id.clone(), body.to_string())
+            });
+        match last {
+            Some((id, body)) => {
+                self.root.event(&mut UIEvent::SetInput(body.clone()));
+                self.correction = Some(Correction {
+                    window,
+                    account,
+                    peer,
+                    id,
+                    body,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
     #[allow(unused)] // XXX Should be used when alt+arrow is fixed see https://gitlab.redox-os.org/redox-os/termion/-/issues/183
     pub fn next_window(&mut self) {
         if let Some(current) = &self.current_window {
@@ -926,6 +1602,13 @@ impl UIMod {
     }
 }
 
+impl Drop for UIMod {
+    fn drop(&mut self) {
+        terminus::vprint!(self.screen, "{}", DISABLE_BRACKETED_PASTE);
+        terminus::flush!(self.screen);
+    }
+}
+
 impl ModTrait for UIMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
         terminus::vprint!(&mut self.screen, "{}", termion::clear::All);
@@ -1104,6 +1787,10 @@ impl ModTrait for UIMod {
                                     *important += 1;
                                 }
                             }
+
+                            if Some(&window_name) == self.current_window.as_ref() {
+                                self.check_displayed = true;
+                            }
                         }
                     }
                     Message::Log(_message) => {}
@@ -1114,6 +1801,16 @@ impl ModTrait for UIMod {
                     message.clone(),
                 )));
             }
+            Event::Notice(account, message) => {
+                let window = notices_window(account);
+                if !self.windows.contains(&window) {
+                    self.add_notices_window(aparte, account);
+                }
+                self.root.event(&mut UIEvent::Core(Event::Notice(
+                    account.clone(),
+                    message.clone(),
+                )));
+            }
             Event::Chat { account, contact } => {
                 // Should we store account association?
                 let win_name = contact.to_string();
@@ -1151,6 +1848,52 @@ impl ModTrait for UIMod {
                     self.change_window(&win_name);
                 }
             }
+            Event::NickChanged {
+                account,
+                conversation,
+                occupant,
+                nick,
+            } => {
+                for known in self.conversations.values_mut() {
+                    if let Conversation::Channel(channel) = known {
+                        if &channel.account == account
+                            && &channel.jid == conversation
+                            && channel.nick == occupant.nick
+                        {
+                            channel.nick = nick.clone();
+                        }
+                    }
+                }
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::Adhoc(AdhocEvent::Note(_)) => {
+                if !self.windows.iter().any(|window| window == ADHOC_WINDOW) {
+                    self.add_adhoc_window(aparte);
+                }
+                self.root.event(&mut UIEvent::Core(event.clone()));
+                self.change_window(ADHOC_WINDOW);
+            }
+            Event::SearchResults { results, .. } => {
+                if !self.windows.iter().any(|window| window == SEARCH_WINDOW) {
+                    self.add_search_window(aparte);
+                }
+
+                self.search_results = results
+                    .iter()
+                    .filter_map(|(account, message)| match message {
+                        Message::Xmpp(message) => {
+                            let peer = match message.direction {
+                                Direction::Incoming => message.from.clone(),
+                                Direction::Outgoing => message.to.clone(),
+                            };
+                            Some((account.clone(), peer, message.id.clone()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                self.root.event(&mut UIEvent::Core(event.clone()));
+                self.change_window(SEARCH_WINDOW);
+            }
             Event::Win(window) => {
                 if self.windows.contains(window) {
                     self.change_window(window);
@@ -1168,11 +1911,21 @@ impl ModTrait for UIMod {
                 self.dimensions =
                     Dimensions::reconcile(&measure_specs, &requested_dimensions, 1, 1);
                 self.root.layout(&self.dimensions);
-                self.root.render(&mut self.screen);
-                terminus::restore_cursor!(&mut self.screen);
+                if !self.suspended {
+                    self.root.render(&mut self.screen);
+                    terminus::restore_cursor!(&mut self.screen);
+                }
             }
             Event::Close(window) => {
                 if window != "console" {
+                    // Closing a form window cancels the form
+                    if let Some(account) = self.forms.remove(window) {
+                        aparte.schedule(Event::DataFormSubmit {
+                            account,
+                            window: window.clone(),
+                            form: None,
+                        });
+                    }
                     self.windows.retain(|win| win != window);
                     self.unread_windows.remove(window);
                     if Some(window) == self.current_window.as_ref() {
@@ -1188,14 +1941,7 @@ impl ModTrait for UIMod {
             Event::Key(key) => {
                 match key {
                     Key::Char('\t') => {
-                        let result = Rc::new(RefCell::new(None));
-
-                        let (raw_buf, cursor, password) = {
-                            self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
-
-                            let result = result.borrow_mut();
-                            result.as_ref().unwrap().clone()
-                        };
+                        let (raw_buf, cursor, password) = self.get_input();
 
                         if password {
                             aparte.schedule(Event::Key(Key::Char('\t')));
@@ -1229,6 +1975,7 @@ impl ModTrait for UIMod {
                             command.args.push(raw_buf);
                             aparte.schedule(Event::Command(command));
                         } else if raw_buf.starts_with('/') {
+                            self.correction = None;
                             let window = self.current_window.clone().unwrap();
                             let account = match self.conversations.get(&window) {
                                 Some(Conversation::Chat(chat)) => Some(chat.account.clone()),
@@ -1238,6 +1985,27 @@ impl ModTrait for UIMod {
                                 _ => None,
                             };
                             aparte.schedule(Event::RawCommand(account, window, raw_buf));
+                        } else if self
+                            .current_window
+                            .as_ref()
+                            .map_or(false, |window| self.forms.contains_key(window))
+                        {
+                            self.root.event(&mut UIEvent::EditForm(raw_buf));
+                        } else if let Some(correction) =
+                            self.correction.take().filter(|correction| {
+                                !raw_buf.is_empty()
+                                    && Some(&correction.window) == self.current_window.as_ref()
+                            })
+                        {
+                            // Nothing to correct when the loaded message wasn't edited
+                            if raw_buf != correction.body {
+                                aparte.schedule(Event::SendCorrection {
+                                    account: correction.account,
+                                    peer: correction.peer,
+                                    id: correction.id,
+                                    body: raw_buf,
+                                });
+                            }
                         } else if !raw_buf.is_empty() {
                             if let Some(current_window) = self.current_window.clone() {
                                 if let Some(conversation) = self.conversations.get(&current_window)
@@ -1295,8 +2063,27 @@ impl ModTrait for UIMod {
                                     }
                                 }
                             }
+                        } else if self.current_window.as_deref() == Some(SEARCH_WINDOW) {
+                            self.jump_to_search_selection(aparte);
+                        }
+                    }
+                    Key::Up | Key::Down => {
+                        aparte.schedule(Event::ResetCompletion);
+                        let loaded = matches!(key, Key::Up) && self.edit_last_message(aparte);
+                        if !loaded {
+                            // Up again once the last message is loaded goes back in the input
+                            // history, which replaces the corrected message
+                            let (before, _, _) = self.get_input();
+                            self.root.event(&mut UIEvent::Core(Event::Key(*key)));
+                            let (after, _, password) = self.get_input();
+                            if !password && before != after {
+                                self.correction = None;
+                                self.input_changed(aparte, &after);
+                            }
                         }
                     }
+                    // Only a key binding, a command would have replaced the draft to edit
+                    Key::Alt('e') => aparte.schedule(Event::Editor),
                     Key::Alt('a') => {
                         if !self.unread_windows.is_empty() {
                             let next = {
@@ -1311,7 +2098,12 @@ impl ModTrait for UIMod {
                     }
                     _ => {
                         aparte.schedule(Event::ResetCompletion);
+                        let (before, _, _) = self.get_input();
                         self.root.event(&mut UIEvent::Core(Event::Key(*key)));
+                        let (after, _, password) = self.get_input();
+                        if !password && before != after {
+                            self.input_changed(aparte, &after);
+                        }
                     }
                 }
             }
@@ -1337,12 +2129,45 @@ impl ModTrait for UIMod {
                 log::debug!("Force render");
                 force_render |= force;
             }
+            Event::Editor => self.open_editor(aparte),
+            Event::DataForm {
+                account,
+                window,
+                form,
+            } => {
+                if self.forms.contains_key(window) {
+                    self.root.event(&mut UIEvent::Core(event.clone()));
+                } else {
+                    if self.windows.contains(window) {
+                        self.windows.retain(|win| win != window);
+                        self.root
+                            .event(&mut UIEvent::Core(Event::Close(window.clone())));
+                    }
+                    self.add_form_window(account, window, form);
+                }
+                self.change_window(window);
+            }
+            Event::Paste(text) => {
+                aparte.schedule(Event::ResetCompletion);
+                self.root
+                    .event(&mut UIEvent::Core(Event::Paste(text.clone())));
+                let (buf, _, password) = self.get_input();
+                if !password {
+                    self.input_changed(aparte, &buf);
+                }
+            }
+            Event::Edited(body) => {
+                self.close_editor(aparte, body);
+                force_render = true;
+            }
             // Forward all unknown events
             event => self.root.event(&mut UIEvent::Core(event.clone())),
         }
 
         // Debounce rendering
-        if force_render || self.last_render.elapsed() > Duration::new(0, UI_DEBOUNCE_NS) {
+        if self.suspended {
+            log::debug!("Terminal suspended, skip rendering");
+        } else if force_render || self.last_render.elapsed() > Duration::new(0, UI_DEBOUNCE_NS) {
             // Update rendering
             log::debug!("Render (saved {} rendering)", self.debounced);
             self.last_render = Instant::now();
@@ -1359,6 +2184,11 @@ impl ModTrait for UIMod {
             self.root.render(&mut self.screen);
             terminus::restore_cursor!(&mut self.screen);
             terminus::flush!(self.screen);
+
+            if self.check_displayed {
+                self.check_displayed = false;
+                self.mark_displayed(aparte);
+            }
         } else {
             log::debug!("Debounce rendering");
             if self.debounced == 0 {
@@ -1387,13 +2217,31 @@ impl fmt::Display for UIMod {
     }
 }
 
+enum TermionInput {
+    Event(TermionEvent),
+    Paste(String),
+}
+
 struct TermionEventStream {
     channel: mpsc::Receiver<Result<u8, IoError>>,
     waker: Arc<AtomicWaker>,
+    /// Text being pasted, until PASTE_END is received
+    paste: Option<Vec<u8>>,
+}
+
+/// Wait for the tty to be readable, return false on timeout
+fn poll_tty(tty: &impl AsRawFd, timeout: Duration) -> bool {
+    let mut fds = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: fds is a valid pollfd for the duration of the call
+    unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) > 0 }
 }
 
 impl TermionEventStream {
-    pub fn new() -> Self {
+    pub fn new(paused: Arc<Mutex<bool>>) -> Self {
         let (send, recv) = mpsc::channel();
         let waker = Arc::new(AtomicWaker::new());
 
@@ -1402,7 +2250,25 @@ impl TermionEventStream {
             let mut input = get_tty().expect("cannot get tty for stdin reading");
             let mut buf = [0u8; 256];
             loop {
-                match input.read(&mut buf[..]) {
+                // Never block on read, so that input can be left to external programs
+                if *paused.lock().unwrap() {
+                    thread::sleep(TTY_POLL_TIMEOUT);
+                    continue;
+                }
+                if !poll_tty(&input, TTY_POLL_TIMEOUT) {
+                    continue;
+                }
+
+                // The input may have been paused while polling, keep it locked while reading so
+                // that suspending waits for the read to complete
+                let result = {
+                    let paused_guard = paused.lock().unwrap();
+                    if *paused_guard {
+                        continue;
+                    }
+                    input.read(&mut buf[..])
+                };
+                match result {
                     Ok(n) => {
                         for byte in buf[..n].iter() {
                             if send.send(Ok(*byte)).is_err() {
@@ -1426,6 +2292,7 @@ impl TermionEventStream {
         Self {
             channel: recv,
             waker,
+            paste: None,
         }
     }
 }
@@ -1450,8 +2317,17 @@ impl<'a, T> Iterator for IterWrapper<'a, T> {
     }
 }
 
+impl TermionEventStream {
+    fn paste(mut paste: Vec<u8>) -> TermionInput {
+        paste.truncate(paste.len() - PASTE_END.len());
+        let paste = String::from_utf8_lossy(&paste);
+        // Terminals send line breaks as carriage returns
+        TermionInput::Paste(paste.replace("\r\n", "\n").replace('\r', "\n"))
+    }
+}
+
 impl Stream for TermionEventStream {
-    type Item = TermionEvent;
+    type Item = TermionInput;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let byte = match self.channel.try_recv() {
@@ -1464,9 +2340,53 @@ impl Stream for TermionEventStream {
             Err(mpsc::TryRecvError::Disconnected) => return Poll::Ready(None),
         };
 
-        let mut iter = IterWrapper::new(&mut self.channel);
+        if let Some(mut paste) = self.paste.take() {
+            paste.push(byte);
+            let mut iter = IterWrapper::new(&mut self.channel);
+            while !paste.ends_with(PASTE_END) {
+                match iter.next() {
+                    Some(Ok(byte)) => paste.push(byte),
+                    _ => break,
+                }
+            }
+
+            if paste.ends_with(PASTE_END) {
+                return Poll::Ready(Some(Self::paste(paste)));
+            }
+            self.paste = Some(paste);
+            self.waker.register(cx.waker());
+            return Poll::Pending;
+        }
+
+        // Read ahead escape sequences only as long as they can be a paste start, remaining
+        // bytes are left to termion
+        let mut lookahead = Vec::new();
+        if byte == PASTE_START[0] {
+            let mut iter = IterWrapper::new(&mut self.channel);
+            while lookahead.len() < PASTE_START.len() - 1 {
+                match iter.next() {
+                    Some(Ok(byte)) => lookahead.push(byte),
+                    _ => break,
+                }
+                if !PASTE_START[1..].starts_with(&lookahead) {
+                    break;
+                }
+            }
+            if lookahead == PASTE_START[1..] {
+                self.paste = Some(Vec::new());
+                self.waker.register(cx.waker());
+                // Wake immediately in case the paste content is already available
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+
+        let mut iter = lookahead
+            .into_iter()
+            .map(Ok)
+            .chain(IterWrapper::new(&mut self.channel));
         if let Ok(event) = termion_parse_event(byte, &mut iter) {
-            Poll::Ready(Some(event))
+            Poll::Ready(Some(TermionInput::Event(event)))
         } else {
             self.waker.register(cx.waker());
             Poll::Pending
@@ -1479,25 +2399,20 @@ pub struct EventStream {
 }
 
 impl EventStream {
-    pub fn new() -> Self {
+    pub fn new(paused: Arc<Mutex<bool>>) -> Self {
         Self {
-            inner: TermionEventStream::new(),
+            inner: TermionEventStream::new(paused),
         }
     }
 }
 
-impl Default for EventStream {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl Stream for EventStream {
     type Item = Event;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         match Pin::new(&mut self.inner).poll_next(cx) {
-            Poll::Ready(Some(TermionEvent::Key(key))) => match key {
+            Poll::Ready(Some(TermionInput::Paste(text))) => Poll::Ready(Some(Event::Paste(text))),
+            Poll::Ready(Some(TermionInput::Event(TermionEvent::Key(key)))) => match key {
                 Key::Char(c) => Poll::Ready(Some(Event::Key(Key::Char(c)))),
                 Key::Backspace => Poll::Ready(Some(Event::Key(Key::Backspace))),
                 Key::Delete => Poll::Ready(Some(Event::Key(Key::Delete))),
@@ -1509,6 +2424,8 @@ impl Stream for EventStream {
                 Key::Right => Poll::Ready(Some(Event::Key(Key::Right))),
                 Key::CtrlLeft => Poll::Ready(Some(Event::Key(Key::CtrlLeft))),
                 Key::CtrlRight => Poll::Ready(Some(Event::Key(Key::CtrlRight))),
+                Key::CtrlUp => Poll::Ready(Some(Event::Key(Key::CtrlUp))),
+                Key::CtrlDown => Poll::Ready(Some(Event::Key(Key::CtrlDown))),
                 Key::Ctrl(c) => Poll::Ready(Some(Event::Key(Key::Ctrl(c)))),
                 Key::Alt(c) => Poll::Ready(Some(Event::Key(Key::Alt(c)))),
                 Key::PageUp => Poll::Ready(Some(Event::Key(Key::PageUp))),
@@ -1518,11 +2435,11 @@ impl Stream for EventStream {
                     Poll::Pending
                 }
             },
-            Poll::Ready(Some(TermionEvent::Mouse(_))) => {
+            Poll::Ready(Some(TermionInput::Event(TermionEvent::Mouse(_)))) => {
                 self.inner.waker.register(cx.waker());
                 Poll::Pending
             }
-            Poll::Ready(Some(TermionEvent::Unsupported(_))) => {
+            Poll::Ready(Some(TermionInput::Event(TermionEvent::Unsupported(_)))) => {
                 self.inner.waker.register(cx.waker());
                 Poll::Pending
             }
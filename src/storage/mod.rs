@@ -4,6 +4,7 @@
 mod models;
 mod schema;
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -12,12 +13,18 @@ use std::sync::Arc;
 
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use xmpp_parsers::BareJid;
+use xmpp_parsers::oob::Oob;
+use xmpp_parsers::{BareJid, Jid};
 
 use crate::account::Account;
+use crate::message::{
+    DeliveryState, Direction, Message, Reply, Retraction, VersionedXmppMessage, XmppMessageType,
+    XmppMessageVersion,
+};
 
 pub use models::{
     OmemoContactDevice, OmemoIdentity, OmemoOwnDevice, OmemoPreKey, OmemoSenderKey, OmemoSession,
@@ -522,6 +529,489 @@ impl Storage {
             })
             .transpose()?)
     }
+
+    pub fn store_message(
+        &mut self,
+        account: &Account,
+        xmpp_message: &VersionedXmppMessage,
+    ) -> Result<()> {
+        use schema::{
+            message, message_body, message_oob, message_reaction, message_subject, message_version,
+        };
+        let mut conn = self.pool.get()?;
+
+        let peer = match xmpp_message.direction {
+            Direction::Incoming => &xmpp_message.from,
+            Direction::Outgoing => &xmpp_message.to,
+        };
+        let timestamp = xmpp_message.get_original_timestamp().timestamp_millis();
+        let reply = xmpp_message.reply.as_ref();
+        let reply_to = reply
+            .and_then(|reply| reply.to.as_ref())
+            .map(|to| to.to_string());
+
+        conn.transaction::<_, Error, _>(|conn| {
+            diesel::insert_into(message::table)
+                .values((
+                    message::account.eq(account.to_string()),
+                    message::id.eq(xmpp_message.id.as_str()),
+                    message::stanza_id.eq(xmpp_message.stanza_id.as_deref()),
+                    message::origin_id.eq(xmpp_message.origin_id.as_deref()),
+                    message::peer.eq(peer.to_string()),
+                    message::from_jid.eq(xmpp_message.from_full.to_string()),
+                    message::to_jid.eq(xmpp_message.to_full.to_string()),
+                    message::direction.eq(direction_to_str(&xmpp_message.direction)),
+                    message::message_type.eq(message_type_to_str(&xmpp_message.type_)),
+                    message::timestamp.eq(timestamp),
+                    message::reply_id.eq(reply.map(|reply| reply.id.as_str())),
+                    message::reply_to.eq(reply_to.as_deref()),
+                    message::reply_preview.eq(reply.and_then(|reply| reply.preview.as_deref())),
+                ))
+                .on_conflict((message::account, message::peer, message::id))
+                .do_nothing()
+                .execute(conn)?;
+
+            let message_pk: i32 = message::table
+                .filter(message::account.eq(account.to_string()))
+                .filter(message::peer.eq(peer.to_string()))
+                .filter(message::id.eq(xmpp_message.id.as_str()))
+                .select(message::message_pk)
+                .first(conn)?;
+
+            for (lang, subject) in xmpp_message.subjects.iter() {
+                diesel::insert_into(message_subject::table)
+                    .values((
+                        message_subject::message_fk.eq(message_pk),
+                        message_subject::lang.eq(lang.as_str()),
+                        message_subject::subject.eq(subject.as_str()),
+                    ))
+                    .on_conflict((message_subject::message_fk, message_subject::lang))
+                    .do_nothing()
+                    .execute(conn)?;
+            }
+
+            // Each author's reactions are replaced as a whole, removed ones are kept empty
+            for (author, emojis) in xmpp_message.reactions.iter() {
+                diesel::delete(
+                    message_reaction::table
+                        .filter(message_reaction::message_fk.eq(message_pk))
+                        .filter(message_reaction::author.eq(author.as_str())),
+                )
+                .execute(conn)?;
+                for emoji in emojis.iter() {
+                    diesel::insert_into(message_reaction::table)
+                        .values((
+                            message_reaction::message_fk.eq(message_pk),
+                            message_reaction::author.eq(author.as_str()),
+                            message_reaction::emoji.eq(emoji.as_str()),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(conn)?;
+                }
+            }
+
+            if let Some(stanza_id) = &xmpp_message.stanza_id {
+                diesel::update(message::table.find(message_pk))
+                    .set(message::stanza_id.eq(stanza_id.as_str()))
+                    .execute(conn)?;
+            }
+
+            if let Some(delivery) = &xmpp_message.delivery {
+                diesel::update(message::table.find(message_pk))
+                    .set(message::delivery.eq(delivery_to_str(delivery)))
+                    .execute(conn)?;
+            }
+
+            if let Some(retraction) = &xmpp_message.retraction {
+                diesel::update(message::table.find(message_pk))
+                    .set((
+                        message::retracted.eq(true),
+                        message::retracted_by.eq(retraction.by.as_deref()),
+                        message::retraction_reason.eq(retraction.reason.as_deref()),
+                    ))
+                    .execute(conn)?;
+
+                // Retracted content must not be kept around
+                let versions = message_version::table
+                    .filter(message_version::message_fk.eq(message_pk))
+                    .select(message_version::version_pk);
+                diesel::delete(
+                    message_body::table.filter(message_body::version_fk.eq_any(versions.clone())),
+                )
+                .execute(conn)?;
+                diesel::delete(message_oob::table.filter(message_oob::version_fk.eq_any(versions)))
+                    .execute(conn)?;
+            }
+
+            for version in xmpp_message.history.iter() {
+                let version_pk: Option<i32> = diesel::insert_into(message_version::table)
+                    .values((
+                        message_version::message_fk.eq(message_pk),
+                        message_version::id.eq(version.id.as_str()),
+                        message_version::timestamp.eq(version.timestamp.timestamp_millis()),
+                    ))
+                    .on_conflict((message_version::message_fk, message_version::id))
+                    .do_nothing()
+                    .returning(message_version::version_pk)
+                    .get_result(conn)
+                    .optional()?;
+
+                // Versions are immutable, only newly inserted ones need their content stored
+                let Some(version_pk) = version_pk else {
+                    continue;
+                };
+
+                for (lang, body) in version.bodies.iter() {
+                    diesel::insert_into(message_body::table)
+                        .values((
+                            message_body::version_fk.eq(version_pk),
+                            message_body::lang.eq(lang.as_str()),
+                            message_body::body.eq(body.as_str()),
+                        ))
+                        .execute(conn)?;
+                }
+
+                for oob in version.oobs.iter() {
+                    diesel::insert_into(message_oob::table)
+                        .values((
+                            message_oob::version_fk.eq(version_pk),
+                            message_oob::url.eq(oob.url.as_str()),
+                            message_oob::description.eq(oob.desc.as_deref()),
+                        ))
+                        .execute(conn)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Get at most `count` messages exchanged with `peer`, older than `before` if set, in
+    /// chronological order.
+    pub fn get_messages(
+        &self,
+        account: &Account,
+        peer: &BareJid,
+        before: Option<DateTime<FixedOffset>>,
+        count: i64,
+    ) -> Result<Vec<Message>> {
+        use schema::message;
+        let mut conn = self.pool.get()?;
+
+        let mut query = message::table
+            .filter(message::account.eq(account.to_string()))
+            .filter(message::peer.eq(peer.to_string()))
+            .order(message::timestamp.desc())
+            .limit(count)
+            .into_boxed();
+
+        if let Some(before) = before {
+            query = query.filter(message::timestamp.lt(before.timestamp_millis()));
+        }
+
+        let stored: Vec<models::Message> = query.load(&mut conn)?;
+
+        Ok(stored
+            .into_iter()
+            .rev()
+            .filter_map(|stored| {
+                let id = stored.id.clone();
+                load_message(&mut conn, stored)
+                    .map_err(|err| log::warn!("Cannot load stored message {id}: {err}"))
+                    .ok()
+            })
+            .collect())
+    }
+
+    /// Outgoing messages of `account` still waiting to be sent, oldest first
+    pub fn get_pending_messages(&self, account: &Account) -> Result<Vec<Message>> {
+        use schema::message;
+        let mut conn = self.pool.get()?;
+
+        let stored: Vec<models::Message> = message::table
+            .filter(message::account.eq(account.to_string()))
+            .filter(message::direction.eq(direction_to_str(&Direction::Outgoing)))
+            .filter(message::delivery.eq(delivery_to_str(&DeliveryState::Pending)))
+            .order(message::timestamp.asc())
+            .load(&mut conn)?;
+
+        Ok(stored
+            .into_iter()
+            .filter_map(|stored| {
+                let id = stored.id.clone();
+                load_message(&mut conn, stored)
+                    .map_err(|err| log::warn!("Cannot load pending message {id}: {err}"))
+                    .ok()
+            })
+            .collect())
+    }
+
+    /// Full-text search over stored message bodies, most recent matches first
+    pub fn search_messages(
+        &self,
+        account: Option<&Account>,
+        query: &SearchQuery,
+        count: i64,
+    ) -> Result<Vec<(Account, Message)>> {
+        use diesel::sql_types::{BigInt, Text};
+        use schema::message;
+        let mut conn = self.pool.get()?;
+
+        let mut search = diesel::sql_query(
+            "SELECT DISTINCT message.message_pk AS message_pk, message.timestamp AS timestamp \
+             FROM message_body_fts \
+             JOIN message_body ON message_body.body_pk = message_body_fts.rowid \
+             JOIN message_version ON message_version.version_pk = message_body.version_fk \
+             JOIN message ON message.message_pk = message_version.message_fk \
+             WHERE message_body_fts MATCH ?",
+        )
+        .into_boxed()
+        .bind::<Text, _>(fts_query(&query.text));
+
+        if let Some(account) = account {
+            search = search
+                .sql(" AND message.account = ?")
+                .bind::<Text, _>(account.to_string());
+        }
+
+        if let Some(peer) = &query.peer {
+            search = search
+                .sql(" AND message.peer = ?")
+                .bind::<Text, _>(peer.to_string());
+        }
+
+        if let Some(from) = &query.from {
+            // Match either a channel occupant nick or a contact jid
+            let escaped = like_escape(from);
+            search = search
+                .sql(" AND (message.from_jid = ?")
+                .bind::<Text, _>(from.clone())
+                .sql(" OR message.from_jid LIKE ? ESCAPE '\\'")
+                .bind::<Text, _>(format!("%/{escaped}"))
+                .sql(" OR message.from_jid LIKE ? ESCAPE '\\')")
+                .bind::<Text, _>(format!("{escaped}/%"));
+        }
+
+        if let Some(before) = &query.before {
+            search = search
+                .sql(" AND message.timestamp < ?")
+                .bind::<BigInt, _>(before.timestamp_millis());
+        }
+
+        if let Some(after) = &query.after {
+            search = search
+                .sql(" AND message.timestamp > ?")
+                .bind::<BigInt, _>(after.timestamp_millis());
+        }
+
+        let hits: Vec<SearchHit> = search
+            .sql(" ORDER BY message.timestamp DESC LIMIT ?")
+            .bind::<BigInt, _>(count)
+            .load(&mut conn)?;
+
+        let hits: Vec<i32> = hits.into_iter().map(|hit| hit.message_pk).collect();
+        let stored: Vec<models::Message> = message::table
+            .filter(message::message_pk.eq_any(hits))
+            .order(message::timestamp.asc())
+            .load(&mut conn)?;
+
+        Ok(stored
+            .into_iter()
+            .filter_map(|stored| {
+                let id = stored.id.clone();
+                let account = Account::from_str(&stored.account)
+                    .map_err(|err| log::warn!("Invalid account for stored message {id}: {err}"))
+                    .ok()?;
+                let message = load_message(&mut conn, stored)
+                    .map_err(|err| log::warn!("Cannot load stored message {id}: {err}"))
+                    .ok()?;
+                Some((account, message))
+            })
+            .collect())
+    }
+}
+
+/// Filters of a full-text search over stored messages
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub peer: Option<BareJid>,
+    pub from: Option<String>,
+    pub before: Option<DateTime<FixedOffset>>,
+    pub after: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(QueryableByName)]
+struct SearchHit {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    message_pk: i32,
+}
+
+/// Build an FTS5 query matching all words of the given text, each word being quoted so that
+/// punctuation isn't interpreted as FTS5 syntax
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn like_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn load_message(conn: &mut SqliteConnection, stored: models::Message) -> Result<Message> {
+    use schema::{message_body, message_oob, message_reaction, message_subject, message_version};
+
+    let versions: Vec<models::MessageVersion> = message_version::table
+        .filter(message_version::message_fk.eq(stored.message_pk))
+        .load(conn)?;
+
+    let mut history = Vec::new();
+    for version in versions {
+        let bodies = message_body::table
+            .filter(message_body::version_fk.eq(version.version_pk))
+            .load::<models::MessageBody>(conn)?
+            .into_iter()
+            .map(|body| (body.lang, body.body))
+            .collect();
+
+        let oobs = message_oob::table
+            .filter(message_oob::version_fk.eq(version.version_pk))
+            .load::<models::MessageOob>(conn)?
+            .into_iter()
+            .map(|oob| Oob {
+                url: oob.url,
+                desc: oob.description,
+            })
+            .collect();
+
+        history.push(XmppMessageVersion {
+            id: version.id,
+            timestamp: timestamp_from_millis(version.timestamp)?,
+            bodies,
+            oobs,
+        });
+    }
+
+    if history.is_empty() {
+        return Err(anyhow!("Missing message version"));
+    }
+
+    let subjects = message_subject::table
+        .filter(message_subject::message_fk.eq(stored.message_pk))
+        .load::<models::MessageSubject>(conn)?
+        .into_iter()
+        .map(|subject| (subject.lang, subject.subject))
+        .collect();
+
+    let mut reactions: HashMap<String, Vec<String>> = HashMap::new();
+    for reaction in message_reaction::table
+        .filter(message_reaction::message_fk.eq(stored.message_pk))
+        .order(message_reaction::reaction_pk.asc())
+        .load::<models::MessageReaction>(conn)?
+    {
+        reactions
+            .entry(reaction.author)
+            .or_default()
+            .push(reaction.emoji);
+    }
+
+    let from_full = Jid::from_str(&stored.from_jid)?;
+    let to_full = Jid::from_str(&stored.to_jid)?;
+
+    Ok(Message::Xmpp(VersionedXmppMessage {
+        id: stored.id,
+        stanza_id: stored.stanza_id,
+        origin_id: stored.origin_id,
+        from: from_full.to_bare(),
+        from_full,
+        to: to_full.to_bare(),
+        to_full,
+        history,
+        type_: message_type_from_str(&stored.message_type)?,
+        direction: direction_from_str(&stored.direction)?,
+        archive: true,
+        retraction: stored.retracted.then_some(Retraction {
+            by: stored.retracted_by,
+            reason: stored.retraction_reason,
+        }),
+        delivery: stored
+            .delivery
+            .as_deref()
+            .map(delivery_from_str)
+            .transpose()?,
+        receipt_request: false,
+        markable: false,
+        reactions,
+        reply: stored.reply_id.map(|id| Reply {
+            to: stored.reply_to.and_then(|to| Jid::from_str(&to).ok()),
+            id,
+            preview: stored.reply_preview,
+        }),
+        error: None,
+        subjects,
+    }))
+}
+
+fn timestamp_from_millis(millis: i64) -> Result<DateTime<FixedOffset>> {
+    DateTime::from_timestamp_millis(millis)
+        .map(|timestamp| timestamp.into())
+        .ok_or(anyhow!("Invalid timestamp {millis}"))
+}
+
+fn direction_to_str(direction: &Direction) -> &'static str {
+    match direction {
+        Direction::Incoming => "incoming",
+        Direction::Outgoing => "outgoing",
+    }
+}
+
+fn direction_from_str(direction: &str) -> Result<Direction> {
+    match direction {
+        "incoming" => Ok(Direction::Incoming),
+        "outgoing" => Ok(Direction::Outgoing),
+        other => Err(anyhow!("Invalid message direction {other}")),
+    }
+}
+
+fn message_type_to_str(type_: &XmppMessageType) -> &'static str {
+    match type_ {
+        XmppMessageType::Chat => "chat",
+        XmppMessageType::Channel => "channel",
+    }
+}
+
+fn message_type_from_str(type_: &str) -> Result<XmppMessageType> {
+    match type_ {
+        "chat" => Ok(XmppMessageType::Chat),
+        "channel" => Ok(XmppMessageType::Channel),
+        other => Err(anyhow!("Invalid message type {other}")),
+    }
+}
+
+fn delivery_to_str(delivery: &DeliveryState) -> &'static str {
+    match delivery {
+        DeliveryState::Pending => "pending",
+        DeliveryState::Sent => "sent",
+        DeliveryState::Acked => "acked",
+        DeliveryState::Delivered => "delivered",
+        DeliveryState::Displayed => "displayed",
+    }
+}
+
+fn delivery_from_str(delivery: &str) -> Result<DeliveryState> {
+    match delivery {
+        "pending" => Ok(DeliveryState::Pending),
+        "sent" => Ok(DeliveryState::Sent),
+        "acked" => Ok(DeliveryState::Acked),
+        "delivered" => Ok(DeliveryState::Delivered),
+        "displayed" => Ok(DeliveryState::Displayed),
+        other => Err(anyhow!("Invalid message delivery state {other}")),
+    }
 }
 
 fn signal_storage_error<T>(
@@ -753,3 +1243,221 @@ impl libsignal_protocol::SenderKeyStore for SignalStorage {
             .map_err(signal_storage_display_error())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use chrono::{DateTime, FixedOffset};
+    use tempfile::TempDir;
+    use xmpp_parsers::{BareJid, Jid};
+
+    use super::Storage;
+    use crate::account::Account;
+    use crate::message::{DeliveryState, Message, Reply, VersionedXmppMessage};
+
+    /// Storage in its own temporary directory, removed when the returned guard is dropped
+    fn storage() -> (TempDir, Storage) {
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().join("aparte.sqlite")).unwrap();
+        (dir, storage)
+    }
+
+    fn account() -> Account {
+        Account::from_str("me@example.org/aparte").unwrap()
+    }
+
+    fn timestamp(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    fn incoming(id: &str, from: &str, body: &str) -> VersionedXmppMessage {
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), body.to_string());
+        let message = Message::incoming_chat(
+            id,
+            timestamp("2024-06-01T10:00:00+00:00"),
+            &Jid::from_str(from).unwrap(),
+            &Jid::from_str("me@example.org/aparte").unwrap(),
+            bodies,
+            None,
+            false,
+        );
+        match message {
+            Message::Xmpp(message) => message,
+            _ => unreachable!(),
+        }
+    }
+
+    fn pending(id: &str, time: &str) -> VersionedXmppMessage {
+        let mut bodies = HashMap::new();
+        bodies.insert("".to_string(), id.to_string());
+        let message = Message::outgoing_chat(
+            id,
+            timestamp(time),
+            &Jid::from_str("me@example.org/aparte").unwrap(),
+            &Jid::from_str("juliet@example.org").unwrap(),
+            bodies,
+            None,
+            false,
+        );
+        match message {
+            Message::Xmpp(mut message) => {
+                message.delivery = Some(DeliveryState::Pending);
+                message
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn stored(messages: Vec<Message>) -> Vec<VersionedXmppMessage> {
+        messages
+            .into_iter()
+            .map(|message| match message {
+                Message::Xmpp(message) => message,
+                _ => panic!("Unexpected message kind"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_store_message_round_trip() {
+        // Given
+        let (_dir, mut storage) = storage();
+        let account = account();
+        let mut message = incoming("id1", "juliet@example.org/balcony", "Hello");
+        message.stanza_id = Some("stanza1".to_string());
+        message.origin_id = Some("origin1".to_string());
+        message.reply = Some(Reply {
+            to: Some(Jid::from_str("romeo@example.org/garden").unwrap()),
+            id: "id0".to_string(),
+            preview: Some("romeo@example.org: Hi".to_string()),
+        });
+
+        // When
+        storage.store_message(&account, &message).unwrap();
+        let loaded = storage
+            .get_messages(
+                &account,
+                &BareJid::from_str("juliet@example.org").unwrap(),
+                None,
+                10,
+            )
+            .unwrap();
+
+        // Then
+        let loaded = stored(loaded);
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, "id1");
+        assert_eq!(loaded[0].stanza_id.as_deref(), Some("stanza1"));
+        assert_eq!(loaded[0].origin_id.as_deref(), Some("origin1"));
+        assert_eq!(loaded[0].reply, message.reply);
+        assert_eq!(loaded[0].from_full, message.from_full);
+        assert_eq!(loaded[0].to_full, message.to_full);
+        assert_eq!(loaded[0].direction, message.direction);
+        assert_eq!(
+            loaded[0].get_original_timestamp(),
+            message.get_original_timestamp()
+        );
+        assert_eq!(loaded[0].get_last_body(vec![]), "Hello");
+    }
+
+    #[test]
+    fn test_store_message_same_id_other_peer() {
+        // Given
+        let (_dir, mut storage) = storage();
+        let account = account();
+        let juliet = incoming("1", "juliet@example.org/balcony", "From Juliet");
+        let romeo = incoming("1", "romeo@example.org/garden", "From Romeo");
+
+        // When
+        storage.store_message(&account, &juliet).unwrap();
+        storage.store_message(&account, &romeo).unwrap();
+
+        // Then
+        let loaded = stored(
+            storage
+                .get_messages(
+                    &account,
+                    &BareJid::from_str("romeo@example.org").unwrap(),
+                    None,
+                    10,
+                )
+                .unwrap(),
+        );
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].get_last_body(vec![]), "From Romeo");
+    }
+
+    #[test]
+    fn test_store_reactions() {
+        // Given
+        let (_dir, mut storage) = storage();
+        let account = account();
+        let juliet = BareJid::from_str("juliet@example.org").unwrap();
+        let mut message = incoming("id1", "juliet@example.org/balcony", "Hello");
+        message
+            .reactions
+            .insert("romeo@example.org".to_string(), vec!["👍".to_string()]);
+        storage.store_message(&account, &message).unwrap();
+
+        // When
+        message
+            .reactions
+            .insert("romeo@example.org".to_string(), vec![]);
+        message.reactions.insert(
+            "juliet@example.org".to_string(),
+            vec!["🐢".to_string(), "❤".to_string()],
+        );
+        storage.store_message(&account, &message).unwrap();
+
+        // Then
+        let loaded = stored(storage.get_messages(&account, &juliet, None, 10).unwrap());
+        assert_eq!(loaded[0].reactions.len(), 1);
+        assert_eq!(
+            loaded[0].reactions.get("juliet@example.org"),
+            Some(&vec!["🐢".to_string(), "❤".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_get_pending_messages_order() {
+        // Given
+        let (_dir, mut storage) = storage();
+        let account = account();
+        let second = pending("second", "2024-06-01T10:05:00+00:00");
+        let first = pending("first", "2024-06-01T10:00:00+00:00");
+        let third = pending("third", "2024-06-01T10:10:00+00:00");
+        for message in [&second, &first, &third] {
+            storage.store_message(&account, message).unwrap();
+        }
+
+        // When
+        let pending = stored(storage.get_pending_messages(&account).unwrap());
+
+        // Then
+        let ids: Vec<&str> = pending.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_pending_message_sent() {
+        // Given
+        let (_dir, mut storage) = storage();
+        let account = account();
+        let mut message = pending("id1", "2024-06-01T10:00:00+00:00");
+        storage.store_message(&account, &message).unwrap();
+
+        // When
+        message.delivery = Some(DeliveryState::Sent);
+        storage.store_message(&account, &message).unwrap();
+
+        // Then
+        assert!(storage.get_pending_messages(&account).unwrap().is_empty());
+        let juliet = BareJid::from_str("juliet@example.org").unwrap();
+        let loaded = stored(storage.get_messages(&account, &juliet, None, 10).unwrap());
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].delivery, Some(DeliveryState::Sent));
+    }
+}
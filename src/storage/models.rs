@@ -73,3 +73,65 @@ pub struct OmemoSenderKey {
     pub distribution_id: Vec<u8>,
     pub sender_key: Vec<u8>,
 }
+
+#[derive(Queryable, Debug)]
+pub struct Message {
+    pub message_pk: i32,
+    pub account: String,
+    pub id: String,
+    pub stanza_id: Option<String>,
+    pub origin_id: Option<String>,
+    pub peer: String,
+    pub from_jid: String,
+    pub to_jid: String,
+    pub direction: String,
+    pub message_type: String,
+    pub timestamp: i64,
+    pub retracted: bool,
+    pub retracted_by: Option<String>,
+    pub retraction_reason: Option<String>,
+    pub delivery: Option<String>,
+    pub reply_id: Option<String>,
+    pub reply_to: Option<String>,
+    pub reply_preview: Option<String>,
+}
+
+#[derive(Queryable, Debug)]
+pub struct MessageVersion {
+    pub version_pk: i32,
+    pub message_fk: i32,
+    pub id: String,
+    pub timestamp: i64,
+}
+
+#[derive(Queryable, Debug)]
+pub struct MessageBody {
+    pub body_pk: i32,
+    pub version_fk: i32,
+    pub lang: String,
+    pub body: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct MessageReaction {
+    pub reaction_pk: i32,
+    pub message_fk: i32,
+    pub author: String,
+    pub emoji: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct MessageSubject {
+    pub subject_pk: i32,
+    pub message_fk: i32,
+    pub lang: String,
+    pub subject: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct MessageOob {
+    pub oob_pk: i32,
+    pub version_fk: i32,
+    pub url: String,
+    pub description: Option<String>,
+}
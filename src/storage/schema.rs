@@ -1,5 +1,73 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    message (message_pk) {
+        message_pk -> Integer,
+        account -> Text,
+        id -> Text,
+        stanza_id -> Nullable<Text>,
+        origin_id -> Nullable<Text>,
+        peer -> Text,
+        from_jid -> Text,
+        to_jid -> Text,
+        direction -> Text,
+        message_type -> Text,
+        timestamp -> BigInt,
+        retracted -> Bool,
+        retracted_by -> Nullable<Text>,
+        retraction_reason -> Nullable<Text>,
+        delivery -> Nullable<Text>,
+        reply_id -> Nullable<Text>,
+        reply_to -> Nullable<Text>,
+        reply_preview -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_body (body_pk) {
+        body_pk -> Integer,
+        version_fk -> Integer,
+        lang -> Text,
+        body -> Text,
+    }
+}
+
+diesel::table! {
+    message_oob (oob_pk) {
+        oob_pk -> Integer,
+        version_fk -> Integer,
+        url -> Text,
+        description -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_reaction (reaction_pk) {
+        reaction_pk -> Integer,
+        message_fk -> Integer,
+        author -> Text,
+        emoji -> Text,
+    }
+}
+
+diesel::table! {
+    message_subject (subject_pk) {
+        subject_pk -> Integer,
+        message_fk -> Integer,
+        lang -> Text,
+        subject -> Text,
+    }
+}
+
+diesel::table! {
+    message_version (version_pk) {
+        version_pk -> Integer,
+        message_fk -> Integer,
+        id -> Text,
+        timestamp -> BigInt,
+    }
+}
+
 diesel::table! {
     omemo_contact_device (contact_device_pk) {
         contact_device_pk -> Integer,
@@ -67,7 +135,19 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(message_body -> message_version (version_fk));
+diesel::joinable!(message_oob -> message_version (version_fk));
+diesel::joinable!(message_reaction -> message (message_fk));
+diesel::joinable!(message_subject -> message (message_fk));
+diesel::joinable!(message_version -> message (message_fk));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    message,
+    message_body,
+    message_oob,
+    message_reaction,
+    message_subject,
+    message_version,
     omemo_contact_device,
     omemo_identity,
     omemo_own_device,
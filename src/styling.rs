@@ -0,0 +1,137 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+//! XEP-0393: Message Styling
+//!
+//! Styling directives are kept in the output, only the text they apply to is decorated with
+//! terminal escape sequences.
+use termion::{color, style};
+
+const CODE_BLOCK: &str = "```";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Span {
+    Strong,
+    Emphasis,
+    Strike,
+    Preformatted,
+}
+
+impl Span {
+    fn from_directive(c: char) -> Option<Self> {
+        match c {
+            '*' => Some(Self::Strong),
+            '_' => Some(Self::Emphasis),
+            '~' => Some(Self::Strike),
+            '`' => Some(Self::Preformatted),
+            _ => None,
+        }
+    }
+
+    fn start(&self) -> String {
+        match self {
+            Self::Strong => style::Bold.to_string(),
+            Self::Emphasis => style::Italic.to_string(),
+            Self::Strike => style::CrossedOut.to_string(),
+            Self::Preformatted => color::Fg(color::Yellow).to_string(),
+        }
+    }
+
+    fn end(&self) -> String {
+        match self {
+            Self::Strong => style::NoBold.to_string(),
+            Self::Emphasis => style::NoItalic.to_string(),
+            Self::Strike => style::NoCrossedOut.to_string(),
+            Self::Preformatted => color::Fg(color::Reset).to_string(),
+        }
+    }
+}
+
+/// Index of the directive closing the span opened at `start`, if any
+fn find_closing(chars: &[char], start: usize) -> Option<usize> {
+    // Spans can't be empty and their content can't start with a whitespace
+    if chars.get(start + 1).map_or(true, |c| c.is_whitespace()) {
+        return None;
+    }
+
+    (start + 2..chars.len()).find(|&i| chars[i] == chars[start] && !chars[i - 1].is_whitespace())
+}
+
+fn format_spans(chars: &[char]) -> String {
+    let mut output = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        // Opening directives must be at the start of the text or follow a whitespace
+        let opening = i == 0 || chars[i - 1].is_whitespace();
+        if let (true, Some(span)) = (opening, Span::from_directive(c)) {
+            if let Some(end) = find_closing(chars, i) {
+                output.push_str(&span.start());
+                output.push(c);
+                match span {
+                    Span::Preformatted => output.extend(&chars[i + 1..end]),
+                    _ => output.push_str(&format_spans(&chars[i + 1..end])),
+                }
+                output.push(c);
+                output.push_str(&span.end());
+                i = end + 1;
+                continue;
+            }
+        }
+        output.push(c);
+        i += 1;
+    }
+
+    output
+}
+
+/// Format a line outside of preformatted blocks
+fn format_line(line: &str) -> String {
+    // Quote markers are dimmed, the quoted text is styled as any other text
+    let quote_len = line
+        .char_indices()
+        .take_while(|(_, c)| *c == '>' || c.is_whitespace())
+        .last()
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    if line.starts_with('>') {
+        let (quote, text) = line.split_at(quote_len);
+        format!(
+            "{}{}{}{}",
+            color::Fg(color::LightBlack),
+            quote,
+            color::Fg(color::Reset),
+            format_spans(&text.chars().collect::<Vec<_>>())
+        )
+    } else {
+        format_spans(&line.chars().collect::<Vec<_>>())
+    }
+}
+
+/// Apply styling to each line of a message body. Lines must not contain any escape sequence.
+pub fn format(body: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut preformatted = false;
+
+    for line in body.lines() {
+        let line = match (preformatted, line.starts_with(CODE_BLOCK)) {
+            (false, true) => {
+                preformatted = true;
+                format!("{}{}", color::Fg(color::Yellow), line)
+            }
+            (true, _) if line == CODE_BLOCK => {
+                preformatted = false;
+                format!("{}{}", line, color::Fg(color::Reset))
+            }
+            (true, _) => line.to_string(),
+            (false, false) => format_line(line),
+        };
+        lines.push(line);
+    }
+
+    // Blocks left open are closed by the end of the message
+    if let (true, Some(last)) = (preformatted, lines.last_mut()) {
+        last.push_str(&color::Fg(color::Reset).to_string());
+    }
+
+    lines
+}
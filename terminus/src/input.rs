@@ -30,6 +30,10 @@ pub struct Input<E> {
     //     | view      |
     //     |-----------|
     pub view: Cursor,
+    // first line of the buffer displayed when it spans more lines than the view height
+    top: Cell<usize>,
+    // maximum number of lines displayed at once
+    max_height: usize,
     pub event_handler: Option<EventHandler<Self, E>>,
     pub dirty: Cell<bool>,
     width: Cell<usize>,
@@ -52,6 +56,8 @@ impl<E> Input<E> {
             history_index: 0,
             cursor: Cursor::new(0),
             view: Cursor::new(0),
+            top: Cell::new(0),
+            max_height: 1,
             event_handler: None,
             dirty: Cell::new(true),
             width: Cell::new(0),
@@ -67,6 +73,44 @@ impl<E> Input<E> {
         self
     }
 
+    /// Let the input grow up to `max_height` lines when the buffer contains line breaks
+    pub fn with_max_height(mut self, max_height: usize) -> Self {
+        self.max_height = max_height;
+        self
+    }
+
+    /// Length (in graphemes) of each line of the buffer
+    fn lines_len(&self) -> Vec<usize> {
+        self.buf
+            .split('\n')
+            .map(|line| line.graphemes(true).count())
+            .collect()
+    }
+
+    /// Line and column of the cursor
+    fn position(&self) -> (usize, usize) {
+        let lines_len = self.lines_len();
+        let mut column = self.cursor.get();
+        for (line, len) in lines_len.iter().enumerate() {
+            if column <= *len {
+                return (line, column);
+            }
+            column -= len + 1;
+        }
+        // Cursor past the end of the buffer
+        (lines_len.len() - 1, lines_len[lines_len.len() - 1])
+    }
+
+    /// Move the cursor to the given line, keeping its column when possible
+    fn goto_line(&mut self, line: usize, column: usize) {
+        let lines_len = self.lines_len();
+        let start: usize = lines_len[..line].iter().map(|len| len + 1).sum();
+        self.cursor.set(start + column.min(lines_len[line]));
+        if !self.password {
+            self.dirty.set(true);
+        }
+    }
+
     pub fn key(&mut self, c: char) {
         let byte_index = self.cursor.index(&self.buf);
         self.buf.insert(byte_index, c);
@@ -77,6 +121,17 @@ impl<E> Input<E> {
         }
     }
 
+    /// Insert text at cursor, line breaks included
+    pub fn paste(&mut self, text: &str) {
+        let byte_index = self.cursor.index(&self.buf);
+        self.buf.insert_str(byte_index, text);
+        self.cursor += text.graphemes(true).count();
+
+        if !self.password {
+            self.dirty.set(true);
+        }
+    }
+
     pub fn backspace(&mut self) {
         if self.cursor > Cursor::new(0) {
             self.cursor -= 1;
@@ -109,17 +164,29 @@ impl<E> Input<E> {
         }
     }
 
+    /// Delete from the start of the current line to the cursor
     pub fn delete_from_cursor_to_start(&mut self) {
-        self.buf.replace_range(0..self.cursor.index(&self.buf), "");
-        self.cursor.set(0);
+        let (_, column) = self.position();
+        let mut line_start = self.cursor.clone();
+        line_start -= column;
+        self.buf.replace_range(
+            line_start.index(&self.buf)..self.cursor.index(&self.buf),
+            "",
+        );
+        self.cursor = line_start;
         self.view.set(0);
         if !self.password {
             self.dirty.set(true);
         }
     }
 
+    /// Delete from the cursor to the end of the current line
     pub fn delete_from_cursor_to_end(&mut self) {
-        self.buf.replace_range(self.cursor.index(&self.buf).., "");
+        let (line, column) = self.position();
+        let mut line_end = self.cursor.clone();
+        line_end += self.lines_len()[line] - column;
+        self.buf
+            .replace_range(self.cursor.index(&self.buf)..line_end.index(&self.buf), "");
         if !self.password {
             self.dirty.set(true);
         }
@@ -139,18 +206,25 @@ impl<E> Input<E> {
         }
     }
 
+    /// Move the cursor to the start of the current line
     pub fn home(&mut self) {
-        self.cursor.set(0);
+        let (line, _) = self.position();
+        self.goto_line(line, 0);
         self.view.set(0);
         if !self.password {
             self.dirty.set(true);
         }
     }
 
+    /// Move the cursor to the end of the current line
     pub fn end(&mut self) {
-        self.cursor.set(self.buf.graphemes(true).count());
-        if self.cursor > self.width.get() - 1 {
-            self.view = &self.cursor - (self.width.get() - 1);
+        let (line, _) = self.position();
+        self.goto_line(line, usize::MAX);
+        let (_, column) = self.position();
+        // The width is unknown until the input is laid out
+        let width = self.width.get().saturating_sub(1);
+        if column > width {
+            self.view.set(column - width);
         } else {
             self.view.set(0);
         }
@@ -163,6 +237,7 @@ impl<E> Input<E> {
         self.buf.clear();
         self.cursor.set(0);
         self.view.set(0);
+        self.top.set(0);
         let _ = self.tmp_buf.take();
         self.password = false;
         self.dirty.set(true);
@@ -186,6 +261,34 @@ impl<E> Input<E> {
         }
     }
 
+    pub fn newline(&mut self) {
+        if !self.password {
+            self.key('\n');
+        }
+    }
+
+    /// Move the cursor to the previous line, return false if already on the first one
+    pub fn up(&mut self) -> bool {
+        match self.position() {
+            (0, _) => false,
+            (line, column) => {
+                self.goto_line(line - 1, column);
+                true
+            }
+        }
+    }
+
+    /// Move the cursor to the next line, return false if already on the last one
+    pub fn down(&mut self) -> bool {
+        let (line, column) = self.position();
+        if line + 1 < self.lines_len().len() {
+            self.goto_line(line + 1, column);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn word_left(&mut self) {
         let iter = self.buf[..self.cursor.index(&self.buf)].chars().rev();
         self.cursor -= next_word(iter);
@@ -256,9 +359,17 @@ where
     W: Write + AsFd,
 {
     fn measure(&self, _measure_specs: &MeasureSpecs) -> RequestedDimensions {
+        let height = match self.password {
+            true => 1,
+            false => self
+                .buf
+                .split('\n')
+                .count()
+                .clamp(1, self.max_height.max(1)),
+        };
         RequestedDimensions {
             width: RequestedDimension::ExpandMax,
-            height: RequestedDimension::Absolute(1),
+            height: RequestedDimension::Absolute(height as u16),
         }
     }
 
@@ -289,43 +400,54 @@ where
                 false => {
                     // Max displayable size is view width less 1 for cursor
                     let max_size = (dimensions.width - 1) as usize;
+                    let height = dimensions.height as usize;
+                    let (line, column) = self.position();
+                    let column = Cursor::new(column);
 
                     // cursor must always be inside the view
-                    if self.cursor < self.view {
-                        if self.cursor < max_size {
+                    if column < self.view {
+                        if column < max_size {
                             self.view.set(0);
                         } else {
-                            self.view
-                                .update(&self.cursor - (dimensions.width as usize - 1));
+                            self.view.update(&column - (dimensions.width as usize - 1));
                         }
-                    } else if self.cursor > &self.view + (dimensions.width as usize - 1) {
-                        self.view
-                            .update(&self.cursor - (dimensions.width as usize - 1));
+                    } else if column > &self.view + (dimensions.width as usize - 1) {
+                        self.view.update(&column - (dimensions.width as usize - 1));
                     }
-                    assert!(self.cursor >= self.view);
-                    assert!(self.cursor <= &self.view + (max_size + 1));
-
-                    let start_index = self.view.index(&self.buf);
-                    let end_index = (&self.view + max_size).index(&self.buf);
-                    let buf = &self.buf[start_index..end_index];
-                    let cursor = &self.cursor - &self.view;
-
-                    if dimensions.left == 1 {
-                        // Use fast erase if possible
-                        goto!(screen, dimensions.left + dimensions.width, dimensions.top);
-                        vprint!(screen, "{}", "\x1B[1K");
-                        goto!(screen, dimensions.left, dimensions.top);
-                        vprint!(screen, "{}", buf);
-                    } else {
-                        goto!(screen, dimensions.left, dimensions.top);
-                        let padding = dimensions.width - term_string_visible_len(buf) as u16;
-                        vprint!(screen, "{}{: <1$}", buf, padding as usize);
+                    assert!(column >= self.view);
+                    assert!(column <= &self.view + (max_size + 1));
+
+                    if line < self.top.get() {
+                        self.top.set(line);
+                    } else if line >= self.top.get() + height {
+                        self.top.set(line + 1 - height);
                     }
 
+                    let lines = self.buf.split('\n').skip(self.top.get()).take(height);
+                    for (row, buf) in lines.enumerate() {
+                        let top = dimensions.top + row as u16;
+                        let start_index = self.view.index(buf);
+                        let end_index = (&self.view + max_size).index(buf);
+                        let buf = &buf[start_index..end_index];
+
+                        if dimensions.left == 1 {
+                            // Use fast erase if possible
+                            goto!(screen, dimensions.left + dimensions.width, top);
+                            vprint!(screen, "{}", "\x1B[1K");
+                            goto!(screen, dimensions.left, top);
+                            vprint!(screen, "{}", buf);
+                        } else {
+                            goto!(screen, dimensions.left, top);
+                            let padding = dimensions.width - term_string_visible_len(buf) as u16;
+                            vprint!(screen, "{}{: <1$}", buf, padding as usize);
+                        }
+                    }
+
+                    let cursor = &column - &self.view;
                     goto!(
                         screen,
                         dimensions.left + cursor.get() as u16,
-                        dimensions.top
+                        dimensions.top + (line - self.top.get()) as u16
                     );
                     save_cursor!(screen);
                 }
@@ -368,4 +490,81 @@ mod tests {
         // Then
         assert_eq!(input.buf, "ab".to_string());
     }
+
+    #[test]
+    fn test_input_paste() {
+        // Given
+        let mut input = Input::<()>::new();
+        input.key('a');
+        input.key('d');
+        input.left();
+
+        // When
+        input.paste("b\nc");
+
+        // Then
+        assert_eq!(input.buf, "ab\ncd".to_string());
+        assert_eq!(input.position(), (1, 1));
+    }
+
+    #[test]
+    fn test_input_multiline_cursor_movement() {
+        // Given
+        let mut input = Input::<()>::new().with_max_height(5);
+        for c in "first".chars() {
+            input.key(c);
+        }
+        input.newline();
+        for c in "ab".chars() {
+            input.key(c);
+        }
+
+        // When
+        let up = input.up();
+        let position_up = input.position();
+        let top = input.up();
+        let down = input.down();
+        let position_down = input.position();
+        let bottom = input.down();
+
+        // Then
+        assert_eq!(input.buf, "first\nab".to_string());
+        assert!(up);
+        assert_eq!(position_up, (0, 2));
+        assert!(!top);
+        assert!(down);
+        assert_eq!(position_down, (1, 2));
+        assert!(!bottom);
+        assert_eq!(
+            View::<(), std::fs::File>::measure(&input, &MeasureSpecs::default()).height,
+            RequestedDimension::Absolute(2)
+        );
+    }
+
+    #[test]
+    fn test_input_multiline_line_editing() {
+        // Given
+        let mut input = Input::<()>::new().with_max_height(5);
+        input.paste("first\nsecond\nthird");
+        input.up();
+        input.left();
+
+        // When
+        input.home();
+        let home = input.position();
+        input.end();
+        let end = input.position();
+        input.left();
+        input.left();
+        input.delete_from_cursor_to_end();
+        let killed = input.buf.clone();
+        input.delete_from_cursor_to_start();
+
+        // Then
+        assert_eq!(home, (1, 0));
+        assert_eq!(end, (1, 6));
+        assert_eq!(killed, "first\nseco\nthird".to_string());
+        assert_eq!(input.buf, "first\n\nthird".to_string());
+        assert_eq!(input.position(), (1, 0));
+    }
 }
@@ -36,6 +36,11 @@ impl<W: Write> BufferedScreen<W> {
             buffer: Vec::with_capacity(100 * 500 * 10),
         }
     }
+
+    /// Underlying terminal, writes to it bypass the buffer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
 }
 
 impl<W: Write> Write for BufferedScreen<W> {
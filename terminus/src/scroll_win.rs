@@ -85,6 +85,38 @@ where
         }
     }
 
+    /// Replace an already inserted child by an updated version of it, insert it otherwise
+    pub fn replace(&mut self, mut item: I) {
+        if self.children.contains(&item) {
+            item.set_dirty();
+            self.children.replace(item);
+        } else {
+            self.insert(item);
+        }
+    }
+
+    pub fn get(&self, item: &I) -> Option<&I> {
+        self.children.get(item)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'_ I> {
+        self.children.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.children.clear();
+        self.bottom_visible_child_index = 0;
+        self.dirty.set(true);
+    }
+
+    /// Scroll the window so that the given child is visible at the bottom of it
+    pub fn scroll_to(&mut self, item: &I) {
+        if self.children.contains(item) {
+            self.bottom_visible_child_index = self.children.range(..item).count();
+            self.dirty.set(true);
+        }
+    }
+
     /// PageUp the window, return true if top is reached
     pub fn page_up(&mut self) -> bool {
         log::debug!("Page up");
@@ -549,6 +581,78 @@ mod tests {
         assert_eq!(visible_children, vec![&third_view, &second_view]);
     }
 
+    #[test]
+    fn test_visible_children_scroll_to() {
+        // Given
+        let mut scroll_win = ScrollWin::<(), File, MockView>::new();
+
+        let first_view = MockView {
+            ord: 0,
+            height: 10,
+            ..Default::default()
+        };
+        let second_view = MockView {
+            ord: 1,
+            height: 10,
+            ..Default::default()
+        };
+        let third_view = MockView {
+            ord: 2,
+            height: 10,
+            ..Default::default()
+        };
+
+        scroll_win.insert(first_view.clone());
+        scroll_win.insert(second_view.clone());
+        scroll_win.insert(third_view.clone());
+        scroll_win.layout(&Dimensions {
+            width: 100,
+            height: 10,
+            top: 1,
+            left: 1,
+        });
+
+        // When
+        scroll_win.scroll_to(&first_view);
+
+        // Then
+        let visible_children = scroll_win.visible_children().collect::<Vec<_>>();
+        assert_eq!(visible_children, vec![&first_view]);
+    }
+
+    #[test]
+    fn test_replace_child() {
+        // Given
+        let mut scroll_win = ScrollWin::<(), File, MockView>::new();
+
+        let first_view = MockView {
+            ord: 0,
+            height: 10,
+            ..Default::default()
+        };
+        let second_view = MockView {
+            ord: 1,
+            height: 10,
+            ..Default::default()
+        };
+
+        scroll_win.insert(first_view.clone());
+        scroll_win.insert(second_view.clone());
+
+        // When
+        scroll_win.replace(MockView {
+            ord: 0,
+            height: 5,
+            ..Default::default()
+        });
+
+        // Then
+        let replaced = scroll_win.get(&first_view).unwrap();
+        assert_eq!(replaced.height, 5);
+        assert!(replaced.dirty);
+        assert_eq!(scroll_win.iter().count(), 2);
+    }
+
     #[test]
     fn test_visible_children_page_down() {
         // Given